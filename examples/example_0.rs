@@ -1,6 +1,6 @@
 extern crate adamant;
 
-use adamant::{Context, ContextFlags, ContextParams, GameTimer};
+use adamant::{Context, ContextParams, GameTimer};
 
 use winit::{
     dpi::LogicalSize,
@@ -10,12 +10,11 @@ use winit::{
 };
 
 fn main() {
-    let params = ContextParams::new(
-        "Tutorial 01".to_string(),
-        1280,
-        720,
-        ContextFlags::ALLOW_TEARING,
-    );
+    let params = ContextParams::builder("Tutorial 01")
+        .window_size(1280, 720)
+        .allow_tearing(true)
+        .build()
+        .expect("Invalid context parameters.");
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
@@ -28,14 +27,25 @@ fn main() {
         .build(&event_loop)
         .unwrap();
 
-    let mut context = Context::new(&window, &params);
-
-    let mut frame_count = 0;
-    let mut elapsed_time: f64 = 0.0;
+    let mut context = match Context::new(&window, &params) {
+        Ok(context) => context,
+        Err(error) => {
+            eprintln!(
+                "Failed to initialize D3D12, is a compatible GPU available? ({:?})",
+                error
+            );
+            return;
+        }
+    };
+    // Present uncapped, tearing is used where supported.
+    context.set_present_interval(0);
 
     let mut timer = GameTimer::new();
     timer.reset();
 
+    #[cfg(debug_assertions)]
+    let mut shown_fps = 0;
+
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::EventsCleared => {
@@ -43,17 +53,14 @@ fn main() {
                 timer.tick();
                 #[cfg(debug_assertions)]
                 {
-                    // Calculate FPS
-                    frame_count += 1;
-                    if timer.total_time() - elapsed_time >= 1.0 {
-                        let fps = frame_count;
+                    let fps = timer.frames_per_second();
+                    if fps != shown_fps {
                         let frame_time = 1000.0 / fps as f32;
                         window.set_title(&format!(
                             "{} [FPS {} - {:.2}ms]",
                             params.window_title, fps, frame_time
                         ));
-                        frame_count = 0;
-                        elapsed_time += 1.0;
+                        shown_fps = fps;
                     }
                 }
 
@@ -65,14 +72,43 @@ fn main() {
                 ..
             } => {
                 // Redraw the application.
-                context.prepare();
+                if let Err(error) = context.prepare() {
+                    eprintln!("Failed to prepare the frame, exiting. ({:?})", error);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
                 context.clear();
-                context.present();
+                if let Err(error) = context.present() {
+                    eprintln!("Failed to present, exiting. ({:?})", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+                // Does nothing unless a target frame rate is set with set_target_fps().
+                timer.wait_for_next_frame();
             }
             Event::WindowEvent {
-                event: WindowEvent::Resized(LogicalSize { width, height }),
+                event: WindowEvent::Resized(size),
                 ..
-            } => context.on_window_resized(width as _, height as _),
+            } => {
+                // Back buffers are sized in pixels, winit reports logical sizes.
+                let size = size.to_physical(window.hidpi_factor());
+                if let Err(error) = context.on_window_resized(size.width as _, size.height as _) {
+                    eprintln!("Failed to resize, exiting. ({:?})", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::HiDpiFactorChanged(hidpi_factor),
+                ..
+            } => {
+                let size = window.inner_size().to_physical(hidpi_factor);
+                if let Err(error) = context.on_window_resized(size.width as _, size.height as _) {
+                    eprintln!("Failed to resize, exiting. ({:?})", error);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            // Time spent suspended doesn't count towards the game's total time.
+            Event::Suspended => timer.stop(),
+            Event::Resumed => timer.start(),
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 window_id,
@@ -0,0 +1,78 @@
+extern crate adamant;
+
+use adamant::{CommandAllocator, Context, ContextFlags, ContextParams, GraphicsCommandList};
+
+use std::thread;
+
+const WORKER_COUNT: usize = 4;
+const FRAME_COUNT: usize = 3;
+
+fn main() {
+    let params = ContextParams::new(
+        "Parallel recording".to_string(),
+        1280,
+        720,
+        ContextFlags::empty(),
+    );
+
+    let mut context = match Context::new_headless(&params) {
+        Ok(context) => context,
+        Err(error) => {
+            eprintln!(
+                "Failed to initialize D3D12, is a compatible GPU available? ({:?})",
+                error
+            );
+            return;
+        }
+    };
+
+    for frame in 0..FRAME_COUNT {
+        context.prepare().expect("Failed to prepare the frame.");
+        context.clear();
+
+        // Each worker is given its own command list and allocator, records into them and hands
+        // them back once closed.
+        let workers: Vec<_> = (0..WORKER_COUNT)
+            .map(|worker| {
+                let (command_list, command_allocator) =
+                    context.command_queue().create_command_list();
+                thread::spawn(move || {
+                    command_list.begin_event(&format!("Frame {} - Worker {}", frame, worker));
+                    command_list.set_marker("Recorded on a worker thread");
+                    command_list.end_event();
+                    command_list.close().unwrap();
+                    (command_list, command_allocator)
+                })
+            })
+            .collect();
+        let recorded: Vec<(GraphicsCommandList, CommandAllocator)> = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect();
+
+        // Submission happens on the thread owning the queue, in a single call that keeps the
+        // order of the workers.
+        let command_lists: Vec<_> = recorded
+            .iter()
+            .map(|(command_list, _)| command_list.as_command_list())
+            .collect();
+        context
+            .command_queue()
+            .execute_command_lists(&command_lists);
+        context.present().expect("Failed to present.");
+
+        // The frame's signal covers the worker lists too, the allocators can be reused once the
+        // GPU has reached it.
+        let fence_value = context.command_queue().last_signaled_value();
+        for (_, command_allocator) in recorded {
+            context
+                .command_queue()
+                .free_allocator(fence_value, command_allocator);
+        }
+    }
+
+    println!(
+        "Recorded {} frames on {} worker threads.",
+        FRAME_COUNT, WORKER_COUNT
+    );
+}
@@ -1,12 +1,17 @@
 use crate::com::ComPtr;
+use crate::command::{CommandContext, CommandContextPool, CommandQueue};
+use crate::descriptor::CpuDescriptor;
 use crate::device::Device;
 use crate::resource::GpuResource;
 
-use winapi::shared::{dxgiformat, dxgitype, winerror::SUCCEEDED};
+use winapi::shared::{
+    dxgiformat, dxgitype,
+    winerror::{FAILED, SUCCEEDED},
+};
 use winapi::um::d3d12;
 use winapi::Interface;
 
-use std::ptr;
+use std::{mem, ptr, slice};
 
 pub struct BufferCopyRegion {
     pub source_offset: u64,
@@ -17,21 +22,143 @@ pub struct BufferCopyRegion {
 #[derive(Debug)]
 pub enum Error {
     GpuBufferCreateFailed,
+    UploadBufferCreateFailed,
+    UploadBufferMapFailed,
+    ReadbackBufferCreateFailed,
+    ReadbackBufferMapFailed,
 }
 
+// What a GpuBuffer holds, which decides the views it caches.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BufferUsage {
+    Raw,
+    Vertex,
+    Index,
+    Structured,
+}
+
+// Element type of index buffers, which D3D12 only supports as 16 or 32 bit unsigned integers.
+pub trait IndexType: Copy {
+    const FORMAT: dxgiformat::DXGI_FORMAT;
+}
+
+impl IndexType for u16 {
+    const FORMAT: dxgiformat::DXGI_FORMAT = dxgiformat::DXGI_FORMAT_R16_UINT;
+}
+
+impl IndexType for u32 {
+    const FORMAT: dxgiformat::DXGI_FORMAT = dxgiformat::DXGI_FORMAT_R32_UINT;
+}
+
+// Default heap buffer, only accessible by the GPU once initialized.
 pub struct GpuBuffer {
-    resource: GpuResource,
+    pub(crate) resource: GpuResource,
     size: u64,
+    // Size of one element in bytes, 0 for raw buffers.
+    stride: u32,
+    usage: BufferUsage,
+    vertex_buffer_view: Option<d3d12::D3D12_VERTEX_BUFFER_VIEW>,
+    index_buffer_view: Option<d3d12::D3D12_INDEX_BUFFER_VIEW>,
+    shader_resource_view: Option<CpuDescriptor>,
+    unordered_access_view: Option<CpuDescriptor>,
 }
 
 impl GpuBuffer {
     pub fn create(device: Device, size: u64) -> Result<Self, Error> {
+        Self::create_committed(
+            &device,
+            size,
+            0,
+            BufferUsage::Raw,
+            d3d12::D3D12_RESOURCE_FLAG_NONE,
+        )
+    }
+
+    // Creates a vertex buffer holding `vertices`, uploaded before returning.
+    pub fn vertex<T: Copy>(
+        device: &Device,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        vertices: &[T],
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::create_committed(
+            device,
+            mem::size_of_val(vertices) as u64,
+            mem::size_of::<T>() as u32,
+            BufferUsage::Vertex,
+            d3d12::D3D12_RESOURCE_FLAG_NONE,
+        )?;
+        buffer.upload(command_queue, pool, vertices);
+        buffer.vertex_buffer_view = Some(buffer.resource.vertex_buffer_view(buffer.stride));
+        Ok(buffer)
+    }
+
+    // Creates an index buffer holding `indices`, uploaded before returning.
+    pub fn index<T: IndexType>(
+        device: &Device,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        indices: &[T],
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::create_committed(
+            device,
+            mem::size_of_val(indices) as u64,
+            mem::size_of::<T>() as u32,
+            BufferUsage::Index,
+            d3d12::D3D12_RESOURCE_FLAG_NONE,
+        )?;
+        buffer.upload(command_queue, pool, indices);
+        buffer.index_buffer_view = Some(buffer.resource.index_buffer_view(T::FORMAT));
+        Ok(buffer)
+    }
+
+    // Creates an uninitialized buffer of `count` elements of `stride` bytes, with a view of all
+    // of them written to `srv_descriptor`. Compute shaders can write to it when a UAV descriptor
+    // is given. The descriptors stay owned by the caller.
+    pub fn structured(
+        device: &Device,
+        count: u32,
+        stride: u32,
+        srv_descriptor: CpuDescriptor,
+        uav_descriptor: Option<CpuDescriptor>,
+    ) -> Result<Self, Error> {
+        let mut buffer = Self::create_committed(
+            device,
+            u64::from(count) * u64::from(stride),
+            stride,
+            BufferUsage::Structured,
+            if uav_descriptor.is_some() {
+                d3d12::D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS
+            } else {
+                d3d12::D3D12_RESOURCE_FLAG_NONE
+            },
+        )?;
+        buffer
+            .resource
+            .create_structured_srv(device, srv_descriptor, 0, count, stride);
+        buffer.shader_resource_view = Some(srv_descriptor);
+        if let Some(uav_descriptor) = uav_descriptor {
+            buffer
+                .resource
+                .create_structured_uav(device, uav_descriptor, 0, count, stride, None);
+            buffer.unordered_access_view = Some(uav_descriptor);
+        }
+        Ok(buffer)
+    }
+
+    fn create_committed(
+        device: &Device,
+        size: u64,
+        stride: u32,
+        usage: BufferUsage,
+        flags: d3d12::D3D12_RESOURCE_FLAGS,
+    ) -> Result<Self, Error> {
         let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
         let resource_desc = d3d12::D3D12_RESOURCE_DESC {
             Alignment: 0,
             DepthOrArraySize: 1,
             Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
-            Flags: d3d12::D3D12_RESOURCE_FLAG_NONE,
+            Flags: flags,
             Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
             Height: 1,
             Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
@@ -49,8 +176,8 @@ impl GpuBuffer {
             CreationNodeMask: 1,
             VisibleNodeMask: 1,
         };
-        unsafe {
-            let hr = device.native.CreateCommittedResource(
+        let hr = unsafe {
+            device.native.CreateCommittedResource(
                 &heap_properties,
                 d3d12::D3D12_HEAP_FLAG_NONE,
                 &resource_desc,
@@ -58,18 +185,261 @@ impl GpuBuffer {
                 ptr::null(),
                 &d3d12::ID3D12Resource::uuidof(),
                 &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(GpuBuffer {
+                resource: GpuResource::create(
+                    unsafe { ComPtr::from_ptr(resource) },
+                    d3d12::D3D12_RESOURCE_STATE_COMMON,
+                ),
+                size,
+                stride,
+                usage,
+                vertex_buffer_view: None,
+                index_buffer_view: None,
+                shader_resource_view: None,
+                unordered_access_view: None,
+            })
+        } else {
+            Err(Error::GpuBufferCreateFailed)
+        }
+    }
+
+    // Blocks until the data has been copied on the GPU, the buffer is then in the generic read
+    // state.
+    fn upload<T: Copy>(
+        &mut self,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        data: &[T],
+    ) {
+        if data.is_empty() {
+            return;
+        }
+        CommandContext::init_buffer(
+            command_queue,
+            pool,
+            &mut self.resource,
+            ptr::NonNull::new(data.as_ptr() as *mut u8).unwrap(),
+            self.size,
+            0,
+        );
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    // Number of elements of `stride` bytes, 0 for raw buffers.
+    pub fn element_count(&self) -> u32 {
+        if self.stride == 0 {
+            0
+        } else {
+            (self.size / u64::from(self.stride)) as u32
+        }
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    pub fn vertex_buffer_view(&self) -> &d3d12::D3D12_VERTEX_BUFFER_VIEW {
+        self.vertex_buffer_view
+            .as_ref()
+            .expect("not a vertex buffer")
+    }
+
+    pub fn index_buffer_view(&self) -> &d3d12::D3D12_INDEX_BUFFER_VIEW {
+        self.index_buffer_view
+            .as_ref()
+            .expect("not an index buffer")
+    }
+
+    pub fn shader_resource_view(&self) -> CpuDescriptor {
+        self.shader_resource_view.expect("not a structured buffer")
+    }
+
+    pub fn unordered_access_view(&self) -> CpuDescriptor {
+        self.unordered_access_view
+            .expect("not a writable structured buffer")
+    }
+}
+
+// Upload heap buffer kept persistently mapped for data written by the CPU every frame.
+pub struct UploadBuffer {
+    resource: GpuResource,
+    mapping: *mut u8,
+    size: u64,
+}
+
+impl UploadBuffer {
+    pub fn create(device: &Device, size: u64) -> Result<Self, Error> {
+        let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let resource_desc = d3d12::D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            DepthOrArraySize: 1,
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
+            Flags: d3d12::D3D12_RESOURCE_FLAG_NONE,
+            Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+            Height: 1,
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            MipLevels: 1,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Width: size,
+        };
+        let heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
+            Type: d3d12::D3D12_HEAP_TYPE_UPLOAD,
+            CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 1,
+            VisibleNodeMask: 1,
+        };
+        let hr = unsafe {
+            device.native.CreateCommittedResource(
+                &heap_properties,
+                d3d12::D3D12_HEAP_FLAG_NONE,
+                &resource_desc,
+                d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if FAILED(hr) {
+            return Err(Error::UploadBufferCreateFailed);
+        }
+
+        let resource = GpuResource::create(
+            unsafe { ComPtr::from_ptr(resource) },
+            d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+        );
+
+        // Upload heap resources can stay mapped for their whole lifetime.
+        let mapping = resource.map().map_err(|_| Error::UploadBufferMapFailed)?;
+
+        Ok(UploadBuffer {
+            resource,
+            mapping,
+            size,
+        })
+    }
+
+    pub fn write<T: Copy>(&self, offset: usize, data: &T) {
+        assert!(
+            (offset + mem::size_of::<T>()) as u64 <= self.size,
+            "write out of upload buffer bounds"
+        );
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data as *const T as *const u8,
+                self.mapping.add(offset),
+                mem::size_of::<T>(),
             );
-            if SUCCEEDED(hr) {
-                Ok(GpuBuffer {
-                    resource: GpuResource::create(
-                        unsafe { ComPtr::from_ptr(resource) },
-                        d3d12::D3D12_RESOURCE_STATE_COMMON,
-                    ),
-                    size,
-                })
-            } else {
-                Err(Error::GpuBufferCreateFailed)
-            }
         }
     }
+
+    pub fn create_constant_buffer_view(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        size: u32,
+    ) {
+        // Constant buffer views must be a multiple of 256 bytes.
+        let alignment = d3d12::D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT;
+        let cbv_desc = d3d12::D3D12_CONSTANT_BUFFER_VIEW_DESC {
+            BufferLocation: self.resource.gpu_virtual_address(),
+            SizeInBytes: (size + alignment - 1) & !(alignment - 1),
+        };
+        unsafe {
+            device
+                .native
+                .CreateConstantBufferView(&cbv_desc, descriptor);
+        }
+    }
+}
+
+impl Drop for UploadBuffer {
+    fn drop(&mut self) {
+        self.resource.unmap();
+    }
+}
+
+// Readback heap buffer the GPU copies into, for results that have to be read by the CPU.
+pub struct ReadbackBuffer {
+    pub(crate) resource: GpuResource,
+    size: u64,
+}
+
+impl ReadbackBuffer {
+    pub fn create(device: &Device, size: u64) -> Result<Self, Error> {
+        let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let resource_desc = d3d12::D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            DepthOrArraySize: 1,
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
+            Flags: d3d12::D3D12_RESOURCE_FLAG_NONE,
+            Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+            Height: 1,
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            MipLevels: 1,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Width: size,
+        };
+        let heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
+            Type: d3d12::D3D12_HEAP_TYPE_READBACK,
+            CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 1,
+            VisibleNodeMask: 1,
+        };
+        // Readback heap resources must be created in, and stay in, the copy destination state.
+        let hr = unsafe {
+            device.native.CreateCommittedResource(
+                &heap_properties,
+                d3d12::D3D12_HEAP_FLAG_NONE,
+                &resource_desc,
+                d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if FAILED(hr) {
+            return Err(Error::ReadbackBufferCreateFailed);
+        }
+
+        Ok(ReadbackBuffer {
+            resource: GpuResource::create(
+                unsafe { ComPtr::from_ptr(resource) },
+                d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+            ),
+            size,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // The copy into the buffer must have completed on the GPU before reading.
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        let mapping = self
+            .resource
+            .map()
+            .map_err(|_| Error::ReadbackBufferMapFailed)?;
+        let data = unsafe { slice::from_raw_parts(mapping, self.size as usize) }.to_vec();
+        self.resource.unmap();
+        Ok(data)
+    }
 }
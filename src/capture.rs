@@ -0,0 +1,98 @@
+use winapi::shared::minwindef;
+use winapi::um::libloaderapi;
+
+use std::os::raw::{c_int, c_void};
+use std::{mem, ptr};
+
+#[derive(Debug)]
+pub enum Error {
+    // RenderDoc is not injected in the process, launch the application from RenderDoc.
+    RenderDocNotLoaded,
+    RenderDocGetApiFailed,
+}
+
+// eRENDERDOC_API_Version_1_1_2, the oldest version with every function used here.
+const RENDERDOC_API_VERSION_1_1_2: c_int = 10102;
+
+type RenderDocGetApi = unsafe extern "C" fn(c_int, *mut *mut c_void) -> c_int;
+
+// Leading entries of RENDERDOC_API_1_1_2, only the capture functions are typed.
+#[repr(C)]
+struct RenderDocApi {
+    _get_api_version: usize,
+    _set_capture_option_u32: usize,
+    _set_capture_option_f32: usize,
+    _get_capture_option_u32: usize,
+    _get_capture_option_f32: usize,
+    _set_focus_toggle_keys: usize,
+    _set_capture_keys: usize,
+    _get_overlay_bits: usize,
+    _mask_overlay_bits: usize,
+    _shutdown: usize,
+    _unload_crash_handler: usize,
+    _set_log_file_path_template: usize,
+    _get_log_file_path_template: usize,
+    _get_num_captures: usize,
+    _get_capture: usize,
+    trigger_capture: unsafe extern "C" fn(),
+    _is_target_control_connected: usize,
+    _launch_replay_ui: usize,
+    _set_active_window: usize,
+    start_frame_capture: unsafe extern "C" fn(*mut c_void, *mut c_void),
+    is_frame_capturing: unsafe extern "C" fn() -> u32,
+    end_frame_capture: unsafe extern "C" fn(*mut c_void, *mut c_void) -> u32,
+}
+
+// RenderDoc in-application API, to take captures from code, for instance when a problematic frame
+// is detected. Captures apply to the active device and window.
+pub struct RenderDoc {
+    api: *const RenderDocApi,
+}
+
+impl RenderDoc {
+    // Succeeds only when RenderDoc has been injected in the process, which must happen before the
+    // device is created for it to hook D3D12, so the library is never loaded here.
+    pub fn new() -> Result<Self, Error> {
+        unsafe {
+            let module = libloaderapi::GetModuleHandleA("renderdoc.dll\0".as_ptr() as *const _);
+            if module.is_null() {
+                return Err(Error::RenderDocNotLoaded);
+            }
+
+            let proc_address =
+                libloaderapi::GetProcAddress(module, "RENDERDOC_GetAPI\0".as_ptr() as *const _);
+            if proc_address.is_null() {
+                return Err(Error::RenderDocGetApiFailed);
+            }
+            let get_api: RenderDocGetApi = mem::transmute(proc_address);
+
+            let mut api: *mut c_void = ptr::null_mut();
+            if get_api(RENDERDOC_API_VERSION_1_1_2, &mut api) != minwindef::TRUE || api.is_null() {
+                return Err(Error::RenderDocGetApiFailed);
+            }
+            Ok(RenderDoc {
+                api: api as *const RenderDocApi,
+            })
+        }
+    }
+
+    // Captures the next frame presented.
+    pub fn trigger_capture(&self) {
+        unsafe { ((*self.api).trigger_capture)() }
+    }
+
+    // Starts capturing immediately, until end_frame_capture(). Allows capturing work that is
+    // never presented, like headless or compute only frames.
+    pub fn start_frame_capture(&self) {
+        unsafe { ((*self.api).start_frame_capture)(ptr::null_mut(), ptr::null_mut()) }
+    }
+
+    pub fn is_frame_capturing(&self) -> bool {
+        unsafe { ((*self.api).is_frame_capturing)() != 0 }
+    }
+
+    // Returns whether the capture was saved.
+    pub fn end_frame_capture(&self) -> bool {
+        unsafe { ((*self.api).end_frame_capture)(ptr::null_mut(), ptr::null_mut()) != 0 }
+    }
+}
@@ -22,6 +22,7 @@ impl<T> ComPtr<T> {
         self.0.as_ptr()
     }
 
+    // Gives up ownership of the reference without releasing it.
     pub fn into_ptr(self) -> *mut T {
         let p = self.0.as_ptr();
         mem::forget(self);
@@ -40,7 +41,7 @@ impl<T> ComPtr<T> {
         unsafe { ComPtr::from_ptr(self.into_ptr() as *mut U) }
     }
 
-    // Cast creates a new ComPtr requiring explicit destroy call to avoid memory leaks.
+    // Cast creates a new ComPtr holding its own reference, released independently on drop.
     pub fn cast<U>(&self) -> Result<ComPtr<U>, i32>
     where
         U: Interface,
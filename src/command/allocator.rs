@@ -6,6 +6,7 @@ use winapi::shared::winerror::{FAILED, SUCCEEDED};
 use winapi::um::d3d12;
 use winapi::Interface;
 
+use std::collections::VecDeque;
 use std::ptr;
 
 #[derive(Debug)]
@@ -19,7 +20,8 @@ pub struct CommandAllocatorPool {
     device: Device,
     type_: CommandListType,
     pool: Vec<CommandAllocator>,
-    free_list: Vec<(u64, usize)>,
+    // Freed allocators with the fence value of their last submission, oldest first.
+    free_list: VecDeque<(u64, usize)>,
 }
 
 impl CommandAllocatorPool {
@@ -28,14 +30,19 @@ impl CommandAllocatorPool {
             device,
             type_,
             pool: Vec::new(),
-            free_list: Vec::new(),
+            free_list: VecDeque::new(),
         }
     }
 
+    // Allocators are only reused once the GPU is done with the commands recorded from them, as
+    // resetting one that is still in flight corrupts those commands.
     pub fn request(&mut self, completed_fence_value: u64) -> &CommandAllocator {
-        match self.free_list.last() {
-            Some((fence_value, command_allocator_id)) if *fence_value <= completed_fence_value => {
-                &self.pool[*command_allocator_id]
+        match self.free_list.front() {
+            Some(&(fence_value, command_allocator_id)) if fence_value <= completed_fence_value => {
+                self.free_list.pop_front();
+                let command_allocator = &self.pool[command_allocator_id];
+                command_allocator.reset().unwrap();
+                command_allocator
             }
             _ => {
                 let id = self.pool.len();
@@ -48,16 +55,21 @@ impl CommandAllocatorPool {
     }
 
     pub fn free(&mut self, fence_value: u64, command_allocator: CommandAllocator) {
-        self.free_list.push((fence_value, command_allocator.id))
+        self.free_list
+            .push_back((fence_value, command_allocator.id))
     }
 }
 
+// Clones share the same allocator, which may be used from any thread but by only one command list
+// recording at a time, and only reset once the GPU is done with what was recorded into it.
 #[derive(Clone)]
 pub struct CommandAllocator {
     pub(crate) native: ComPtr<d3d12::ID3D12CommandAllocator>,
     pub(crate) id: usize,
 }
 
+unsafe impl Send for CommandAllocator {}
+
 impl CommandAllocator {
     pub fn new(device: &Device, type_: CommandListType, id: usize) -> Result<Self, Error> {
         let mut command_allocator: *mut d3d12::ID3D12CommandAllocator = ptr::null_mut();
@@ -102,3 +114,53 @@ impl CommandAllocator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandQueue;
+    use crate::device::create_test_device;
+    use crate::sync::Fence;
+
+    #[test]
+    fn pool_reuses_allocators_once_their_fence_completed() {
+        let device = create_test_device();
+        let mut pool = CommandAllocatorPool::new(device, CommandListType::Direct);
+
+        let id = pool.request(0).id;
+        pool.free(5, pool.pool[id].clone());
+
+        assert_ne!(pool.request(4).id, id);
+        assert_eq!(pool.request(5).id, id);
+    }
+
+    #[test]
+    fn queue_does_not_reissue_in_flight_allocators() {
+        let device = create_test_device();
+        let mut queue = CommandQueue::new(
+            &device,
+            CommandListType::Direct,
+            d3d12::D3D12_COMMAND_QUEUE_FLAG_NONE,
+            "Adamant::TestQueue",
+        )
+        .unwrap();
+
+        // Hold the queue back until the CPU signals the gate, so the fence stays pending.
+        let gate = Fence::new(&device).unwrap();
+        unsafe { queue.native.Wait(gate.0.as_ptr(), 1) };
+        let fence_value = queue.signal_fence().unwrap();
+
+        let command_allocator = queue.request_allocator();
+        let id = command_allocator.id;
+        queue.free_allocator(fence_value, command_allocator);
+
+        assert!(!queue.is_fence_complete(fence_value));
+        assert_ne!(queue.request_allocator().id, id);
+
+        gate.signal(1).unwrap();
+        queue.wait_for_value(fence_value).unwrap();
+
+        assert!(queue.is_fence_complete(fence_value));
+        assert_eq!(queue.request_allocator().id, id);
+    }
+}
@@ -1,11 +1,13 @@
-use crate::buffer::BufferCopyRegion;
+use crate::buffer::{self, BufferCopyRegion, ReadbackBuffer};
 use crate::command::{CommandAllocator, CommandListType, CommandQueue, GraphicsCommandList};
 use crate::descriptor::CpuDescriptor;
 use crate::device::Device;
 use crate::memory::{AllocationType, MemoryAllocator};
-use crate::resource::GpuResource;
+use crate::mips::MipGenerator;
+use crate::query::TimestampQueries;
+use crate::resource::{GpuResource, SubresourceData, ALL_SUBRESOURCES};
 
-use winapi::shared::minwindef;
+use winapi::shared::dxgiformat;
 use winapi::um::d3d12;
 
 use std::cell::RefCell;
@@ -13,63 +15,66 @@ use std::collections::HashMap;
 use std::mem;
 use std::ptr;
 
+// Recycles command contexts per command list type. A context is obtained with request(), which
+// also hands it a command allocator the GPU is done with, and is given back with
+// CommandContext::end() once its commands have been submitted.
 pub struct CommandContextPool {
     device: Device,
-    pool: HashMap<CommandListType, Vec<CommandContext>>,
-    free_list: HashMap<CommandListType, Vec<usize>>,
+    available: HashMap<CommandListType, Vec<CommandContext>>,
+    context_count: usize,
 }
 
 impl CommandContextPool {
     pub fn new(device: Device) -> Self {
         CommandContextPool {
             device,
-            pool: HashMap::new(),
-            free_list: HashMap::new(),
+            available: HashMap::new(),
+            context_count: 0,
         }
     }
 
-    pub fn request(
-        &mut self,
-        type_: CommandListType,
-        command_allocator: &CommandAllocator,
-    ) -> &CommandContext {
-        if !self.free_list.contains_key(&type_) {
-            let command_contexts = self.pool.entry(type_).or_insert_with(Vec::new);
-            let id = command_contexts.len();
-            let command_context = CommandContext::new(&self.device, command_allocator, type_, id);
-            command_contexts.push(command_context);
-            command_contexts.last().unwrap()
-        } else {
-            let free_list = self.free_list.get_mut(&type_).unwrap();
-            let context_id = free_list.pop().unwrap();
-            let command_contexts = self.pool.get(&type_).unwrap();
-            let command_context = &command_contexts[context_id];
-            command_context.reset();
-            command_context
+    // Returns a context ready for recording commands to be submitted to `command_queue`.
+    pub fn request(&mut self, command_queue: &CommandQueue) -> CommandContext {
+        let type_ = command_queue.type_();
+        let command_allocator = command_queue.request_allocator();
+        match self
+            .available
+            .get_mut(&type_)
+            .and_then(|command_contexts| command_contexts.pop())
+        {
+            Some(mut command_context) => {
+                command_context.reset(command_allocator);
+                command_context
+            }
+            None => {
+                let id = self.context_count;
+                self.context_count += 1;
+                CommandContext::new(&self.device, command_allocator, type_, id)
+            }
         }
     }
 
-    pub fn free(&mut self, command_context: &CommandContext) {
-        let free_list = self
-            .free_list
+    fn free(&mut self, command_context: CommandContext) {
+        self.available
             .entry(command_context.type_)
-            .or_insert_with(Vec::new);
-        free_list.push(command_context.id);
+            .or_insert_with(Vec::new)
+            .push(command_context);
     }
 }
 
 pub struct CommandContext {
     resource_barriers: RefCell<Vec<d3d12::D3D12_RESOURCE_BARRIER>>,
     command_list: RefCell<GraphicsCommandList>,
+    command_allocator: CommandAllocator,
     cpu_memory_allocator: RefCell<MemoryAllocator>,
     pub(crate) type_: CommandListType,
     pub(crate) id: usize,
 }
 
 impl CommandContext {
-    pub fn new(
+    fn new(
         device: &Device,
-        allocator: &CommandAllocator,
+        command_allocator: CommandAllocator,
         type_: CommandListType,
         id: usize,
     ) -> Self {
@@ -78,12 +83,13 @@ impl CommandContext {
             command_list: RefCell::new(
                 GraphicsCommandList::new(
                     &device,
-                    allocator,
+                    &command_allocator,
                     type_,
                     &format!("Adamant::CommandContext_{}::CommandList", id),
                 )
                 .unwrap(),
             ),
+            command_allocator,
             cpu_memory_allocator: RefCell::new(MemoryAllocator::new(
                 device.clone(),
                 AllocationType::CpuWritable,
@@ -93,138 +99,758 @@ impl CommandContext {
         }
     }
 
-    pub fn begin(&self) {}
+    // Submits the commands recorded so far and keeps recording into the same allocator.
+    pub fn flush(&self, command_queue: &mut CommandQueue, wait_for_completion: bool) {
+        self.flush_resource_barriers();
 
-    pub fn flush(
-        &self,
+        let command_list = self.command_list.borrow();
+        command_list.close().unwrap();
+        command_queue.execute_command_list(command_list.as_command_list());
+        let fence_value = command_queue.signal_fence().unwrap();
+
+        if wait_for_completion {
+            command_queue.wait_for_value(fence_value).unwrap();
+        }
+
+        command_list.reset(&self.command_allocator).unwrap();
+    }
+
+    // Submits the recorded commands and returns the context and its allocator to their pools.
+    // Without waiting for completion, resources referenced by the commands must be kept alive
+    // until the GPU is done with them.
+    pub fn end(
+        self,
         command_queue: &mut CommandQueue,
-        command_allocator: CommandAllocator,
+        command_context_pool: &mut CommandContextPool,
         wait_for_completion: bool,
     ) {
         self.flush_resource_barriers();
 
-        command_queue.execute_command_list(self.command_list.borrow().as_command_list());
-        command_queue.signal_fence().unwrap();
+        {
+            let command_list = self.command_list.borrow();
+            command_list.close().unwrap();
+            command_queue.execute_command_list(command_list.as_command_list());
+        }
+        let fence_value = command_queue.signal_fence().unwrap();
+        command_queue.free_allocator(fence_value, self.command_allocator.clone());
 
         if wait_for_completion {
-            command_queue.wait_for_fence().unwrap();
+            command_queue.wait_for_value(fence_value).unwrap();
         }
 
+        command_context_pool.free(self);
+    }
+
+    fn reset(&mut self, command_allocator: CommandAllocator) {
         self.command_list
             .borrow()
             .reset(&command_allocator)
             .unwrap();
+        self.command_allocator = command_allocator;
+        self.resource_barriers.borrow_mut().clear();
     }
 
-    pub fn end(
+    // Transitions one subresource, or all of them with ALL_SUBRESOURCES.
+    pub fn transition_resource(
         &self,
-        command_queue: &mut CommandQueue,
-        command_allocator: CommandAllocator,
-        command_context_pool: &mut CommandContextPool,
-        wait_for_completion: bool,
+        resource: &mut GpuResource,
+        subresource: u32,
+        new_state: d3d12::D3D12_RESOURCE_STATES,
+        flush: bool,
     ) {
-        self.flush_resource_barriers();
+        // A pending split transition to the same state only needs to be ended.
+        if subresource == ALL_SUBRESOURCES && resource.transitioning_state == Some(new_state) {
+            self.transition_resource_split_end(resource, flush);
+            return;
+        }
+        assert!(
+            resource.transitioning_state.is_none(),
+            "resource has a split transition in flight"
+        );
 
-        command_queue.execute_command_list(self.command_list.borrow().as_command_list());
-        command_queue.signal_fence().unwrap();
-        command_queue.free_allocator(command_allocator);
+        if subresource == ALL_SUBRESOURCES {
+            if resource.subresource_states.is_empty() {
+                let old_state = resource.usage_state;
+                if old_state != new_state {
+                    self.push_transition_barrier(
+                        resource,
+                        ALL_SUBRESOURCES,
+                        old_state,
+                        new_state,
+                        d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                    );
+                }
+            } else {
+                let subresource_states = mem::replace(&mut resource.subresource_states, Vec::new());
+                for (index, &old_state) in subresource_states.iter().enumerate() {
+                    if old_state != new_state {
+                        self.push_transition_barrier(
+                            resource,
+                            index as _,
+                            old_state,
+                            new_state,
+                            d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                        );
+                    }
+                }
+            }
+            resource.usage_state = new_state;
+        } else {
+            if resource.subresource_states.is_empty() {
+                resource.subresource_states =
+                    vec![resource.usage_state; resource.subresource_count() as usize];
+            }
 
-        if wait_for_completion {
-            command_queue.wait_for_fence().unwrap();
+            let old_state = resource.subresource_states[subresource as usize];
+            if old_state != new_state {
+                self.push_transition_barrier(
+                    resource,
+                    subresource,
+                    old_state,
+                    new_state,
+                    d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                );
+                resource.subresource_states[subresource as usize] = new_state;
+            }
+
+            // Go back to tracking a single state once every subresource agrees again.
+            if resource
+                .subresource_states
+                .iter()
+                .all(|&state| state == new_state)
+            {
+                resource.subresource_states.clear();
+                resource.usage_state = new_state;
+            }
         }
 
-        command_context_pool.free(&self);
+        if flush {
+            self.flush_resource_barriers();
+        }
     }
 
-    pub fn reset(&self) {}
-
-    pub fn transition_resource(
+    // Starts a transition that the GPU can overlap with the work recorded until the matching
+    // transition_resource_split_end. The resource must not be used in between.
+    pub fn transition_resource_split_begin(
         &self,
         resource: &mut GpuResource,
         new_state: d3d12::D3D12_RESOURCE_STATES,
         flush: bool,
     ) {
+        assert!(
+            resource.transitioning_state.is_none(),
+            "resource already has a split transition in flight"
+        );
+        assert!(
+            resource.subresource_states.is_empty(),
+            "split transitions apply to resources whose subresources share a state"
+        );
+
         let old_state = resource.usage_state;
         if old_state != new_state {
-            let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
-                Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                u: unsafe { mem::zeroed() },
-            };
-            *unsafe { barrier.u.Transition_mut() } = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
-                pResource: resource.native.as_ptr(),
-                Subresource: d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                StateBefore: old_state,
-                StateAfter: new_state,
-            };
+            self.push_transition_barrier(
+                resource,
+                ALL_SUBRESOURCES,
+                old_state,
+                new_state,
+                d3d12::D3D12_RESOURCE_BARRIER_FLAG_BEGIN_ONLY,
+            );
+            resource.transitioning_state = Some(new_state);
+        }
 
-            let mut resource_barriers = self.resource_barriers.borrow_mut();
-            resource_barriers.push(barrier);
+        if flush {
+            self.flush_resource_barriers();
+        }
+    }
 
-            resource.usage_state = new_state;
+    pub fn transition_resource_split_end(&self, resource: &mut GpuResource, flush: bool) {
+        let new_state = resource
+            .transitioning_state
+            .take()
+            .expect("resource has no split transition in flight");
+        self.push_transition_barrier(
+            resource,
+            ALL_SUBRESOURCES,
+            resource.usage_state,
+            new_state,
+            d3d12::D3D12_RESOURCE_BARRIER_FLAG_END_ONLY,
+        );
+        resource.usage_state = new_state;
+
+        if flush {
+            self.flush_resource_barriers();
         }
+    }
+
+    // Orders dependent unordered access writes to `resource`, or to every UAV if None.
+    pub fn uav_barrier(&self, resource: Option<&GpuResource>, flush: bool) {
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV,
+            Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+            u: unsafe { mem::zeroed() },
+        };
+        *unsafe { barrier.u.UAV_mut() } = d3d12::D3D12_RESOURCE_UAV_BARRIER {
+            pResource: resource.map_or(ptr::null_mut(), |resource| resource.native.as_ptr()),
+        };
+        self.resource_barriers.borrow_mut().push(barrier);
 
         if flush {
             self.flush_resource_barriers();
         }
     }
 
+    // Switches which of two placed resources sharing heap memory is in use. None stands for any
+    // resource placed in the overlapping memory.
+    pub fn aliasing_barrier(
+        &self,
+        before: Option<&GpuResource>,
+        after: Option<&GpuResource>,
+        flush: bool,
+    ) {
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_ALIASING,
+            Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+            u: unsafe { mem::zeroed() },
+        };
+        *unsafe { barrier.u.Aliasing_mut() } = d3d12::D3D12_RESOURCE_ALIASING_BARRIER {
+            pResourceBefore: before.map_or(ptr::null_mut(), |resource| resource.native.as_ptr()),
+            pResourceAfter: after.map_or(ptr::null_mut(), |resource| resource.native.as_ptr()),
+        };
+        self.resource_barriers.borrow_mut().push(barrier);
+
+        if flush {
+            self.flush_resource_barriers();
+        }
+    }
+
+    fn push_transition_barrier(
+        &self,
+        resource: &GpuResource,
+        subresource: u32,
+        state_before: d3d12::D3D12_RESOURCE_STATES,
+        state_after: d3d12::D3D12_RESOURCE_STATES,
+        flags: d3d12::D3D12_RESOURCE_BARRIER_FLAGS,
+    ) {
+        Self::push_transition(
+            &mut self.resource_barriers.borrow_mut(),
+            resource.native.as_ptr(),
+            subresource,
+            state_before,
+            state_after,
+            flags,
+        );
+    }
+
+    // A pending transition of the same subresource into `state_before` is extended to
+    // `state_after` instead, and dropped when that brings the subresource back to its state.
+    fn push_transition(
+        resource_barriers: &mut Vec<d3d12::D3D12_RESOURCE_BARRIER>,
+        resource: *mut d3d12::ID3D12Resource,
+        subresource: u32,
+        state_before: d3d12::D3D12_RESOURCE_STATES,
+        state_after: d3d12::D3D12_RESOURCE_STATES,
+        flags: d3d12::D3D12_RESOURCE_BARRIER_FLAGS,
+    ) {
+        if flags == d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE {
+            if let Some(index) =
+                Self::find_pending_transition(resource_barriers, resource, subresource)
+            {
+                let transition = unsafe { resource_barriers[index].u.Transition_mut() };
+                if transition.StateAfter == state_before {
+                    if transition.StateBefore == state_after {
+                        resource_barriers.remove(index);
+                    } else {
+                        transition.StateAfter = state_after;
+                    }
+                    return;
+                }
+            }
+        }
+
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+            Flags: flags,
+            u: unsafe { mem::zeroed() },
+        };
+        *unsafe { barrier.u.Transition_mut() } = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
+            pResource: resource,
+            Subresource: subresource,
+            StateBefore: state_before,
+            StateAfter: state_after,
+        };
+        resource_barriers.push(barrier);
+    }
+
+    // Index of the last pending barrier involving `resource` when it is a plain transition of
+    // `subresource`, which can then be merged with a following transition. Any other barrier
+    // involving the resource, including UAV barriers on every resource, must keep its place.
+    fn find_pending_transition(
+        resource_barriers: &[d3d12::D3D12_RESOURCE_BARRIER],
+        resource: *mut d3d12::ID3D12Resource,
+        subresource: u32,
+    ) -> Option<usize> {
+        for (index, barrier) in resource_barriers.iter().enumerate().rev() {
+            match barrier.Type {
+                d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION => {
+                    let transition = unsafe { barrier.u.Transition() };
+                    if transition.pResource != resource {
+                        continue;
+                    }
+                    return if transition.Subresource == subresource
+                        && barrier.Flags == d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE
+                    {
+                        Some(index)
+                    } else {
+                        None
+                    };
+                }
+                d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV => {
+                    let uav = unsafe { barrier.u.UAV() };
+                    if uav.pResource.is_null() || uav.pResource == resource {
+                        return None;
+                    }
+                }
+                _ => {
+                    let aliasing = unsafe { barrier.u.Aliasing() };
+                    if aliasing.pResourceBefore.is_null()
+                        || aliasing.pResourceBefore == resource
+                        || aliasing.pResourceAfter.is_null()
+                        || aliasing.pResourceAfter == resource
+                    {
+                        return None;
+                    }
+                }
+            }
+        }
+        None
+    }
+
     pub fn flush_resource_barriers(&self) {
-        self.command_list
-            .borrow()
-            .insert_resource_barriers(&self.resource_barriers.borrow());
+        let mut resource_barriers = self.resource_barriers.borrow_mut();
+        if !resource_barriers.is_empty() {
+            self.command_list
+                .borrow()
+                .insert_resource_barriers(&resource_barriers);
+            resource_barriers.clear();
+        }
+    }
+
+    pub fn begin_event(&self, name: &str) {
+        self.command_list.borrow().begin_event(name);
+    }
+
+    pub fn end_event(&self) {
+        self.command_list.borrow().end_event();
+    }
+
+    pub fn set_marker(&self, name: &str) {
+        self.command_list.borrow().set_marker(name);
+    }
+
+    pub fn begin_timestamp(&self, queries: &TimestampQueries, label: &str) {
+        let index = queries.begin(label);
+        self.command_list.borrow().end_query(
+            &queries.heap,
+            d3d12::D3D12_QUERY_TYPE_TIMESTAMP,
+            index,
+        );
+    }
+
+    pub fn end_timestamp(&self, queries: &TimestampQueries, label: &str) {
+        let index = queries.end(label);
+        self.command_list.borrow().end_query(
+            &queries.heap,
+            d3d12::D3D12_QUERY_TYPE_TIMESTAMP,
+            index,
+        );
+    }
+
+    // Copies the timestamps written so far to the CPU, readable with TimestampQueries::read once
+    // this context's commands have completed.
+    pub fn resolve_timestamps(&self, queries: &TimestampQueries) {
+        self.command_list.borrow().resolve_query_data(
+            &queries.heap,
+            d3d12::D3D12_QUERY_TYPE_TIMESTAMP,
+            0,
+            queries.query_count(),
+            &queries.readback.resource,
+            0,
+        );
     }
 
     pub fn set_render_targets(
         &self,
         rtv_descriptors: &[CpuDescriptor],
+        dsv_descriptor: Option<CpuDescriptor>,
+    ) {
+        self.command_list
+            .borrow()
+            .set_render_targets(rtv_descriptors, dsv_descriptor);
+    }
+
+    pub fn set_stencil_ref(&self, value: u32) {
+        self.command_list.borrow().set_stencil_ref(value);
+    }
+
+    pub fn set_blend_factor(&self, factor: [f32; 4]) {
+        self.command_list.borrow().set_blend_factor(factor);
+    }
+
+    pub fn set_viewports(&self, viewports: &[d3d12::D3D12_VIEWPORT]) {
+        self.command_list.borrow().set_viewports(viewports);
+    }
+
+    pub fn set_scissor_rects(&self, rects: &[d3d12::D3D12_RECT]) {
+        self.command_list.borrow().set_scissor_rects(rects);
+    }
+
+    pub fn clear_render_target(&self, rtv_descriptor: CpuDescriptor, color: [f32; 4]) {
+        self.command_list
+            .borrow()
+            .clear_render_target(rtv_descriptor, color);
+    }
+
+    pub fn clear_depth_stencil(
+        &self,
         dsv_descriptor: CpuDescriptor,
+        flags: d3d12::D3D12_CLEAR_FLAGS,
+        depth: f32,
+        stencil: u8,
     ) {
-        unsafe {
-            self.command_list.borrow().0.OMSetRenderTargets(
-                rtv_descriptors.len() as _,
-                rtv_descriptors.as_ptr(),
-                minwindef::FALSE,
-                &dsv_descriptor,
-            );
-        }
+        self.command_list
+            .borrow()
+            .clear_depth_stencil(dsv_descriptor, flags, depth, stencil);
     }
 
     pub fn init_buffer(
         command_queue: &mut CommandQueue,
-        command_allocator: CommandAllocator,
-        command_context_pool: &mut CommandContextPool,
         pool: &mut CommandContextPool,
         dest: &mut GpuResource,
         data: ptr::NonNull<u8>,
         size: u64,
         offset: u64,
     ) {
-        let init_context = pool.request(CommandListType::Direct, &command_allocator);
-        init_context.begin();
+        let init_context = pool.request(command_queue);
 
         // Upload buffer data into GPU memory
-        let mut allocator = init_context.cpu_memory_allocator.borrow_mut();
-        let memory = allocator.allocate(size);
-        unsafe {
-            let mapping = memory.resource.map().unwrap();
-            ptr::copy_nonoverlapping(data.as_ptr(), mapping, size as _);
-            memory.resource.unmap();
+        {
+            let mut allocator = init_context.cpu_memory_allocator.borrow_mut();
+            let memory = allocator.allocate(size);
+            unsafe {
+                let mapping = memory.resource.map().unwrap();
+                ptr::copy_nonoverlapping(data.as_ptr(), mapping, size as _);
+                memory.resource.unmap();
+            }
+
+            init_context.transition_resource(
+                dest,
+                ALL_SUBRESOURCES,
+                d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+                true,
+            );
+            let command_list = init_context.command_list.borrow();
+            command_list.copy_buffer(
+                dest,
+                &memory.resource,
+                &[BufferCopyRegion {
+                    source_offset: 0,
+                    dest_offset: offset,
+                    size,
+                }],
+            );
+        }
+        init_context.transition_resource(
+            dest,
+            ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+            true,
+        );
+
+        init_context.end(command_queue, pool, true);
+    }
+
+    // Uploads every subresource of `dest`, in subresource order, and leaves it ready for pixel
+    // shader reads. Rows are copied one by one to honor the upload footprint's row pitch alignment.
+    pub fn init_texture(
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        dest: &mut GpuResource,
+        subresources: &[SubresourceData],
+    ) {
+        let desc = unsafe { dest.native.GetDesc() };
+        let (layouts, row_counts, row_sizes, total_size) =
+            pool.device
+                .copyable_footprints(&desc, 0, subresources.len() as _, 0);
+
+        let init_context = pool.request(command_queue);
+
+        // Write each subresource into upload memory following its footprint.
+        {
+            let mut allocator = init_context.cpu_memory_allocator.borrow_mut();
+            let memory = allocator.allocate(total_size);
+            unsafe {
+                let mapping = memory.resource.map().unwrap();
+                for (i, subresource) in subresources.iter().enumerate() {
+                    let layout = &layouts[i];
+                    let row_count = row_counts[i] as usize;
+                    let row_size = row_sizes[i] as usize;
+                    let dest_row_pitch = layout.Footprint.RowPitch as usize;
+                    let dest_slice_pitch = dest_row_pitch * row_count;
+                    for z in 0..layout.Footprint.Depth as usize {
+                        for row in 0..row_count {
+                            let source_offset =
+                                z * subresource.slice_pitch + row * subresource.row_pitch;
+                            let source = &subresource.data[source_offset..source_offset + row_size];
+                            ptr::copy_nonoverlapping(
+                                source.as_ptr(),
+                                mapping.add(
+                                    layout.Offset as usize
+                                        + z * dest_slice_pitch
+                                        + row * dest_row_pitch,
+                                ),
+                                row_size,
+                            );
+                        }
+                    }
+                }
+                memory.resource.unmap();
+            }
+
+            init_context.transition_resource(
+                dest,
+                ALL_SUBRESOURCES,
+                d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+                true,
+            );
+            let command_list = init_context.command_list.borrow();
+            for (i, layout) in layouts.iter().enumerate() {
+                command_list.copy_texture_from_buffer(dest, i as _, &memory.resource, *layout);
+            }
         }
+        init_context.transition_resource(
+            dest,
+            ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            true,
+        );
+
+        init_context.end(command_queue, pool, true);
+    }
 
-        init_context.transition_resource(dest, d3d12::D3D12_RESOURCE_STATE_COPY_DEST, true);
-        let command_list = init_context.command_list.borrow();
-        command_list.copy_buffer(
+    // Copies a region of `source_subresource`, or all of it without a box, to the origin of
+    // `dest_subresource`, transitioning both subresources for the copy.
+    pub fn copy_texture_region(
+        &self,
+        dest: &mut GpuResource,
+        dest_subresource: u32,
+        source: &mut GpuResource,
+        source_subresource: u32,
+        source_box: Option<d3d12::D3D12_BOX>,
+    ) {
+        self.transition_resource(
             dest,
-            &memory.resource,
+            dest_subresource,
+            d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+            false,
+        );
+        self.transition_resource(
+            source,
+            source_subresource,
+            d3d12::D3D12_RESOURCE_STATE_COPY_SOURCE,
+            true,
+        );
+        self.command_list.borrow().copy_texture_region(
+            dest,
+            dest_subresource,
+            source,
+            source_subresource,
+            source_box.as_ref(),
+        );
+    }
+
+    // Resolves the multisampled `source` into `dest`, transitioning both for the resolve. The
+    // format must be compatible with both resources.
+    pub fn resolve_subresource(
+        &self,
+        dest: &mut GpuResource,
+        source: &mut GpuResource,
+        format: dxgiformat::DXGI_FORMAT,
+    ) {
+        self.transition_resource(
+            dest,
+            ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_RESOLVE_DEST,
+            false,
+        );
+        self.transition_resource(
+            source,
+            ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+            true,
+        );
+        self.command_list
+            .borrow()
+            .resolve_subresource(dest, 0, source, 0, format);
+    }
+
+    // Records the mip chain generation of `texture` after the pending barriers.
+    pub fn generate_mips(
+        &self,
+        device: &Device,
+        generator: &MipGenerator,
+        texture: &mut GpuResource,
+    ) {
+        self.flush_resource_barriers();
+        generator.generate(device, &self.command_list.borrow(), texture);
+    }
+
+    // Copies `size` bytes from the start of `source` back to the CPU, waiting for the GPU copy.
+    // Fails when the readback buffer cannot be created or mapped.
+    pub fn read_buffer(
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        source: &mut GpuResource,
+        size: u64,
+    ) -> Result<Vec<u8>, buffer::Error> {
+        let readback = ReadbackBuffer::create(&pool.device, size)?;
+
+        let read_context = pool.request(command_queue);
+
+        let previous_state = source.usage_state;
+        read_context.transition_resource(
+            source,
+            ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_COPY_SOURCE,
+            true,
+        );
+        read_context.command_list.borrow().copy_buffer(
+            &readback.resource,
+            source,
             &[BufferCopyRegion {
-                source_offset: offset,
+                source_offset: 0,
                 dest_offset: 0,
                 size,
             }],
         );
-        init_context.transition_resource(dest, d3d12::D3D12_RESOURCE_STATE_GENERIC_READ, true);
+        read_context.transition_resource(source, ALL_SUBRESOURCES, previous_state, true);
+
+        read_context.end(command_queue, pool, true);
+
+        readback.read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Barriers are only compared, never submitted, so any distinct pointer stands for a resource.
+    const RESOURCE: *mut d3d12::ID3D12Resource = 0x10 as *mut _;
+    const OTHER_RESOURCE: *mut d3d12::ID3D12Resource = 0x20 as *mut _;
+
+    const A: d3d12::D3D12_RESOURCE_STATES = d3d12::D3D12_RESOURCE_STATE_COPY_DEST;
+    const B: d3d12::D3D12_RESOURCE_STATES = d3d12::D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE;
+    const C: d3d12::D3D12_RESOURCE_STATES = d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET;
+
+    fn transition(
+        resource_barriers: &mut Vec<d3d12::D3D12_RESOURCE_BARRIER>,
+        state_before: d3d12::D3D12_RESOURCE_STATES,
+        state_after: d3d12::D3D12_RESOURCE_STATES,
+    ) {
+        CommandContext::push_transition(
+            resource_barriers,
+            RESOURCE,
+            ALL_SUBRESOURCES,
+            state_before,
+            state_after,
+            d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        );
+    }
+
+    fn states(barrier: &d3d12::D3D12_RESOURCE_BARRIER) -> (u32, u32) {
+        let transition = unsafe { barrier.u.Transition() };
+        (transition.StateBefore, transition.StateAfter)
+    }
+
+    #[test]
+    fn consecutive_transitions_merge() {
+        let mut resource_barriers = Vec::new();
+        transition(&mut resource_barriers, A, B);
+        transition(&mut resource_barriers, B, C);
+
+        assert_eq!(resource_barriers.len(), 1);
+        assert_eq!(states(&resource_barriers[0]), (A, C));
+    }
+
+    #[test]
+    fn round_trip_transitions_cancel_out() {
+        let mut resource_barriers = Vec::new();
+        transition(&mut resource_barriers, A, B);
+        transition(&mut resource_barriers, B, A);
+
+        assert!(resource_barriers.is_empty());
+    }
+
+    #[test]
+    fn transitions_do_not_merge_across_uav_barriers() {
+        for uav_resource in [RESOURCE, ptr::null_mut()].iter() {
+            let mut resource_barriers = Vec::new();
+            transition(&mut resource_barriers, A, B);
+
+            let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+                Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV,
+                Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                u: unsafe { mem::zeroed() },
+            };
+            unsafe { barrier.u.UAV_mut().pResource = *uav_resource };
+            resource_barriers.push(barrier);
+
+            transition(&mut resource_barriers, B, C);
+
+            assert_eq!(resource_barriers.len(), 3);
+            assert_eq!(states(&resource_barriers[0]), (A, B));
+            assert_eq!(states(&resource_barriers[2]), (B, C));
+        }
+    }
+
+    #[test]
+    fn transitions_do_not_merge_across_aliasing_barriers() {
+        let mut resource_barriers = Vec::new();
+        transition(&mut resource_barriers, A, B);
+
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_ALIASING,
+            Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+            u: unsafe { mem::zeroed() },
+        };
+        *unsafe { barrier.u.Aliasing_mut() } = d3d12::D3D12_RESOURCE_ALIASING_BARRIER {
+            pResourceBefore: OTHER_RESOURCE,
+            pResourceAfter: RESOURCE,
+        };
+        resource_barriers.push(barrier);
+
+        transition(&mut resource_barriers, B, C);
+
+        assert_eq!(resource_barriers.len(), 3);
+        assert_eq!(states(&resource_barriers[2]), (B, C));
+    }
+
+    #[test]
+    fn barriers_on_other_resources_do_not_prevent_merging() {
+        let mut resource_barriers = Vec::new();
+        transition(&mut resource_barriers, A, B);
+
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV,
+            Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+            u: unsafe { mem::zeroed() },
+        };
+        unsafe { barrier.u.UAV_mut().pResource = OTHER_RESOURCE };
+        resource_barriers.push(barrier);
+
+        transition(&mut resource_barriers, B, C);
 
-        init_context.end(command_queue, command_allocator, command_context_pool, true);
+        assert_eq!(resource_barriers.len(), 2);
+        assert_eq!(states(&resource_barriers[0]), (A, C));
     }
 }
@@ -1,14 +1,21 @@
 use crate::buffer::BufferCopyRegion;
 use crate::com::ComPtr;
-use crate::command::CommandAllocator;
+use crate::command::{CommandAllocator, CommandSignature};
+use crate::descriptor::{CpuDescriptor, DescriptorHeap, GpuDescriptor};
 use crate::device::Device;
+use crate::pso::PipelineState;
+use crate::query::QueryHeap;
 use crate::resource::GpuResource;
+use crate::root_signature::RootSignature;
 
-use winapi::shared::winerror::{FAILED, SUCCEEDED};
-use winapi::um::d3d12;
+use winapi::shared::{
+    dxgiformat, minwindef,
+    winerror::{FAILED, SUCCEEDED},
+};
+use winapi::um::{d3d12, d3dcommon};
 use winapi::Interface;
 
-use std::ptr;
+use std::{mem, ptr};
 
 #[repr(u32)]
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
@@ -16,6 +23,7 @@ pub enum CommandListType {
     Direct = d3d12::D3D12_COMMAND_LIST_TYPE_DIRECT,
     Compute = d3d12::D3D12_COMMAND_LIST_TYPE_COMPUTE,
     Copy = d3d12::D3D12_COMMAND_LIST_TYPE_COPY,
+    Bundle = d3d12::D3D12_COMMAND_LIST_TYPE_BUNDLE,
 }
 
 #[derive(Debug)]
@@ -26,10 +34,26 @@ pub enum Error {
     CommandListCloseFailed,
 }
 
+// Event metadata telling PIX the payload is a nul terminated UTF-16 string
+// (WINPIX_EVENT_UNICODE_VERSION in pix3.h).
+const PIX_EVENT_UNICODE_VERSION: u32 = 0;
+
+fn pix_event_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(Some(0)).collect()
+}
+
 pub struct CommandList(pub(crate) ComPtr<d3d12::ID3D12CommandList>);
 
+// Only submitted, which the queue does from its own thread.
+unsafe impl Send for CommandList {}
+
+// Command lists can be recorded on any thread, but by only one thread at a time, so they can be
+// moved to a worker thread but not shared. Parallel recording uses one list and one allocator
+// per thread, and the closed lists are handed back to the thread owning the queue for submission.
 pub struct GraphicsCommandList(pub(crate) ComPtr<d3d12::ID3D12GraphicsCommandList>);
 
+unsafe impl Send for GraphicsCommandList {}
+
 impl GraphicsCommandList {
     pub fn new(
         device: &Device,
@@ -37,13 +61,24 @@ impl GraphicsCommandList {
         type_: CommandListType,
         debug_name: &str,
     ) -> Result<Self, Error> {
+        Self::create(device, allocator, type_, None, debug_name)
+    }
+
+    fn create(
+        device: &Device,
+        allocator: &CommandAllocator,
+        type_: CommandListType,
+        initial_state: Option<&PipelineState>,
+        debug_name: &str,
+    ) -> Result<Self, Error> {
+        let initial_state = initial_state.map_or(ptr::null_mut(), |pso| pso.0.as_ptr());
         let mut command_list: *mut d3d12::ID3D12GraphicsCommandList = ptr::null_mut();
         let mut hr = unsafe {
             device.native.CreateCommandList(
                 0,
                 type_ as _,
                 allocator.native.as_ptr(),
-                ptr::null_mut(),
+                initial_state,
                 &d3d12::ID3D12GraphicsCommandList::uuidof(),
                 &mut command_list as *mut *mut _ as *mut *mut _,
             )
@@ -52,19 +87,79 @@ impl GraphicsCommandList {
             return Err(Error::CommandListCreateFailed);
         }
 
-        /*#[cfg(debug_assertions)]
+        let command_list = unsafe { ComPtr::from_ptr(command_list) };
+
+        #[cfg(debug_assertions)]
         {
             hr = unsafe {
-                command_list.SetName(debug_name.encode_utf16().collect::<Vec<u16>>().as_ptr())
+                command_list.SetName(
+                    debug_name
+                        .encode_utf16()
+                        .chain(Some(0))
+                        .collect::<Vec<u16>>()
+                        .as_ptr(),
+                )
             };
             if FAILED(hr) {
                 return Err(Error::CommandListSetNameFailed);
             }
-        }*/
+        }
+
+        Ok(GraphicsCommandList(command_list))
+    }
+
+    // Records a bundle, a short command sequence that is replayed with execute_bundle(). The
+    // allocator must have been created with CommandListType::Bundle. Bundles only inherit the
+    // descriptor heaps bound on the executing list, and its root arguments when the bundle sets
+    // the same root signature. The pipeline state and primitive topology start from their
+    // defaults, so the bundle sets them itself or is created with `initial_state`. Bundles must
+    // not change the descriptor heaps or render targets.
+    pub fn new_bundle(
+        device: &Device,
+        allocator: &CommandAllocator,
+        initial_state: Option<&PipelineState>,
+    ) -> Result<Self, Error> {
+        Self::create(
+            device,
+            allocator,
+            CommandListType::Bundle,
+            initial_state,
+            "Adamant::Bundle",
+        )
+    }
+
+    // Replays a closed bundle on this direct command list.
+    pub fn execute_bundle(&self, bundle: &GraphicsCommandList) {
+        unsafe {
+            self.0.ExecuteBundle(bundle.0.as_ptr());
+        }
+    }
 
-        Ok(GraphicsCommandList(unsafe {
-            ComPtr::from_ptr(command_list)
-        }))
+    // Draws or dispatches up to `max_count` commands laid out as described by `signature`, read
+    // from `arguments` at `arguments_offset`. When given, the count buffer holds the actual number
+    // of commands as a u32, clamped to `max_count`.
+    pub fn execute_indirect(
+        &self,
+        signature: &CommandSignature,
+        max_count: u32,
+        arguments: &GpuResource,
+        arguments_offset: u64,
+        count: Option<(&GpuResource, u64)>,
+    ) {
+        let (count_buffer, count_offset) = match count {
+            Some((count_buffer, count_offset)) => (count_buffer.native.as_ptr(), count_offset),
+            None => (ptr::null_mut(), 0),
+        };
+        unsafe {
+            self.0.ExecuteIndirect(
+                signature.native.as_ptr(),
+                max_count,
+                arguments.native.as_ptr(),
+                arguments_offset,
+                count_buffer,
+                count_offset,
+            );
+        }
     }
 
     pub fn copy_buffer(
@@ -86,6 +181,86 @@ impl GraphicsCommandList {
         }
     }
 
+    // Copies a buffer laid out as described by `footprint` into a texture subresource.
+    pub fn copy_texture_from_buffer(
+        &self,
+        dest: &GpuResource,
+        dest_subresource: u32,
+        source: &GpuResource,
+        footprint: d3d12::D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+    ) {
+        unsafe {
+            let mut dest_location = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: dest.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                u: mem::zeroed(),
+            };
+            *dest_location.u.SubresourceIndex_mut() = dest_subresource;
+            let mut source_location = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: source.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                u: mem::zeroed(),
+            };
+            *source_location.u.PlacedFootprint_mut() = footprint;
+            self.0
+                .CopyTextureRegion(&dest_location, 0, 0, 0, &source_location, ptr::null());
+        }
+    }
+
+    // Copies a region of a texture subresource, or all of it without a box, to the origin of
+    // another texture subresource.
+    pub fn copy_texture_region(
+        &self,
+        dest: &GpuResource,
+        dest_subresource: u32,
+        source: &GpuResource,
+        source_subresource: u32,
+        source_box: Option<&d3d12::D3D12_BOX>,
+    ) {
+        unsafe {
+            let mut dest_location = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: dest.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                u: mem::zeroed(),
+            };
+            *dest_location.u.SubresourceIndex_mut() = dest_subresource;
+            let mut source_location = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: source.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                u: mem::zeroed(),
+            };
+            *source_location.u.SubresourceIndex_mut() = source_subresource;
+            self.0.CopyTextureRegion(
+                &dest_location,
+                0,
+                0,
+                0,
+                &source_location,
+                source_box.map_or(ptr::null(), |source_box| source_box),
+            );
+        }
+    }
+
+    // Resolves a multisampled subresource into a single sampled one, in the given format.
+    pub fn resolve_subresource(
+        &self,
+        dest: &GpuResource,
+        dest_subresource: u32,
+        source: &GpuResource,
+        source_subresource: u32,
+        format: dxgiformat::DXGI_FORMAT,
+    ) {
+        unsafe {
+            self.0.ResolveSubresource(
+                dest.native.as_ptr(),
+                dest_subresource,
+                source.native.as_ptr(),
+                source_subresource,
+                format,
+            );
+        }
+    }
+
     pub fn insert_resource_barriers(&self, barriers: &[d3d12::D3D12_RESOURCE_BARRIER]) {
         unsafe {
             self.0
@@ -93,6 +268,258 @@ impl GraphicsCommandList {
         }
     }
 
+    pub fn set_pipeline_state(&self, pso: &PipelineState) {
+        unsafe {
+            self.0.SetPipelineState(pso.0.as_ptr());
+        }
+    }
+
+    pub fn set_graphics_root_signature(&self, root_signature: &RootSignature) {
+        unsafe {
+            self.0
+                .SetGraphicsRootSignature(root_signature.native.as_ptr());
+        }
+    }
+
+    pub fn set_compute_root_signature(&self, root_signature: &RootSignature) {
+        unsafe {
+            self.0
+                .SetComputeRootSignature(root_signature.native.as_ptr());
+        }
+    }
+
+    pub fn set_graphics_root_descriptor_table(&self, root_index: u32, base: GpuDescriptor) {
+        unsafe {
+            self.0.SetGraphicsRootDescriptorTable(root_index, base);
+        }
+    }
+
+    pub fn set_compute_root_descriptor_table(&self, root_index: u32, base: GpuDescriptor) {
+        unsafe {
+            self.0.SetComputeRootDescriptorTable(root_index, base);
+        }
+    }
+
+    pub fn set_descriptor_heaps(&self, heaps: &[&DescriptorHeap]) {
+        let heaps: Vec<*mut d3d12::ID3D12DescriptorHeap> =
+            heaps.iter().map(|heap| heap.native.as_ptr()).collect();
+        unsafe {
+            self.0
+                .SetDescriptorHeaps(heaps.len() as _, heaps.as_ptr() as *mut _);
+        }
+    }
+
+    pub fn set_compute_root_32bit_constants(&self, root_index: u32, values: &[u32], offset: u32) {
+        unsafe {
+            self.0.SetComputeRoot32BitConstants(
+                root_index,
+                values.len() as _,
+                values.as_ptr() as *const _,
+                offset,
+            );
+        }
+    }
+
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        unsafe {
+            self.0.Dispatch(group_count_x, group_count_y, group_count_z);
+        }
+    }
+
+    pub fn end_query(&self, heap: &QueryHeap, type_: d3d12::D3D12_QUERY_TYPE, index: u32) {
+        unsafe {
+            self.0.EndQuery(heap.native.as_ptr(), type_, index);
+        }
+    }
+
+    // Copies `count` query results, starting at `start_index`, into `dest` as 64 bit values.
+    pub fn resolve_query_data(
+        &self,
+        heap: &QueryHeap,
+        type_: d3d12::D3D12_QUERY_TYPE,
+        start_index: u32,
+        count: u32,
+        dest: &GpuResource,
+        dest_offset: u64,
+    ) {
+        unsafe {
+            self.0.ResolveQueryData(
+                heap.native.as_ptr(),
+                type_,
+                start_index,
+                count,
+                dest.native.as_ptr(),
+                dest_offset,
+            );
+        }
+    }
+
+    // Opens a named region shown in PIX and RenderDoc captures, closed by end_event().
+    pub fn begin_event(&self, name: &str) {
+        let name = pix_event_name(name);
+        unsafe {
+            self.0.BeginEvent(
+                PIX_EVENT_UNICODE_VERSION,
+                name.as_ptr() as *const _,
+                (name.len() * mem::size_of::<u16>()) as _,
+            );
+        }
+    }
+
+    pub fn end_event(&self) {
+        unsafe {
+            self.0.EndEvent();
+        }
+    }
+
+    pub fn set_marker(&self, name: &str) {
+        let name = pix_event_name(name);
+        unsafe {
+            self.0.SetMarker(
+                PIX_EVENT_UNICODE_VERSION,
+                name.as_ptr() as *const _,
+                (name.len() * mem::size_of::<u16>()) as _,
+            );
+        }
+    }
+
+    // Binds up to D3D12_SIMULTANEOUS_RENDER_TARGET_COUNT color targets, and optionally a depth
+    // stencil target.
+    pub fn set_render_targets(
+        &self,
+        rtv_descriptors: &[CpuDescriptor],
+        dsv_descriptor: Option<CpuDescriptor>,
+    ) {
+        assert!(
+            rtv_descriptors.len() <= d3d12::D3D12_SIMULTANEOUS_RENDER_TARGET_COUNT as usize,
+            "too many render targets"
+        );
+        unsafe {
+            self.0.OMSetRenderTargets(
+                rtv_descriptors.len() as _,
+                rtv_descriptors.as_ptr(),
+                minwindef::FALSE,
+                dsv_descriptor
+                    .as_ref()
+                    .map_or(ptr::null(), |dsv_descriptor| dsv_descriptor),
+            );
+        }
+    }
+
+    // Up to D3D12_VIEWPORT_AND_SCISSORRECT_OBJECT_COUNT_PER_PIPELINE viewports, selected per
+    // primitive with SV_ViewportArrayIndex.
+    pub fn set_viewports(&self, viewports: &[d3d12::D3D12_VIEWPORT]) {
+        assert!(
+            viewports.len()
+                <= d3d12::D3D12_VIEWPORT_AND_SCISSORRECT_OBJECT_COUNT_PER_PIPELINE as usize,
+            "too many viewports"
+        );
+        unsafe {
+            self.0
+                .RSSetViewports(viewports.len() as _, viewports.as_ptr());
+        }
+    }
+
+    // One scissor rectangle per viewport, matched by index.
+    pub fn set_scissor_rects(&self, rects: &[d3d12::D3D12_RECT]) {
+        assert!(
+            rects.len() <= d3d12::D3D12_VIEWPORT_AND_SCISSORRECT_OBJECT_COUNT_PER_PIPELINE as usize,
+            "too many scissor rectangles"
+        );
+        unsafe {
+            self.0.RSSetScissorRects(rects.len() as _, rects.as_ptr());
+        }
+    }
+
+    pub fn clear_render_target(&self, rtv_descriptor: CpuDescriptor, color: [f32; 4]) {
+        unsafe {
+            self.0
+                .ClearRenderTargetView(rtv_descriptor, &color, 0, ptr::null());
+        }
+    }
+
+    pub fn clear_depth_stencil(
+        &self,
+        dsv_descriptor: CpuDescriptor,
+        flags: d3d12::D3D12_CLEAR_FLAGS,
+        depth: f32,
+        stencil: u8,
+    ) {
+        unsafe {
+            self.0
+                .ClearDepthStencilView(dsv_descriptor, flags, depth, stencil, 0, ptr::null());
+        }
+    }
+
+    // Reference value of the stencil tests of the bound pipeline state.
+    pub fn set_stencil_ref(&self, value: u32) {
+        unsafe {
+            self.0.OMSetStencilRef(value);
+        }
+    }
+
+    // Constant RGBA used by the D3D12_BLEND_BLEND_FACTOR and D3D12_BLEND_INV_BLEND_FACTOR blends.
+    pub fn set_blend_factor(&self, factor: [f32; 4]) {
+        unsafe {
+            self.0.OMSetBlendFactor(&factor);
+        }
+    }
+
+    pub fn set_vertex_buffer(&self, slot: u32, view: d3d12::D3D12_VERTEX_BUFFER_VIEW) {
+        self.set_vertex_buffers(slot, &[view]);
+    }
+
+    pub fn set_vertex_buffers(&self, start_slot: u32, views: &[d3d12::D3D12_VERTEX_BUFFER_VIEW]) {
+        unsafe {
+            self.0
+                .IASetVertexBuffers(start_slot, views.len() as _, views.as_ptr());
+        }
+    }
+
+    pub fn set_index_buffer(&self, view: d3d12::D3D12_INDEX_BUFFER_VIEW) {
+        unsafe {
+            self.0.IASetIndexBuffer(&view);
+        }
+    }
+
+    pub fn set_primitive_topology(&self, topology: d3dcommon::D3D_PRIMITIVE_TOPOLOGY) {
+        unsafe {
+            self.0.IASetPrimitiveTopology(topology);
+        }
+    }
+
+    pub fn draw_instanced(
+        &self,
+        vertex_count: u32,
+        instance_count: u32,
+        start_vertex: u32,
+        start_instance: u32,
+    ) {
+        unsafe {
+            self.0
+                .DrawInstanced(vertex_count, instance_count, start_vertex, start_instance);
+        }
+    }
+
+    pub fn draw_indexed_instanced(
+        &self,
+        index_count: u32,
+        instance_count: u32,
+        start_index: u32,
+        base_vertex: i32,
+        start_instance: u32,
+    ) {
+        unsafe {
+            self.0.DrawIndexedInstanced(
+                index_count,
+                instance_count,
+                start_index,
+                base_vertex,
+                start_instance,
+            );
+        }
+    }
+
     pub fn reset(&self, command_allocator: &CommandAllocator) -> Result<(), Error> {
         let hr = unsafe {
             self.0
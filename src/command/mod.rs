@@ -2,8 +2,10 @@ mod allocator;
 mod context;
 mod list;
 mod queue;
+mod signature;
 
-pub use allocator::{CommandAllocator, CommandAllocatorPool};
+pub use allocator::{CommandAllocator, CommandAllocatorPool, Error as CommandAllocatorError};
 pub use context::{CommandContext, CommandContextPool};
-pub use list::{CommandList, CommandListType, GraphicsCommandList};
-pub use queue::CommandQueue;
+pub use list::{CommandList, CommandListType, Error as CommandListError, GraphicsCommandList};
+pub use queue::{CommandQueue, Error as CommandQueueError};
+pub use signature::{CommandSignature, CommandSignatureBuilder, Error as CommandSignatureError};
@@ -3,7 +3,7 @@ use crate::command::{
     CommandAllocator, CommandAllocatorPool, CommandList, CommandListType, GraphicsCommandList,
 };
 use crate::device::Device;
-use crate::sync::{Event, Fence};
+use crate::sync::GpuTimeline;
 
 use winapi::shared::winerror::{FAILED, SUCCEEDED};
 use winapi::um::d3d12;
@@ -20,12 +20,16 @@ pub enum Error {
     CommandQueueWaitFailed,
 }
 
+// ID3D12CommandQueue is free threaded, but the allocator pool and fence timeline kept alongside it
+// are not, so CommandQueue is neither Send nor Sync. Command lists recorded on other threads are
+// sent back to the thread owning the queue, which submits them with execute_command_lists() in
+// the order they must execute, signals the fence and frees their allocators with that value.
 pub struct CommandQueue {
     device: Device,
+    type_: CommandListType,
     pub(crate) native: ComPtr<d3d12::ID3D12CommandQueue>,
     command_allocator_pool: RefCell<CommandAllocatorPool>,
-    fence: Fence,
-    pub(crate) fence_value: u64,
+    timeline: GpuTimeline,
 }
 
 impl CommandQueue {
@@ -53,37 +57,65 @@ impl CommandQueue {
             return Err(Error::CommandQueueCreateFailed);
         }
 
-        /*#[cfg(debug_assertions)]
+        let queue = unsafe { ComPtr::from_ptr(queue) };
+
+        #[cfg(debug_assertions)]
         {
-            hr = unsafe { queue.SetName(debug_name.encode_utf16().collect::<Vec<u16>>().as_ptr()) };
+            hr = unsafe {
+                queue.SetName(
+                    debug_name
+                        .encode_utf16()
+                        .chain(Some(0))
+                        .collect::<Vec<u16>>()
+                        .as_ptr(),
+                )
+            };
             if FAILED(hr) {
                 return Err(Error::CommandQueueSetNameFailed);
             }
-        }*/
+        }
 
         Ok(CommandQueue {
             device: device.clone(),
-            native: unsafe { ComPtr::from_ptr(queue) },
+            type_,
+            native: queue,
             command_allocator_pool: RefCell::new(CommandAllocatorPool::new(device.clone(), type_)),
-            fence: Fence::new(device).unwrap(),
-            fence_value: 0,
+            timeline: GpuTimeline::new(device).unwrap(),
         })
     }
 
+    pub fn type_(&self) -> CommandListType {
+        self.type_
+    }
+
+    // Ticks per second of the timestamps written by command lists executed on this queue.
+    pub fn timestamp_frequency(&self) -> u64 {
+        let mut frequency = 0;
+        let hr = unsafe { self.native.GetTimestampFrequency(&mut frequency) };
+        if SUCCEEDED(hr) {
+            frequency
+        } else {
+            panic!("Failed to retrieve command queue timestamp frequency.");
+        }
+    }
+
     pub fn request_allocator(&self) -> CommandAllocator {
         self.command_allocator_pool
             .borrow_mut()
-            .request(self.fence.get_value())
+            .request(self.timeline.completed_ticket())
             .clone()
     }
 
-    pub fn free_allocator(&self, command_allocator: CommandAllocator) {
+    // `fence_value` is the value signaled after the last submission using the allocator.
+    pub fn free_allocator(&self, fence_value: u64, command_allocator: CommandAllocator) {
         self.command_allocator_pool
             .borrow_mut()
-            .free(self.fence.get_value(), command_allocator);
+            .free(fence_value, command_allocator);
     }
 
-    pub fn create_command_list(&mut self) -> (GraphicsCommandList, CommandAllocator) {
+    // Creates a direct command list and the allocator it records into, ready for recording on
+    // another thread.
+    pub fn create_command_list(&self) -> (GraphicsCommandList, CommandAllocator) {
         let command_allocator = self.request_allocator();
         let device = &self.device;
         (
@@ -113,37 +145,55 @@ impl CommandQueue {
         };
     }
 
-    pub fn signal_fence(&mut self) -> Result<(), Error> {
-        self.fence_value += 1;
-        let hr = unsafe { self.native.Signal(self.fence.0.as_ptr(), self.fence_value) };
+    // Signals the queue's timeline once the work submitted so far completes, and returns the
+    // signaled value, the ticket of that work.
+    pub fn signal_fence(&mut self) -> Result<u64, Error> {
+        let value = self.timeline.next_ticket();
+        let hr = unsafe { self.native.Signal(self.timeline.fence.0.as_ptr(), value) };
         if SUCCEEDED(hr) {
-            Ok(())
+            Ok(value)
         } else {
             Err(Error::CommandQueueSignalFailed)
         }
     }
 
+    pub fn last_signaled_value(&self) -> u64 {
+        self.timeline.last_ticket()
+    }
+
+    // Handle to the queue's fence values, for systems tracking the lifetime of their submissions.
+    pub fn timeline(&self) -> GpuTimeline {
+        self.timeline.clone()
+    }
+
     pub fn wait_for_fence(&self) -> Result<(), Error> {
-        if self.fence.get_value() < self.fence_value {
-            let event = Event::new();
-            self.fence
-                .wait(event, self.fence_value)
-                .and_then(|_| {
-                    event.close();
-                    Ok(())
-                })
-                .map_err(|_| Error::CommandQueueWaitFailed)
-        } else {
+        self.wait_for_value(self.timeline.last_ticket())
+    }
+
+    // Blocks the CPU until the GPU has reached `value` on this queue.
+    pub fn wait_for_value(&self, value: u64) -> Result<(), Error> {
+        self.timeline
+            .wait(value)
+            .map_err(|_| Error::CommandQueueWaitFailed)
+    }
+
+    // Makes this queue wait on the GPU until `other` has reached `value`, without
+    // blocking the CPU.
+    pub fn wait_for_queue(&self, other: &CommandQueue, value: u64) -> Result<(), Error> {
+        let hr = unsafe { self.native.Wait(other.timeline.fence.0.as_ptr(), value) };
+        if SUCCEEDED(hr) {
             Ok(())
+        } else {
+            Err(Error::CommandQueueWaitFailed)
         }
     }
 
     pub fn flush(&mut self) -> Result<(), Error> {
-        self.signal_fence().unwrap();
-        self.wait_for_fence()
+        let fence_value = self.signal_fence()?;
+        self.wait_for_value(fence_value)
     }
 
     pub fn is_fence_complete(&self, fence_value: u64) -> bool {
-        fence_value <= self.fence.get_value()
+        self.timeline.is_complete(fence_value)
     }
 }
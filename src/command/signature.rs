@@ -0,0 +1,195 @@
+use crate::com::ComPtr;
+use crate::device::Device;
+use crate::root_signature::RootSignature;
+
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::um::d3d12;
+use winapi::Interface;
+
+use std::{mem, ptr};
+
+#[derive(Debug)]
+pub enum Error {
+    CommandSignatureCreateFailed,
+    // Arguments changing root parameters need the root signature they refer to.
+    MissingRootSignature,
+}
+
+// Layout of the arguments read by GraphicsCommandList::execute_indirect. The argument changes
+// are applied in order before the draw or dispatch, which must come last.
+pub struct CommandSignature {
+    pub(crate) native: ComPtr<d3d12::ID3D12CommandSignature>,
+    byte_stride: u32,
+}
+
+// Command signatures are immutable once created.
+unsafe impl Send for CommandSignature {}
+unsafe impl Sync for CommandSignature {}
+
+impl CommandSignature {
+    // Size in bytes of the arguments of one command in the argument buffer.
+    pub fn byte_stride(&self) -> u32 {
+        self.byte_stride
+    }
+}
+
+#[derive(Default)]
+pub struct CommandSignatureBuilder {
+    arguments: Vec<d3d12::D3D12_INDIRECT_ARGUMENT_DESC>,
+    // Size of the arguments added so far, used as the stride unless overridden.
+    arguments_size: u32,
+    byte_stride: Option<u32>,
+    changes_root_arguments: bool,
+}
+
+impl CommandSignatureBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads a D3D12_DRAW_ARGUMENTS.
+    pub fn with_draw(self) -> Self {
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_DRAW,
+            unsafe { mem::zeroed() },
+            mem::size_of::<d3d12::D3D12_DRAW_ARGUMENTS>(),
+        )
+    }
+
+    // Reads a D3D12_DRAW_INDEXED_ARGUMENTS.
+    pub fn with_draw_indexed(self) -> Self {
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_DRAW_INDEXED,
+            unsafe { mem::zeroed() },
+            mem::size_of::<d3d12::D3D12_DRAW_INDEXED_ARGUMENTS>(),
+        )
+    }
+
+    // Reads a D3D12_DISPATCH_ARGUMENTS.
+    pub fn with_dispatch(self) -> Self {
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_DISPATCH,
+            unsafe { mem::zeroed() },
+            mem::size_of::<d3d12::D3D12_DISPATCH_ARGUMENTS>(),
+        )
+    }
+
+    // Reads a D3D12_VERTEX_BUFFER_VIEW bound to `slot`.
+    pub fn with_vertex_buffer_view(self, slot: u32) -> Self {
+        let mut u: d3d12::D3D12_INDIRECT_ARGUMENT_DESC_u = unsafe { mem::zeroed() };
+        *unsafe { u.VertexBuffer_mut() } =
+            d3d12::D3D12_INDIRECT_ARGUMENT_DESC_VertexBuffer { Slot: slot };
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_VERTEX_BUFFER_VIEW,
+            u,
+            mem::size_of::<d3d12::D3D12_VERTEX_BUFFER_VIEW>(),
+        )
+    }
+
+    // Reads a D3D12_INDEX_BUFFER_VIEW.
+    pub fn with_index_buffer_view(self) -> Self {
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_INDEX_BUFFER_VIEW,
+            unsafe { mem::zeroed() },
+            mem::size_of::<d3d12::D3D12_INDEX_BUFFER_VIEW>(),
+        )
+    }
+
+    // Reads `count` 32 bit values set as root constants, starting at `dest_offset` values.
+    pub fn with_root_constants(
+        mut self,
+        root_parameter_index: u32,
+        dest_offset: u32,
+        count: u32,
+    ) -> Self {
+        let mut u: d3d12::D3D12_INDIRECT_ARGUMENT_DESC_u = unsafe { mem::zeroed() };
+        *unsafe { u.Constant_mut() } = d3d12::D3D12_INDIRECT_ARGUMENT_DESC_Constant {
+            RootParameterIndex: root_parameter_index,
+            DestOffsetIn32BitValues: dest_offset,
+            Num32BitValuesToSet: count,
+        };
+        self.changes_root_arguments = true;
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_CONSTANT,
+            u,
+            count as usize * mem::size_of::<u32>(),
+        )
+    }
+
+    // Reads the GPU virtual address of a root constant buffer view.
+    pub fn with_constant_buffer_view(mut self, root_parameter_index: u32) -> Self {
+        let mut u: d3d12::D3D12_INDIRECT_ARGUMENT_DESC_u = unsafe { mem::zeroed() };
+        *unsafe { u.ConstantBufferView_mut() } =
+            d3d12::D3D12_INDIRECT_ARGUMENT_DESC_ConstantBufferView {
+                RootParameterIndex: root_parameter_index,
+            };
+        self.changes_root_arguments = true;
+        self.with_argument(
+            d3d12::D3D12_INDIRECT_ARGUMENT_TYPE_CONSTANT_BUFFER_VIEW,
+            u,
+            mem::size_of::<d3d12::D3D12_GPU_VIRTUAL_ADDRESS>(),
+        )
+    }
+
+    // Overrides the stride between commands, which defaults to the size of the arguments. Larger
+    // strides let the argument buffer hold extra per command data.
+    pub fn with_byte_stride(mut self, byte_stride: u32) -> Self {
+        self.byte_stride = Some(byte_stride);
+        self
+    }
+
+    fn with_argument(
+        mut self,
+        type_: d3d12::D3D12_INDIRECT_ARGUMENT_TYPE,
+        u: d3d12::D3D12_INDIRECT_ARGUMENT_DESC_u,
+        size: usize,
+    ) -> Self {
+        self.arguments
+            .push(d3d12::D3D12_INDIRECT_ARGUMENT_DESC { Type: type_, u });
+        self.arguments_size += size as u32;
+        self
+    }
+
+    // The root signature is required when root constants or views are changed, and the signature
+    // may then only be used with it.
+    pub fn build(
+        self,
+        device: &Device,
+        root_signature: Option<&RootSignature>,
+    ) -> Result<CommandSignature, Error> {
+        if self.changes_root_arguments && root_signature.is_none() {
+            return Err(Error::MissingRootSignature);
+        }
+
+        let byte_stride = self.byte_stride.unwrap_or(self.arguments_size);
+        let desc = d3d12::D3D12_COMMAND_SIGNATURE_DESC {
+            ByteStride: byte_stride,
+            NumArgumentDescs: self.arguments.len() as _,
+            pArgumentDescs: self.arguments.as_ptr(),
+            NodeMask: 0,
+        };
+        let mut signature: *mut d3d12::ID3D12CommandSignature = ptr::null_mut();
+        let hr = unsafe {
+            device.native.CreateCommandSignature(
+                &desc,
+                // Only allowed when root arguments change.
+                match root_signature {
+                    Some(root_signature) if self.changes_root_arguments => {
+                        root_signature.native.as_ptr()
+                    }
+                    _ => ptr::null_mut(),
+                },
+                &d3d12::ID3D12CommandSignature::uuidof(),
+                &mut signature as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(CommandSignature {
+                native: unsafe { ComPtr::from_ptr(signature) },
+                byte_stride,
+            })
+        } else {
+            Err(Error::CommandSignatureCreateFailed)
+        }
+    }
+}
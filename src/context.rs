@@ -1,16 +1,24 @@
+use crate::buffer::{self, ReadbackBuffer};
 use crate::com::ComPtr;
-use crate::command::{CommandAllocator, CommandListType, CommandQueue, GraphicsCommandList};
+use crate::command::{
+    CommandAllocator, CommandAllocatorError, CommandListError, CommandListType, CommandQueue,
+    CommandQueueError, GraphicsCommandList,
+};
 use crate::descriptor::{CpuDescriptor, CpuDescriptorPool};
-use crate::device::Device;
-use crate::dxgi::{Adapter, Factory, Swapchain, SwapchainProperties};
-use crate::resource::GpuResource;
+use crate::device::{self, Device};
+use crate::dxgi::{
+    self as dxgi_wrapper, Adapter, AdapterInfo, AlphaMode, ColorPrimaries, Factory, FrameStats,
+    MemorySegmentGroup, OutputInfo, SwapEffect, Swapchain, SwapchainProperties, VideoMemoryInfo,
+};
+use crate::resource::{DeferredDeleteQueue, GpuResource};
 
-use crate::{ContextFlags, ContextParams};
+use crate::{ContextFlags, ContextParams, DebugConfig};
 
 use log::{info, trace, warn};
 
 use winapi::shared::{
-    dxgi, dxgi1_3, dxgi1_5, dxgiformat, dxgitype, minwindef,
+    dxgi, dxgi1_2, dxgi1_3, dxgi1_5, dxgiformat, dxgitype, minwindef,
+    windef::{HWND, RECT},
     winerror::{self, FAILED, SUCCEEDED},
 };
 use winapi::um::{d3d12, d3d12sdklayers, d3dcommon, dxgidebug};
@@ -19,45 +27,163 @@ use winapi::Interface;
 use winit::platform::windows::WindowExtWindows;
 use winit::window::Window;
 
-use std::convert::TryInto;
 use std::mem::{self, ManuallyDrop};
 use std::ptr;
 
+#[derive(Debug)]
+pub enum ContextError {
+    Dxgi(crate::dxgi::Error),
+    Device(device::Error),
+    CommandQueue(CommandQueueError),
+    CommandAllocator(CommandAllocatorError),
+    CommandList(CommandListError),
+    Readback(buffer::Error),
+    UnsupportedMsaaSampleCount,
+    UnsupportedBackBufferFormat,
+    InvalidBackBufferCount,
+    InvalidWindowSize,
+    RenderTargetCreateFailed,
+    DepthStencilCreateFailed,
+    MsaaRenderTargetCreateFailed,
+    PresentFailed,
+    DeviceLost,
+    // Presented buffers of SwapEffect::FlipDiscard swapchains cannot be read back.
+    UnsupportedSwapEffect,
+}
+
+impl From<crate::dxgi::Error> for ContextError {
+    fn from(error: crate::dxgi::Error) -> Self {
+        ContextError::Dxgi(error)
+    }
+}
+
+impl From<device::Error> for ContextError {
+    fn from(error: device::Error) -> Self {
+        ContextError::Device(error)
+    }
+}
+
+impl From<CommandQueueError> for ContextError {
+    fn from(error: CommandQueueError) -> Self {
+        ContextError::CommandQueue(error)
+    }
+}
+
+impl From<CommandAllocatorError> for ContextError {
+    fn from(error: CommandAllocatorError) -> Self {
+        ContextError::CommandAllocator(error)
+    }
+}
+
+impl From<CommandListError> for ContextError {
+    fn from(error: CommandListError) -> Self {
+        ContextError::CommandList(error)
+    }
+}
+
+impl From<buffer::Error> for ContextError {
+    fn from(error: buffer::Error) -> Self {
+        ContextError::Readback(error)
+    }
+}
+
+// Parts of the depth stencil buffer cleared by Context::clear().
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClearFlags {
+    Depth = d3d12::D3D12_CLEAR_FLAG_DEPTH,
+    // Only valid with a depth buffer format that has a stencil component.
+    Stencil = d3d12::D3D12_CLEAR_FLAG_STENCIL,
+    DepthStencil = d3d12::D3D12_CLEAR_FLAG_DEPTH | d3d12::D3D12_CLEAR_FLAG_STENCIL,
+}
+
+// COM objects are released by ComPtr on drop; ManuallyDrop is only kept to control the
+// teardown order, so live objects can be reported before the device and factory go away.
 pub struct Context {
     factory: ManuallyDrop<Factory>,
+    adapter: ManuallyDrop<Adapter>,
     device: ManuallyDrop<Device>,
     command_queue: ManuallyDrop<CommandQueue>,
+    compute_queue: Option<CommandQueue>,
+    copy_queue: Option<CommandQueue>,
     command_allocators: ManuallyDrop<Vec<CommandAllocator>>,
+    // Fence value signaled after the last submission of each back buffer's frame.
+    frame_fence_values: Vec<u64>,
+    // Size dependent resources replaced on resize while frames using them may still be in flight.
+    deferred_deletes: DeferredDeleteQueue,
     command_list: ManuallyDrop<GraphicsCommandList>,
-    swapchain: ManuallyDrop<Swapchain>,
+    // None for headless contexts, which rotate through offscreen render targets instead.
+    swapchain: Option<Swapchain>,
     descriptor_allocator:
         ManuallyDrop<[CpuDescriptorPool; d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_NUM_TYPES as _]>,
-    render_targets: ManuallyDrop<Vec<GpuResource>>,
-    depth_stencil: ManuallyDrop<GpuResource>,
+    render_targets: Vec<GpuResource>,
+    // Only None while being recreated.
+    depth_stencil: Option<GpuResource>,
+    msaa_render_target: Option<GpuResource>,
     rtv_descriptors: Vec<CpuDescriptor>,
     dsv_descriptor: CpuDescriptor,
+    msaa_rtv_descriptor: Option<CpuDescriptor>,
     screen_viewport: d3d12::D3D12_VIEWPORT,
     scissor_rect: d3d12::D3D12_RECT,
+    clear_color: [f32; 4],
+    clear_depth: f32,
+    clear_stencil: u8,
+    clear_flags: ClearFlags,
+    present_interval: u32,
     color_space: dxgitype::DXGI_COLOR_SPACE_TYPE,
+    // Max and min mastering luminance in nits, max content and max frame average light levels.
+    hdr_metadata: (f32, f32, u16, u16),
     back_buffer_format: dxgiformat::DXGI_FORMAT,
     depth_buffer_format: dxgiformat::DXGI_FORMAT,
     back_buffer_count: u32,
     back_buffer_width: u32,
     back_buffer_height: u32,
     back_buffer_index: u32,
+    // Set while the window is minimized, presents are skipped until it is restored.
+    minimized: bool,
+    // Set when a present reports the window as hidden, presents are skipped until a test
+    // present succeeds.
+    occluded: bool,
+    msaa_sample_count: u32,
     flags: ContextFlags,
+    window_handle: HWND,
+    params: ContextParams,
+    is_device_lost: bool,
+    windowed_size: Option<(u32, u32)>,
 }
 
 impl Context {
-    pub fn new(window: &Window, params: &ContextParams) -> Self {
-        let window_handle = window.hwnd() as *mut _;
+    // Hardware adapters available for ContextParams::adapter_index.
+    pub fn enumerate_adapters() -> Result<Vec<AdapterInfo>, ContextError> {
+        let factory = Factory::new(ptr::null_mut(), 0)?;
+        Ok(factory.enumerate_adapters())
+    }
+
+    // Convenience constructor for winit windows.
+    pub fn new(window: &Window, params: &ContextParams) -> Result<Self, ContextError> {
+        Self::from_hwnd(window.hwnd() as *mut _, params)
+    }
+
+    // Renders to any Win32 window, independently of the windowing library that created it.
+    pub fn from_hwnd(window_handle: HWND, params: &ContextParams) -> Result<Self, ContextError> {
+        Self::create(Some(window_handle), params)
+    }
+
+    // Renders offscreen into `back_buffer_count` render targets of the window size, without any
+    // window or swapchain. Use read_back_current() to get the rendered pixels.
+    pub fn new_headless(params: &ContextParams) -> Result<Self, ContextError> {
+        Self::create(None, params)
+    }
 
+    fn create(window_handle: Option<HWND>, params: &ContextParams) -> Result<Self, ContextError> {
         // Enable debug layer.
-        let factory_flags = Self::enable_debug_layer();
+        let factory_flags = Self::enable_debug_layer(&params.debug);
 
-        // Create DXGI factory.
-        let factory = Factory::new(window_handle, factory_flags).unwrap();
-        factory.disable_exclusive_fullscreen().unwrap();
+        // Create DXGI factory, still needed to pick an adapter when headless.
+        let factory = Factory::new(window_handle.unwrap_or(ptr::null_mut()), factory_flags)?;
+        if window_handle.is_some() {
+            factory.disable_exclusive_fullscreen()?;
+        }
 
         // Determine if tearing is supported for fullscreen borderless windows.
         let mut flags = params.flags;
@@ -73,11 +199,36 @@ impl Context {
             }
         }
 
-        // Get adapter.
-        let adapter = Adapter::new(&factory, d3dcommon::D3D_FEATURE_LEVEL_11_0, false).unwrap();
+        // Get adapter, the software rasterizer is used straight away if requested.
+        let adapter = match params.adapter_index {
+            Some(index) if !flags.contains(ContextFlags::USE_WARP) => {
+                Adapter::from_index(&factory, index)?
+            }
+            _ => Adapter::new(
+                &factory,
+                params.min_feature_level,
+                flags.contains(ContextFlags::USE_WARP),
+            )?,
+        };
+
+        // Flip model swapchains only support a few formats, check before creating anything.
+        if window_handle.is_some() {
+            Self::validate_swapchain_format(params.back_buffer_format, flags)?;
+        }
 
         // Create D3D12 API device.
-        let device = Device::new(&adapter, d3dcommon::D3D_FEATURE_LEVEL_11_0).unwrap();
+        let device = Device::new(&adapter, params.min_feature_level, &params.debug)?;
+
+        // Make sure the requested multisampling is supported for the back buffer format.
+        if params.msaa_sample_count > 1
+            && !Self::is_msaa_supported(
+                &device,
+                params.back_buffer_format,
+                params.msaa_sample_count,
+            )
+        {
+            return Err(ContextError::UnsupportedMsaaSampleCount);
+        }
 
         // Create command queue.
         let command_queue = CommandQueue::new(
@@ -85,14 +236,61 @@ impl Context {
             CommandListType::Direct,
             d3d12::D3D12_COMMAND_QUEUE_FLAG_NONE,
             "Adamant::CommandQueue",
-        )
-        .unwrap();
+        )?;
+
+        // Create optional queues for async compute and copy workloads.
+        let compute_queue = if flags.contains(ContextFlags::ENABLE_COMPUTE_QUEUE) {
+            Some(CommandQueue::new(
+                &device,
+                CommandListType::Compute,
+                d3d12::D3D12_COMMAND_QUEUE_FLAG_NONE,
+                "Adamant::ComputeQueue",
+            )?)
+        } else {
+            None
+        };
+        let copy_queue = if flags.contains(ContextFlags::ENABLE_COPY_QUEUE) {
+            Some(CommandQueue::new(
+                &device,
+                CommandListType::Copy,
+                d3d12::D3D12_COMMAND_QUEUE_FLAG_NONE,
+                "Adamant::CopyQueue",
+            )?)
+        } else {
+            None
+        };
+
+        // Compute appropriate back buffer format.
+        let back_buffer_format = Self::no_srgb(params.back_buffer_format);
+
+        // Create swapchain.
+        let swapchain = match window_handle {
+            Some(window_handle) => Some(Swapchain::new(
+                &factory,
+                &command_queue,
+                SwapchainProperties {
+                    window_handle,
+                    back_buffer_count: params.back_buffer_count,
+                    back_buffer_width: params.window_width,
+                    back_buffer_height: params.window_height,
+                    back_buffer_format,
+                    swap_effect: params.swap_effect,
+                    alpha_mode: AlphaMode::Unspecified,
+                    is_tearing_supported: flags.contains(ContextFlags::ALLOW_TEARING),
+                    is_waitable: flags.contains(ContextFlags::WAITABLE_SWAPCHAIN),
+                },
+            )?),
+            None => None,
+        };
 
         // Create a command allocator for each render target that will be rendered to.
         let mut command_allocators = Vec::with_capacity(params.back_buffer_count as usize);
         for n in 0..params.back_buffer_count {
-            command_allocators
-                .push(CommandAllocator::new(&device, CommandListType::Direct, n as _).unwrap());
+            command_allocators.push(CommandAllocator::new(
+                &device,
+                CommandListType::Direct,
+                n as _,
+            )?);
         }
 
         // Create a command list for recording graphics commands.
@@ -101,38 +299,28 @@ impl Context {
             &command_allocators[0],
             CommandListType::Direct,
             "Adamant::CommandList",
-        )
-        .unwrap();
+        )?;
 
         // Start off in a closed state. This is because the first time we refer
         // to the command list we will Reset it, and it needs to be closed before
         // calling Reset.
-        command_list.close().unwrap();
-
-        // Compute appropriate back buffer format.
-        let back_buffer_format = Self::no_srgb(params.back_buffer_format);
-
-        // Create swapchain.
-        let swapchain = Swapchain::new(
-            &factory,
-            &command_queue,
-            SwapchainProperties {
-                window_handle,
-                back_buffer_count: params.back_buffer_count,
-                back_buffer_width: params.window_width,
-                back_buffer_height: params.window_height,
-                back_buffer_format,
-                is_tearing_supported: flags.contains(ContextFlags::ALLOW_TEARING),
-            },
-        )
-        .unwrap();
+        command_list.close()?;
 
         // Cache back buffer index.
-        let back_buffer_index = swapchain.get_current_back_buffer_index();
+        let back_buffer_index = swapchain
+            .as_ref()
+            .map_or(0, |swapchain| swapchain.get_current_back_buffer_index());
 
         // Handle HDR output.
-        let color_space = swapchain
-            .compute_color_space(back_buffer_format, flags.contains(ContextFlags::ENABLE_HDR));
+        let color_space = swapchain.as_ref().map_or(
+            dxgitype::DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+            |swapchain| {
+                swapchain.compute_color_space(
+                    back_buffer_format,
+                    flags.contains(ContextFlags::ENABLE_HDR),
+                )
+            },
+        );
 
         // Create cpu descriptor allocator.
         let mut descriptor_allocator = [
@@ -143,13 +331,24 @@ impl Context {
         ];
 
         // Create render targets for each bak buffer.
-        let (render_targets, rtv_descriptors) = Self::create_render_targets(
-            &device,
-            &swapchain,
-            &mut descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
-            back_buffer_format,
-            params.back_buffer_count,
-        );
+        let (render_targets, rtv_descriptors) = match swapchain {
+            Some(ref swapchain) => Self::create_render_targets(
+                &device,
+                swapchain,
+                &mut descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                // Views may use the sRGB variant of the swapchain format.
+                params.back_buffer_format,
+                params.back_buffer_count,
+            )?,
+            None => Self::create_offscreen_render_targets(
+                &device,
+                &mut descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                params.back_buffer_format,
+                params.back_buffer_count,
+                params.window_width,
+                params.window_height,
+            )?,
+        };
 
         // Allocate a 2-D surface as the depth/stencil buffer and create a depth/stencil view on this surface.
         let (depth_stencil, dsv_descriptor) = Self::create_depth_stencil(
@@ -158,7 +357,23 @@ impl Context {
             params.depth_buffer_format,
             params.window_width,
             params.window_height,
-        );
+            params.msaa_sample_count,
+        )?;
+
+        // Allocate a multisampled render target, resolved into the back buffer on present.
+        let (msaa_render_target, msaa_rtv_descriptor) = if params.msaa_sample_count > 1 {
+            let (render_target, rtv_descriptor) = Self::create_msaa_render_target(
+                &device,
+                &mut descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                params.back_buffer_format,
+                params.window_width,
+                params.window_height,
+                params.msaa_sample_count,
+            )?;
+            (Some(render_target), Some(rtv_descriptor))
+        } else {
+            (None, None)
+        };
 
         // Set rendering viewport and scissor rectangle to fit client window.
         let screen_viewport = d3d12::D3D12_VIEWPORT {
@@ -176,97 +391,318 @@ impl Context {
             bottom: params.window_height as _,
         };
 
-        Context {
+        let context = Context {
             factory: ManuallyDrop::new(factory),
+            adapter: ManuallyDrop::new(adapter),
             device: ManuallyDrop::new(device),
             command_queue: ManuallyDrop::new(command_queue),
+            compute_queue,
+            copy_queue,
             command_allocators: ManuallyDrop::new(command_allocators),
+            frame_fence_values: vec![0; params.back_buffer_count as usize],
+            deferred_deletes: DeferredDeleteQueue::new(),
             command_list: ManuallyDrop::new(command_list),
-            swapchain: ManuallyDrop::new(swapchain),
+            swapchain,
             descriptor_allocator: ManuallyDrop::new(descriptor_allocator),
-            render_targets: ManuallyDrop::new(render_targets),
-            depth_stencil: ManuallyDrop::new(depth_stencil),
+            render_targets,
+            depth_stencil: Some(depth_stencil),
+            msaa_render_target,
             rtv_descriptors,
             dsv_descriptor,
+            msaa_rtv_descriptor,
             screen_viewport,
             scissor_rect,
+            clear_color: [0.392, 0.584, 0.929, 1.0],
+            clear_depth: 1.0,
+            clear_stencil: 0,
+            clear_flags: ClearFlags::Depth,
+            present_interval: 1,
             color_space,
+            // Typical HDR10 mastering display, the Windows defaults when nothing is set.
+            hdr_metadata: (1000.0, 0.001, 2000, 500),
             back_buffer_format: params.back_buffer_format,
             depth_buffer_format: params.depth_buffer_format,
             back_buffer_count: params.back_buffer_count,
             back_buffer_width: params.window_width,
             back_buffer_height: params.window_height,
             back_buffer_index,
+            minimized: false,
+            occluded: false,
+            msaa_sample_count: params.msaa_sample_count,
             flags,
+            window_handle: window_handle.unwrap_or(ptr::null_mut()),
+            params: params.clone(),
+            is_device_lost: false,
+            windowed_size: None,
+        };
+        context.update_hdr_metadata();
+        Ok(context)
+    }
+
+    // Blocks until the swapchain is ready for a new frame, call it before prepare(). Does nothing
+    // unless the context was created with ContextFlags::WAITABLE_SWAPCHAIN.
+    pub fn wait_for_swapchain(&self) {
+        if let Some(ref swapchain) = self.swapchain {
+            swapchain.wait_for_frame_latency();
         }
     }
 
-    pub fn prepare(&self) {
+    pub fn prepare(&self) -> Result<(), ContextError> {
+        self.check_device()?;
         let current_index = self.back_buffer_index as usize;
         unsafe {
-            self.command_allocators[current_index].reset().unwrap();
+            self.command_allocators[current_index].reset()?;
             self.command_list
-                .reset(&self.command_allocators[current_index])
-                .unwrap();
+                .reset(&self.command_allocators[current_index])?;
 
             // Transition the render target into the correct state to allow for drawing into it.
-            let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
-                Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                u: mem::zeroed(),
-            };
-            *barrier.u.Transition_mut() = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
-                pResource: self.render_targets[current_index].native.as_ptr(),
-                Subresource: d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                StateBefore: d3d12::D3D12_RESOURCE_STATE_PRESENT,
-                StateAfter: d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
+            let barrier = match self.msaa_render_target {
+                Some(ref msaa_render_target) => Self::transition_barrier(
+                    msaa_render_target,
+                    d3d12::D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+                    d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
+                ),
+                None => Self::transition_barrier(
+                    &self.render_targets[current_index],
+                    d3d12::D3D12_RESOURCE_STATE_PRESENT,
+                    d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
+                ),
             };
             self.command_list.0.ResourceBarrier(1, &barrier);
         }
+        Ok(())
     }
 
     pub fn clear(&self) {
-        unsafe {
-            let rtv_descriptor = self.rtv_descriptors[self.back_buffer_index as usize];
-            self.command_list.0.OMSetRenderTargets(
-                1,
-                &rtv_descriptor,
-                minwindef::FALSE,
-                &self.dsv_descriptor,
-            );
-            let clear_color = [0.392, 0.584, 0.929, 1.0];
-            self.command_list
-                .0
-                .ClearRenderTargetView(rtv_descriptor, &clear_color, 0, ptr::null());
-            self.command_list.0.ClearDepthStencilView(
-                self.dsv_descriptor,
-                d3d12::D3D12_CLEAR_FLAG_DEPTH,
-                1.0,
-                0,
-                0,
-                ptr::null(),
-            );
-            self.command_list.0.RSSetViewports(1, &self.screen_viewport);
-            self.command_list.0.RSSetScissorRects(1, &self.scissor_rect);
+        self.assert_device();
+        let rtv_descriptor = self
+            .msaa_rtv_descriptor
+            .unwrap_or(self.rtv_descriptors[self.back_buffer_index as usize]);
+        self.command_list
+            .set_render_targets(&[rtv_descriptor], Some(self.dsv_descriptor));
+        self.command_list
+            .clear_render_target(rtv_descriptor, self.clear_color);
+        self.command_list.clear_depth_stencil(
+            self.dsv_descriptor,
+            self.clear_flags as _,
+            self.clear_depth,
+            self.clear_stencil,
+        );
+        self.command_list.set_viewports(&[self.screen_viewport]);
+        self.command_list.set_scissor_rects(&[self.scissor_rect]);
+    }
+
+    // Binds render targets for the current frame, for instance the G-buffer of a deferred pass.
+    // The depth stencil target is optional.
+    pub fn set_render_targets(
+        &self,
+        rtv_descriptors: &[CpuDescriptor],
+        dsv_descriptor: Option<CpuDescriptor>,
+    ) {
+        self.assert_device();
+        self.command_list
+            .set_render_targets(rtv_descriptors, dsv_descriptor);
+    }
+
+    // Command list of the current frame, valid between prepare() and present(). It has already
+    // been reset and the frame's render target transitioned into the render target state.
+    pub fn command_list(&self) -> &GraphicsCommandList {
+        self.assert_device();
+        &self.command_list
+    }
+
+    // Device the context renders with, to create pipelines, buffers and other resources.
+    pub fn device(&self) -> &Device {
+        self.assert_device();
+        &self.device
+    }
+
+    // Maximum feature level supported by the device.
+    pub fn feature_level(&self) -> d3dcommon::D3D_FEATURE_LEVEL {
+        self.assert_device();
+        self.device.feature_level()
+    }
+
+    pub fn command_queue(&self) -> &CommandQueue {
+        self.assert_device();
+        &self.command_queue
+    }
+
+    pub fn compute_queue(&self) -> Option<&CommandQueue> {
+        self.compute_queue.as_ref()
+    }
+
+    pub fn copy_queue(&self) -> Option<&CommandQueue> {
+        self.copy_queue.as_ref()
+    }
+
+    pub fn set_clear_color(&mut self, rgba: [f32; 4]) {
+        self.clear_color = rgba;
+    }
+
+    pub fn set_depth_clear(&mut self, depth: f32, stencil: u8) {
+        self.clear_depth = depth;
+        self.clear_stencil = stencil;
+    }
+
+    // Only the depth is cleared by default.
+    pub fn set_clear_flags(&mut self, flags: ClearFlags) {
+        assert!(
+            flags == ClearFlags::Depth
+                || self.depth_buffer_format == dxgiformat::DXGI_FORMAT_D24_UNORM_S8_UINT
+                || self.depth_buffer_format == dxgiformat::DXGI_FORMAT_D32_FLOAT_S8X24_UINT,
+            "the depth buffer format has no stencil to clear"
+        );
+        self.clear_flags = flags;
+    }
+
+    // Number of vertical blanks to wait for before presenting, 0 presents immediately.
+    pub fn set_present_interval(&mut self, interval: u32) {
+        assert!(interval <= 4, "present interval must be in the range 0..=4");
+        self.present_interval = interval;
+    }
+
+    // Describes the HDR content to the display so it tone maps it correctly, luminances are in
+    // nits. Only sent to the display while the swapchain outputs an HDR color space.
+    pub fn set_hdr_metadata(
+        &mut self,
+        max_nits: f32,
+        min_nits: f32,
+        max_content_light_level: u16,
+        max_frame_average_light_level: u16,
+    ) {
+        self.hdr_metadata = (
+            max_nits,
+            min_nits,
+            max_content_light_level,
+            max_frame_average_light_level,
+        );
+        self.update_hdr_metadata();
+    }
+
+    // Sends the HDR metadata matching the current color space to the display, or clears it for
+    // SDR output.
+    fn update_hdr_metadata(&self) {
+        let swapchain = match self.swapchain {
+            Some(ref swapchain) => swapchain,
+            None => return,
+        };
+        let primaries = match self.color_space {
+            dxgitype::DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020 => Some(ColorPrimaries::REC2020),
+            dxgitype::DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709 => Some(ColorPrimaries::REC709),
+            _ => None,
+        };
+        let result = match primaries {
+            Some(primaries) => {
+                let (max_nits, min_nits, max_cll, max_fall) = self.hdr_metadata;
+                swapchain.set_hdr_metadata(max_nits, min_nits, max_cll, max_fall, &primaries)
+            }
+            None => swapchain.clear_hdr_metadata(),
+        };
+        if let Err(err) = result {
+            warn!("Failed to set swapchain HDR metadata: {:?}", err);
         }
     }
 
-    pub fn present(&mut self) {
+    // Restricts rendering to a region of the render target, in pixels. Resizing the window resets
+    // the viewport and scissor rectangle to the whole window.
+    pub fn set_viewport(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        min_depth: f32,
+        max_depth: f32,
+    ) {
+        self.screen_viewport = d3d12::D3D12_VIEWPORT {
+            TopLeftX: x,
+            TopLeftY: y,
+            Width: width,
+            Height: height,
+            MinDepth: min_depth,
+            MaxDepth: max_depth,
+        };
+    }
+
+    pub fn set_scissor(&mut self, left: i32, top: i32, right: i32, bottom: i32) {
+        self.scissor_rect = d3d12::D3D12_RECT {
+            left,
+            top,
+            right,
+            bottom,
+        };
+    }
+
+    pub fn present(&mut self) -> Result<(), ContextError> {
+        self.present_with_dirty_rects(&[])
+    }
+
+    // Presents the frame telling the compositor that only `rects` changed since the previous one,
+    // an empty slice marks the whole back buffer as dirty. With SwapEffect::FlipDiscard the back
+    // buffer content is not preserved across frames, so the whole frame must still be rendered
+    // and the rectangles only let the compositor update less of the screen. Only
+    // SwapEffect::FlipSequential allows rendering just the changed areas, see its constraints.
+    // Fails when presenting fails for another reason than device removal, or when the device
+    // cannot be recreated after it.
+    pub fn present_with_dirty_rects(&mut self, rects: &[RECT]) -> Result<(), ContextError> {
+        self.check_device()?;
+
+        // Nothing is shown while minimized or hidden, the recorded commands are dropped and the
+        // frame recorded again into the same back buffer once visible.
+        if self.minimized || self.test_occlusion() {
+            self.command_list.close()?;
+            return Ok(());
+        }
+
         let current_index = self.back_buffer_index as usize;
         unsafe {
-            // Transition the render target to the state that allows it to be presented to the display.
-            let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
-                Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                u: mem::zeroed(),
-            };
-            *barrier.u.Transition_mut() = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
-                pResource: self.render_targets[current_index].native.as_ptr(),
-                Subresource: d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                StateBefore: d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
-                StateAfter: d3d12::D3D12_RESOURCE_STATE_PRESENT,
-            };
-            self.command_list.0.ResourceBarrier(1, &barrier);
+            let render_target = &self.render_targets[current_index];
+            match self.msaa_render_target {
+                Some(ref msaa_render_target) => {
+                    // Resolve the multisampled render target into the back buffer.
+                    let barriers = [
+                        Self::transition_barrier(
+                            msaa_render_target,
+                            d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
+                            d3d12::D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+                        ),
+                        Self::transition_barrier(
+                            render_target,
+                            d3d12::D3D12_RESOURCE_STATE_PRESENT,
+                            d3d12::D3D12_RESOURCE_STATE_RESOLVE_DEST,
+                        ),
+                    ];
+                    self.command_list
+                        .0
+                        .ResourceBarrier(barriers.len() as _, barriers.as_ptr());
+                    self.command_list.resolve_subresource(
+                        render_target,
+                        0,
+                        msaa_render_target,
+                        0,
+                        // Same format as the views, so sRGB targets are resolved in linear space.
+                        self.back_buffer_format,
+                    );
+
+                    // Transition the back buffer to the state that allows it to be presented to the display.
+                    let barrier = Self::transition_barrier(
+                        render_target,
+                        d3d12::D3D12_RESOURCE_STATE_RESOLVE_DEST,
+                        d3d12::D3D12_RESOURCE_STATE_PRESENT,
+                    );
+                    self.command_list.0.ResourceBarrier(1, &barrier);
+                }
+                None => {
+                    // Transition the render target to the state that allows it to be presented to the display.
+                    let barrier = Self::transition_barrier(
+                        render_target,
+                        d3d12::D3D12_RESOURCE_STATE_RENDER_TARGET,
+                        d3d12::D3D12_RESOURCE_STATE_PRESENT,
+                    );
+                    self.command_list.0.ResourceBarrier(1, &barrier);
+                }
+            }
 
             // Send the command list off to the GPU for processing.
             self.command_list.close().unwrap();
@@ -276,115 +712,245 @@ impl Context {
                 .native
                 .ExecuteCommandLists(command_lists.len() as _, command_lists.as_ptr());
 
-            let hr = if self.flags.contains(ContextFlags::ALLOW_TEARING) {
-                // Recommended to always use tearing if supported when using a sync interval of 0.
-                // Note this will fail if in true 'fullscreen' mode.
-                self.swapchain
-                    .0
-                    .Present(0, dxgi::DXGI_PRESENT_ALLOW_TEARING)
-            } else {
-                // The first argument instructs DXGI to block until VSync, putting the application
-                // to sleep until the next VSync. This ensures we don't waste any cycles rendering
-                // frames that will never be displayed to the screen.
-                self.swapchain.0.Present(1, 0)
+            let hr = match self.swapchain {
+                Some(ref swapchain) => {
+                    // Scrolling is not supported by flip discard swapchains, only dirty rectangles.
+                    let parameters = dxgi1_2::DXGI_PRESENT_PARAMETERS {
+                        DirtyRectsCount: rects.len() as _,
+                        pDirtyRects: if rects.is_empty() {
+                            ptr::null_mut()
+                        } else {
+                            rects.as_ptr() as *mut _
+                        },
+                        pScrollRect: ptr::null_mut(),
+                        pScrollOffset: ptr::null_mut(),
+                    };
+                    if self.present_interval == 0
+                        && self.flags.contains(ContextFlags::ALLOW_TEARING)
+                        && !swapchain.is_fullscreen()
+                    {
+                        // Recommended to always use tearing if supported when using a sync interval of 0.
+                        // Note this will fail if in true 'fullscreen' mode.
+                        swapchain
+                            .0
+                            .Present1(0, dxgi::DXGI_PRESENT_ALLOW_TEARING, &parameters)
+                    } else {
+                        // The first argument instructs DXGI to block until VSync, putting the application
+                        // to sleep until the next VSync. This ensures we don't waste any cycles rendering
+                        // frames that will never be displayed to the screen.
+                        swapchain.0.Present1(self.present_interval, 0, &parameters)
+                    }
+                }
+                // Nothing to present, the render targets are just rotated.
+                None => winerror::S_OK,
             };
 
-            // If the device was reset we must completely reinitialize the renderer.
             if SUCCEEDED(hr) {
-                // Cache next back buffer index from swapchain.
-                self.back_buffer_index = self.swapchain.get_current_back_buffer_index();
-                // Wait until frame commands are complete. This waiting is inefficient and is
-                // done for simplicity for now. Organize rendering code so it does not have to wait per frame.
-                self.command_queue.flush().unwrap();
+                // A success code, but nothing will be visible until the window is shown again.
+                if hr == winerror::DXGI_STATUS_OCCLUDED {
+                    self.occluded = true;
+                }
+                self.move_to_next_frame()?;
+
+                #[cfg(debug_assertions)]
+                self.log_debug_messages();
             } else if hr == winerror::DXGI_ERROR_DEVICE_REMOVED
                 || hr == winerror::DXGI_ERROR_DEVICE_RESET
             {
-                panic!(
+                // If the device was reset we must completely reinitialize the renderer.
+                warn!(
                     "Device lost on Present() function call. Reason code: {}",
                     if hr == winerror::DXGI_ERROR_DEVICE_REMOVED {
-                        self.device.native.GetDeviceRemovedReason()
+                        self.device_removed_reason()
                     } else {
                         hr
                     }
                 );
+                self.handle_device_lost()?;
             } else if FAILED(hr) {
-                panic!("Failed to present");
+                return Err(ContextError::PresentFailed);
             }
         }
+        Ok(())
     }
 
-    pub fn on_window_resized(&mut self, width: u32, height: u32) {
-        if self.back_buffer_width != width && self.back_buffer_height != height {
-            self.back_buffer_width = u32::max(width, 1);
-            self.back_buffer_height = u32::max(height, 1);
+    // Lets the CPU record the next frame while the GPU still works on the previous ones, only
+    // waiting when the next back buffer's last frame is still in flight.
+    fn move_to_next_frame(&mut self) -> Result<(), ContextError> {
+        self.frame_fence_values[self.back_buffer_index as usize] =
+            self.command_queue.signal_fence()?;
 
-            // Wait until all previous GPU work is complete.
-            self.command_queue.flush().unwrap();
+        // Cache next back buffer index from swapchain.
+        self.back_buffer_index = match self.swapchain {
+            Some(ref swapchain) => swapchain.get_current_back_buffer_index(),
+            None => (self.back_buffer_index + 1) % self.back_buffer_count,
+        };
 
-            // Release resources that are tied to the swap chain and update fence values.
-            unsafe {
-                ManuallyDrop::drop(&mut self.render_targets);
-                ManuallyDrop::drop(&mut self.depth_stencil);
-            }
+        self.command_queue
+            .wait_for_value(self.frame_fence_values[self.back_buffer_index as usize])?;
+
+        self.deferred_deletes
+            .collect(self.command_queue.timeline().completed_ticket());
+        Ok(())
+    }
+
+    // Forwards the validation messages reported by the debug layer during the frame to the log.
+    #[cfg(debug_assertions)]
+    fn log_debug_messages(&self) {
+        for message in self.device.drain_debug_messages() {
+            warn!(
+                "D3D12 debug layer message {} (severity {}, category {}): {}",
+                message.id, message.severity, message.category, message.description
+            );
+        }
+    }
+
+    // A zero size, reported while the window is minimized, keeps the current resources and marks
+    // the context as occluded until a new size is reported.
+    // Fails when the resources cannot be recreated at the new size, or when the device cannot be
+    // recreated after being lost while resizing.
+    pub fn on_window_resized(&mut self, width: u32, height: u32) -> Result<(), ContextError> {
+        self.check_device()?;
+        if width == 0 || height == 0 {
+            self.minimized = true;
+            return Ok(());
+        }
+        self.minimized = false;
+
+        if self.back_buffer_width != width || self.back_buffer_height != height {
+            self.back_buffer_width = width;
+            self.back_buffer_height = height;
+
+            // Frames still in flight keep using the old resources until they complete.
+            let fence_value = self.command_queue.last_signaled_value();
 
             // Resize swap chain.
-            unsafe {
-                let hr = self.swapchain.0.ResizeBuffers(
+            if let Some(ref swapchain) = self.swapchain {
+                // DXGI requires the back buffers to be idle and released before resizing them.
+                self.command_queue.flush()?;
+                self.render_targets.clear();
+
+                match swapchain.resize(
                     self.back_buffer_count,
-                    self.back_buffer_width.try_into().unwrap(),
-                    self.back_buffer_height.try_into().unwrap(),
-                    self.back_buffer_format,
-                    if self.flags.contains(ContextFlags::ALLOW_TEARING) {
-                        dxgi::DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING
-                    } else {
-                        0
-                    },
-                );
-                if hr == winerror::DXGI_ERROR_DEVICE_REMOVED
-                    || hr == winerror::DXGI_ERROR_DEVICE_RESET
-                {
-                    panic!(
-                        "Device lost on ResizeBuffers() function call. Reason code: {}",
-                        if hr == winerror::DXGI_ERROR_DEVICE_REMOVED {
-                            self.device.native.GetDeviceRemovedReason()
-                        } else {
-                            hr
-                        }
-                    );
-                } else if FAILED(hr) {
-                    panic!("Failed to resize resources on window size changed.");
+                    self.back_buffer_width,
+                    self.back_buffer_height,
+                    Self::no_srgb(self.back_buffer_format),
+                    self.flags.contains(ContextFlags::ALLOW_TEARING),
+                    self.flags.contains(ContextFlags::WAITABLE_SWAPCHAIN),
+                ) {
+                    Ok(()) => {}
+                    Err(dxgi_wrapper::Error::DeviceLost) => {
+                        // Everything is recreated at the new size, no need to go any further.
+                        warn!(
+                            "Device lost on ResizeBuffers() function call. Reason code: {}",
+                            self.device_removed_reason()
+                        );
+                        return self.handle_device_lost();
+                    }
+                    Err(error) => return Err(error.into()),
                 }
             }
 
-            self.back_buffer_index = self.swapchain.get_current_back_buffer_index();
+            // Release the remaining size dependent resources once no frame uses them anymore.
+            for render_target in self.render_targets.drain(..) {
+                self.deferred_deletes.push(render_target, fence_value);
+            }
+            if let Some(depth_stencil) = self.depth_stencil.take() {
+                self.deferred_deletes.push(depth_stencil, fence_value);
+            }
+            if let Some(msaa_render_target) = self.msaa_render_target.take() {
+                self.deferred_deletes.push(msaa_render_target, fence_value);
+            }
 
-            // Handle HDR output
-            self.color_space = self.swapchain.compute_color_space(
-                self.back_buffer_format,
-                self.flags.contains(ContextFlags::ENABLE_HDR),
-            );
+            // Return the views of the released resources to the descriptor pools.
+            for rtv_descriptor in self.rtv_descriptors.drain(..) {
+                self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize]
+                    .free(rtv_descriptor, 1);
+            }
+            self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_DSV as usize]
+                .free(self.dsv_descriptor, 1);
+            if let Some(msaa_rtv_descriptor) = self.msaa_rtv_descriptor.take() {
+                self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize]
+                    .free(msaa_rtv_descriptor, 1);
+            }
 
             // Create render targets for each back buffer.
-            let (render_targets, rtv_descriptors) = Self::create_render_targets(
-                &self.device,
-                &self.swapchain,
-                &mut self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
-                self.back_buffer_format,
-                self.back_buffer_count,
-            );
-            self.render_targets = ManuallyDrop::new(render_targets);
+            let render_targets = match self.swapchain {
+                Some(ref swapchain) => {
+                    self.back_buffer_index = swapchain.get_current_back_buffer_index();
+
+                    // Handle HDR output
+                    self.color_space = swapchain.compute_color_space(
+                        Self::no_srgb(self.back_buffer_format),
+                        self.flags.contains(ContextFlags::ENABLE_HDR),
+                    );
+
+                    Self::create_render_targets(
+                        &self.device,
+                        swapchain,
+                        &mut self.descriptor_allocator
+                            [d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                        self.back_buffer_format,
+                        self.back_buffer_count,
+                    )
+                }
+                None => {
+                    // Restarting from the first buffer, its allocator may still be in flight.
+                    self.back_buffer_index = 0;
+                    self.command_queue
+                        .wait_for_value(self.frame_fence_values[0])?;
+                    Ok(Self::create_offscreen_render_targets(
+                        &self.device,
+                        &mut self.descriptor_allocator
+                            [d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                        self.back_buffer_format,
+                        self.back_buffer_count,
+                        self.back_buffer_width,
+                        self.back_buffer_height,
+                    )?)
+                }
+            };
+            let (render_targets, rtv_descriptors) = match render_targets {
+                Ok(render_targets) => render_targets,
+                Err(error) => {
+                    // The swapchain is unusable without its buffers, recreate everything.
+                    warn!(
+                        "Failed to get the resized swapchain buffers ({:?}), recreating the device.",
+                        error
+                    );
+                    return self.handle_device_lost();
+                }
+            };
+            self.render_targets = render_targets;
             self.rtv_descriptors = rtv_descriptors;
 
+            // The color space may have changed when moving to another output.
+            self.update_hdr_metadata();
+
             let (depth_stencil, dsv_descriptor) = Self::create_depth_stencil(
                 &self.device,
                 &mut self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_DSV as usize],
                 self.depth_buffer_format,
                 self.back_buffer_width,
                 self.back_buffer_height,
-            );
-            self.depth_stencil = ManuallyDrop::new(depth_stencil);
+                self.msaa_sample_count,
+            )?;
+            self.depth_stencil = Some(depth_stencil);
             self.dsv_descriptor = dsv_descriptor;
 
+            if self.msaa_sample_count > 1 {
+                let (msaa_render_target, msaa_rtv_descriptor) = Self::create_msaa_render_target(
+                    &self.device,
+                    &mut self.descriptor_allocator[d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_RTV as usize],
+                    self.back_buffer_format,
+                    self.back_buffer_width,
+                    self.back_buffer_height,
+                    self.msaa_sample_count,
+                )?;
+                self.msaa_render_target = Some(msaa_render_target);
+                self.msaa_rtv_descriptor = Some(msaa_rtv_descriptor);
+            }
+
             // Set rendering viewport and scissor rectangle to fit client window.
             self.screen_viewport = d3d12::D3D12_VIEWPORT {
                 TopLeftX: 0.0,
@@ -401,9 +967,250 @@ impl Context {
                 bottom: self.back_buffer_height as _,
             };
         }
+        Ok(())
     }
 
-    fn enable_debug_layer() -> u32 {
+    // Switches between windowed and borderless fullscreen, resizing the swapchain to the
+    // output's desktop resolution and back. The window itself is expected to be made borderless
+    // and resized by the application.
+    pub fn toggle_fullscreen(&mut self) -> Result<(), ContextError> {
+        match self.windowed_size.take() {
+            Some((width, height)) => self.on_window_resized(width, height),
+            None => {
+                if let Some((width, height)) = self
+                    .swapchain
+                    .as_ref()
+                    .and_then(|swapchain| swapchain.get_output_desktop_size())
+                {
+                    self.windowed_size = Some((self.back_buffer_width, self.back_buffer_height));
+                    self.on_window_resized(width, height)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.windowed_size.is_some()
+    }
+
+    // True while the window is minimized or hidden. Presents are skipped then, so rendering can be
+    // skipped as well, calling test_occlusion() to find out when to resume.
+    pub fn is_occluded(&self) -> bool {
+        self.minimized || self.occluded
+    }
+
+    // Checks whether a hidden window can be presented to again, without presenting anything, and
+    // returns whether the context is still occluded.
+    pub fn test_occlusion(&mut self) -> bool {
+        if self.occluded {
+            self.occluded = match self.swapchain {
+                Some(ref swapchain) => {
+                    let hr = unsafe { swapchain.0.Present(0, dxgi::DXGI_PRESENT_TEST) };
+                    hr == winerror::DXGI_STATUS_OCCLUDED
+                }
+                None => false,
+            };
+        }
+        self.is_occluded()
+    }
+
+    // Statistics of the last presents, None for headless contexts or when the swapchain cannot
+    // report them yet.
+    pub fn frame_statistics(&self) -> Option<FrameStats> {
+        self.swapchain
+            .as_ref()
+            .and_then(|swapchain| swapchain.get_frame_statistics())
+    }
+
+    // Video memory budget and usage of the adapter in use.
+    pub fn video_memory_info(&self) -> VideoMemoryInfo {
+        self.assert_device();
+        self.adapter.query_video_memory_info()
+    }
+
+    pub fn set_video_memory_reservation(
+        &self,
+        group: MemorySegmentGroup,
+        bytes: u64,
+    ) -> Result<(), ContextError> {
+        self.check_device()?;
+        self.adapter
+            .set_video_memory_reservation(group, bytes)
+            .map_err(ContextError::Dxgi)
+    }
+
+    // Outputs (monitors) connected to the adapter in use, with their supported display modes.
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.assert_device();
+        crate::dxgi::enumerate_outputs(&self.adapter, Self::no_srgb(self.back_buffer_format))
+    }
+
+    // Copies the most recently presented render target back to the CPU. Rows are tightly packed,
+    // in the back buffer format. Blocks until the copy has completed on the GPU.
+    // Presenting leaves the buffer content undefined with SwapEffect::FlipDiscard, so reading back
+    // fails with ContextError::UnsupportedSwapEffect for those swapchains. Headless contexts and
+    // SwapEffect::FlipSequential swapchains keep the content.
+    pub fn read_back_current(&mut self) -> Result<Vec<u8>, ContextError> {
+        self.check_device()?;
+        if self.swapchain.is_some() && self.params.swap_effect == SwapEffect::FlipDiscard {
+            return Err(ContextError::UnsupportedSwapEffect);
+        }
+
+        let index = ((self.back_buffer_index + self.back_buffer_count - 1) % self.back_buffer_count)
+            as usize;
+        let render_target = &self.render_targets[index];
+
+        let desc = unsafe { render_target.native.GetDesc() };
+        let (footprints, row_counts, row_sizes, total_size) =
+            self.device.copyable_footprints(&desc, 0, 1, 0);
+        let (footprint, row_count, row_size) = (footprints[0], row_counts[0], row_sizes[0]);
+
+        let readback = ReadbackBuffer::create(&self.device, total_size)?;
+
+        // Present waited for the last frame using this allocator, so it can be reused.
+        let command_allocator = &self.command_allocators[self.back_buffer_index as usize];
+        command_allocator.reset()?;
+        self.command_list.reset(command_allocator)?;
+        unsafe {
+            let barrier = Self::transition_barrier(
+                render_target,
+                d3d12::D3D12_RESOURCE_STATE_PRESENT,
+                d3d12::D3D12_RESOURCE_STATE_COPY_SOURCE,
+            );
+            self.command_list.0.ResourceBarrier(1, &barrier);
+
+            let mut dest = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: readback.resource.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                u: mem::zeroed(),
+            };
+            *dest.u.PlacedFootprint_mut() = footprint;
+            let mut source = d3d12::D3D12_TEXTURE_COPY_LOCATION {
+                pResource: render_target.native.as_ptr(),
+                Type: d3d12::D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+                u: mem::zeroed(),
+            };
+            *source.u.SubresourceIndex_mut() = 0;
+            self.command_list
+                .0
+                .CopyTextureRegion(&dest, 0, 0, 0, &source, ptr::null());
+
+            let barrier = Self::transition_barrier(
+                render_target,
+                d3d12::D3D12_RESOURCE_STATE_COPY_SOURCE,
+                d3d12::D3D12_RESOURCE_STATE_PRESENT,
+            );
+            self.command_list.0.ResourceBarrier(1, &barrier);
+        }
+        self.command_list.close()?;
+        self.command_queue
+            .execute_command_list(self.command_list.as_command_list());
+        self.command_queue.flush()?;
+
+        // Strip the row padding required by the placed footprint.
+        let row_size = row_size as usize;
+        let row_pitch = footprint.Footprint.RowPitch as usize;
+        let data = readback.read()?;
+        let mut pixels = Vec::with_capacity(row_size * row_count as usize);
+        for row in 0..row_count as usize {
+            let start = footprint.Offset as usize + row * row_pitch;
+            pixels.extend_from_slice(&data[start..start + row_size]);
+        }
+        Ok(pixels)
+    }
+
+    pub fn device_removed_reason(&self) -> i32 {
+        self.assert_device();
+        unsafe { self.device.native.GetDeviceRemovedReason() }
+    }
+
+    // True after the device was lost and could not be recreated yet. Every call using the device
+    // fails with ContextError::DeviceLost, or panics when it cannot fail, until
+    // handle_device_lost() succeeds.
+    pub fn is_device_lost(&self) -> bool {
+        self.is_device_lost
+    }
+
+    // Releases the objects of the lost device and recreates every D3D12 object from scratch,
+    // keeping the creation parameters and runtime settings. The old swapchain has to be released
+    // before a new one can be created for the same window, so a failed recreation cannot restore
+    // the previous state: the context is left lost and only handle_device_lost() can be retried.
+    pub fn handle_device_lost(&mut self) -> Result<(), ContextError> {
+        // The device is gone, so the old objects are released without waiting for the GPU.
+        if !self.is_device_lost {
+            self.release_device_objects();
+            self.is_device_lost = true;
+        }
+
+        let mut params = self.params.clone();
+        params.window_width = self.back_buffer_width;
+        params.window_height = self.back_buffer_height;
+        // Headless contexts have no window.
+        let window_handle = if self.window_handle.is_null() {
+            None
+        } else {
+            Some(self.window_handle)
+        };
+        let mut context = Self::create(window_handle, &params)?;
+
+        // Carry over the runtime settings.
+        context.clear_color = self.clear_color;
+        context.clear_depth = self.clear_depth;
+        context.clear_stencil = self.clear_stencil;
+        context.clear_flags = self.clear_flags;
+        context.present_interval = self.present_interval;
+        context.screen_viewport = self.screen_viewport;
+        context.scissor_rect = self.scissor_rect;
+        context.minimized = self.minimized;
+        context.occluded = self.occluded;
+        context.windowed_size = self.windowed_size;
+        context.hdr_metadata = self.hdr_metadata;
+        context.update_hdr_metadata();
+
+        // Dropping the lost context doesn't touch its released objects again.
+        *self = context;
+        Ok(())
+    }
+
+    fn check_device(&self) -> Result<(), ContextError> {
+        if self.is_device_lost {
+            Err(ContextError::DeviceLost)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn assert_device(&self) {
+        assert!(
+            !self.is_device_lost,
+            "Device lost, handle_device_lost() must succeed before using the context."
+        );
+    }
+
+    fn release_device_objects(&mut self) {
+        // The device is gone, so there is no GPU work left to wait for.
+        self.deferred_deletes.clear();
+        self.compute_queue = None;
+        self.copy_queue = None;
+        self.msaa_render_target = None;
+        self.depth_stencil = None;
+        self.render_targets.clear();
+
+        unsafe {
+            ManuallyDrop::drop(&mut self.descriptor_allocator);
+            self.swapchain = None;
+            ManuallyDrop::drop(&mut self.command_list);
+            ManuallyDrop::drop(&mut self.command_allocators);
+            ManuallyDrop::drop(&mut self.command_queue);
+            ManuallyDrop::drop(&mut self.device);
+            ManuallyDrop::drop(&mut self.adapter);
+            ManuallyDrop::drop(&mut self.factory);
+        }
+    }
+
+    fn enable_debug_layer(debug: &DebugConfig) -> u32 {
         let mut dxgi_factory_flags = 0;
         #[cfg(debug_assertions)]
         {
@@ -413,8 +1220,16 @@ impl Context {
                     &d3d12sdklayers::ID3D12Debug::uuidof(),
                     &mut debug_controller as *mut *mut _ as *mut *mut _,
                 )) {
-                    (*debug_controller).EnableDebugLayer();
-                    (*debug_controller).Release();
+                    let debug_controller = ComPtr::from_ptr(debug_controller);
+                    debug_controller.EnableDebugLayer();
+                    if debug.gpu_based_validation {
+                        match debug_controller.cast::<d3d12sdklayers::ID3D12Debug1>() {
+                            Ok(debug_controller) => {
+                                debug_controller.SetEnableGPUBasedValidation(minwindef::TRUE)
+                            }
+                            Err(_) => warn!("GPU based validation is not supported."),
+                        }
+                    }
                 }
             }
 
@@ -426,16 +1241,27 @@ impl Context {
                     &mut info_queue as *mut *mut _ as *mut *mut _,
                 )) {
                     dxgi_factory_flags = dxgi1_3::DXGI_CREATE_FACTORY_DEBUG;
-                    (*info_queue).SetBreakOnSeverity(
-                        dxgidebug::DXGI_DEBUG_ALL,
-                        dxgidebug::DXGI_INFO_QUEUE_MESSAGE_SEVERITY_CORRUPTION,
-                        minwindef::TRUE,
-                    );
-                    (*info_queue).SetBreakOnSeverity(
-                        dxgidebug::DXGI_DEBUG_ALL,
-                        dxgidebug::DXGI_INFO_QUEUE_MESSAGE_SEVERITY_ERROR,
-                        minwindef::TRUE,
-                    );
+                    let severities = [
+                        (
+                            dxgidebug::DXGI_INFO_QUEUE_MESSAGE_SEVERITY_CORRUPTION,
+                            debug.break_on_corruption,
+                        ),
+                        (
+                            dxgidebug::DXGI_INFO_QUEUE_MESSAGE_SEVERITY_ERROR,
+                            debug.break_on_error,
+                        ),
+                        (
+                            dxgidebug::DXGI_INFO_QUEUE_MESSAGE_SEVERITY_WARNING,
+                            debug.break_on_warning,
+                        ),
+                    ];
+                    for &(severity, enable) in severities.iter() {
+                        (*info_queue).SetBreakOnSeverity(
+                            dxgidebug::DXGI_DEBUG_ALL,
+                            severity,
+                            enable as _,
+                        );
+                    }
 
                     let mut hide: Vec<dxgidebug::DXGI_INFO_QUEUE_MESSAGE_ID> = vec![
                         80, // IDXGISwapChain::GetContainingOutput: The swapchain's adapter does not control the output on which the swapchain's window resides.
@@ -456,6 +1282,25 @@ impl Context {
         dxgi_factory_flags
     }
 
+    fn validate_swapchain_format(
+        format: dxgiformat::DXGI_FORMAT,
+        flags: ContextFlags,
+    ) -> Result<(), ContextError> {
+        match Self::no_srgb(format) {
+            dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM => {
+                if flags.contains(ContextFlags::ENABLE_HDR) {
+                    warn!(
+                        "HDR output requires a R10G10B10A2_UNORM or R16G16B16A16_FLOAT back buffer format, falling back to SDR."
+                    );
+                }
+                Ok(())
+            }
+            dxgiformat::DXGI_FORMAT_R10G10B10A2_UNORM
+            | dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT => Ok(()),
+            _ => Err(ContextError::UnsupportedBackBufferFormat),
+        }
+    }
+
     fn no_srgb(format: dxgiformat::DXGI_FORMAT) -> dxgiformat::DXGI_FORMAT {
         match format {
             dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM,
@@ -465,56 +1310,161 @@ impl Context {
         }
     }
 
+    // Typeless variant of the formats that have an sRGB variant, so resources can be viewed in both.
+    fn typeless(format: dxgiformat::DXGI_FORMAT) -> dxgiformat::DXGI_FORMAT {
+        match format {
+            dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM
+            | dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+                dxgiformat::DXGI_FORMAT_R8G8B8A8_TYPELESS
+            }
+            dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM
+            | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+                dxgiformat::DXGI_FORMAT_B8G8R8A8_TYPELESS
+            }
+            dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM
+            | dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => {
+                dxgiformat::DXGI_FORMAT_B8G8R8X8_TYPELESS
+            }
+            _ => format,
+        }
+    }
+
+    fn is_msaa_supported(
+        device: &Device,
+        format: dxgiformat::DXGI_FORMAT,
+        sample_count: u32,
+    ) -> bool {
+        let mut quality_levels = d3d12::D3D12_FEATURE_DATA_MULTISAMPLE_QUALITY_LEVELS {
+            Format: format,
+            SampleCount: sample_count,
+            Flags: d3d12::D3D12_MULTISAMPLE_QUALITY_LEVELS_FLAG_NONE,
+            NumQualityLevels: 0,
+        };
+        let hr = unsafe {
+            device.native.CheckFeatureSupport(
+                d3d12::D3D12_FEATURE_MULTISAMPLE_QUALITY_LEVELS,
+                &mut quality_levels as *mut _ as *mut _,
+                mem::size_of::<d3d12::D3D12_FEATURE_DATA_MULTISAMPLE_QUALITY_LEVELS>() as _,
+            )
+        };
+        SUCCEEDED(hr) && quality_levels.NumQualityLevels > 0
+    }
+
+    fn transition_barrier(
+        resource: &GpuResource,
+        state_before: d3d12::D3D12_RESOURCE_STATES,
+        state_after: d3d12::D3D12_RESOURCE_STATES,
+    ) -> d3d12::D3D12_RESOURCE_BARRIER {
+        let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+            Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+            Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+            u: unsafe { mem::zeroed() },
+        };
+        *unsafe { barrier.u.Transition_mut() } = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
+            pResource: resource.native.as_ptr(),
+            Subresource: d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            StateBefore: state_before,
+            StateAfter: state_after,
+        };
+        barrier
+    }
+
     fn create_render_targets(
         device: &Device,
         swapchain: &Swapchain,
         descriptor_allocator: &mut CpuDescriptorPool,
         back_buffer_format: dxgiformat::DXGI_FORMAT,
         back_buffer_count: u32,
-    ) -> (Vec<GpuResource>, Vec<CpuDescriptor>) {
+    ) -> Result<(Vec<GpuResource>, Vec<CpuDescriptor>), dxgi_wrapper::Error> {
+        // Get every buffer first, so no view is left allocated when one fails.
+        let buffers = (0..back_buffer_count)
+            .map(|n| swapchain.get_buffer(n))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let mut render_targets = Vec::with_capacity(back_buffer_count as _);
         let mut rtv_descriptors = Vec::with_capacity(back_buffer_count as _);
-        unsafe {
-            for n in 0..back_buffer_count {
-                let mut render_target: *mut d3d12::ID3D12Resource = ptr::null_mut();
-                if FAILED(swapchain.0.GetBuffer(
-                    n,
-                    &d3d12::ID3D12Resource::uuidof(),
-                    &mut render_target as *mut *mut _ as *mut *mut _,
-                )) {
-                    panic!(
-                        "Failed to create D3D12 render target view for back buffer {}.",
-                        n
-                    );
-                }
+        for render_target in buffers {
+            #[cfg(debug_assertions)]
+            unsafe {
+                render_target.SetName(
+                    format!("Adamant::RenderTarget_{}", render_targets.len())
+                        .encode_utf16()
+                        .chain(Some(0))
+                        .collect::<Vec<u16>>()
+                        .as_ptr(),
+                );
+            }
 
-                /*#[cfg(debug_assertions)]
-                {
-                    render_target.SetName(
-                        format!("AdamantRenderTarget{}", n)
-                            .encode_utf16()
-                            .collect::<Vec<u16>>()
-                            .as_ptr(),
-                    );
-                }*/
+            let rtv_desc = d3d12::D3D12_RENDER_TARGET_VIEW_DESC {
+                Format: back_buffer_format,
+                ViewDimension: d3d12::D3D12_RTV_DIMENSION_TEXTURE2D,
+                ..unsafe { mem::zeroed() }
+            };
+            let rtv_descriptor = descriptor_allocator.allocate();
+            unsafe {
+                device.native.CreateRenderTargetView(
+                    render_target.as_ptr(),
+                    &rtv_desc,
+                    rtv_descriptor,
+                );
+            }
+            rtv_descriptors.push(rtv_descriptor);
+            render_targets.push(GpuResource::create(
+                render_target,
+                d3d12::D3D12_RESOURCE_STATE_PRESENT,
+            ));
+        }
+        Ok((render_targets, rtv_descriptors))
+    }
 
+    fn create_offscreen_render_targets(
+        device: &Device,
+        descriptor_allocator: &mut CpuDescriptorPool,
+        back_buffer_format: dxgiformat::DXGI_FORMAT,
+        back_buffer_count: u32,
+        back_buffer_width: u32,
+        back_buffer_height: u32,
+    ) -> Result<(Vec<GpuResource>, Vec<CpuDescriptor>), ContextError> {
+        trace!("Creating D3D12 offscreen render targets.");
+        let mut render_targets = Vec::with_capacity(back_buffer_count as _);
+        let mut rtv_descriptors = Vec::with_capacity(back_buffer_count as _);
+        for _ in 0..back_buffer_count {
+            // Created in the same state as swapchain buffers, so frames transition them alike.
+            // Typeless, so views and resolves can use the sRGB format like on swapchain buffers.
+            let render_target = GpuResource::create_texture_2d(
+                device,
+                Self::typeless(back_buffer_format),
+                back_buffer_width,
+                back_buffer_height,
+                1,
+                d3d12::D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                d3d12::D3D12_RESOURCE_STATE_PRESENT,
+            )
+            .map_err(|_| {
+                // Return the views created so far, the resources are released on drop.
+                for &rtv_descriptor in &rtv_descriptors {
+                    descriptor_allocator.free(rtv_descriptor, 1);
+                }
+                ContextError::RenderTargetCreateFailed
+            })?;
+
+            let rtv_descriptor = descriptor_allocator.allocate();
+            unsafe {
                 let rtv_desc = d3d12::D3D12_RENDER_TARGET_VIEW_DESC {
                     Format: back_buffer_format,
                     ViewDimension: d3d12::D3D12_RTV_DIMENSION_TEXTURE2D,
                     ..mem::zeroed()
                 };
-                let rtv_descriptor = descriptor_allocator.allocate();
-                device
-                    .native
-                    .CreateRenderTargetView(render_target, &rtv_desc, rtv_descriptor);
-                rtv_descriptors.push(rtv_descriptor);
-                render_targets.push(GpuResource::create(
-                    unsafe { ComPtr::from_ptr(render_target) },
-                    d3d12::D3D12_RESOURCE_STATE_PRESENT,
-                ));
+                device.native.CreateRenderTargetView(
+                    render_target.native.as_ptr(),
+                    &rtv_desc,
+                    rtv_descriptor,
+                );
             }
+            rtv_descriptors.push(rtv_descriptor);
+            render_targets.push(render_target);
         }
-        (render_targets, rtv_descriptors)
+        Ok((render_targets, rtv_descriptors))
     }
 
     fn create_depth_stencil(
@@ -523,9 +1473,9 @@ impl Context {
         depth_buffer_format: dxgiformat::DXGI_FORMAT,
         back_buffer_width: u32,
         back_buffer_height: u32,
-    ) -> (GpuResource, CpuDescriptor) {
+        sample_count: u32,
+    ) -> Result<(GpuResource, CpuDescriptor), ContextError> {
         trace!("Creating D3D12 depth stencil buffer.");
-        let dsv_descriptor = descriptor_allocator.allocate();
         let depth_heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
             Type: d3d12::D3D12_HEAP_TYPE_DEFAULT,
             CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
@@ -542,7 +1492,7 @@ impl Context {
             MipLevels: 1,
             Format: depth_buffer_format,
             SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
-                Count: 1,
+                Count: sample_count,
                 Quality: 0,
             },
             Layout: d3d12::D3D12_TEXTURE_LAYOUT_UNKNOWN,
@@ -568,48 +1518,134 @@ impl Context {
                 &d3d12::ID3D12Resource::uuidof(),
                 &mut depth_stencil as *mut *mut _ as *mut *mut _,
             )) {
-                panic!("Failed to create D3D12 depth/stencil buffer.");
+                return Err(ContextError::DepthStencilCreateFailed);
             }
 
-            /*#[cfg(debug_assertions)]
+            #[cfg(debug_assertions)]
             {
-                depth_stencil.SetName(
-                    "AdamantDepthStencil"
+                (*depth_stencil).SetName(
+                    "Adamant::DepthStencil"
                         .encode_utf16()
+                        .chain(Some(0))
                         .collect::<Vec<u16>>()
                         .as_ptr(),
                 );
-            }*/
+            }
 
             let dsv_desc = d3d12::D3D12_DEPTH_STENCIL_VIEW_DESC {
                 Format: depth_buffer_format,
-                ViewDimension: d3d12::D3D12_DSV_DIMENSION_TEXTURE2D,
+                ViewDimension: if sample_count > 1 {
+                    d3d12::D3D12_DSV_DIMENSION_TEXTURE2DMS
+                } else {
+                    d3d12::D3D12_DSV_DIMENSION_TEXTURE2D
+                },
                 ..mem::zeroed()
             };
+            let dsv_descriptor = descriptor_allocator.allocate();
             device
                 .native
                 .CreateDepthStencilView(depth_stencil, &dsv_desc, dsv_descriptor);
+            Ok((
+                GpuResource::create(
+                    ComPtr::from_ptr(depth_stencil),
+                    d3d12::D3D12_RESOURCE_STATE_DEPTH_WRITE,
+                ),
+                dsv_descriptor,
+            ))
+        }
+    }
+
+    fn create_msaa_render_target(
+        device: &Device,
+        descriptor_allocator: &mut CpuDescriptorPool,
+        back_buffer_format: dxgiformat::DXGI_FORMAT,
+        back_buffer_width: u32,
+        back_buffer_height: u32,
+        sample_count: u32,
+    ) -> Result<(GpuResource, CpuDescriptor), ContextError> {
+        trace!("Creating D3D12 MSAA render target.");
+        let heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
+            Type: d3d12::D3D12_HEAP_TYPE_DEFAULT,
+            CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 1,
+            VisibleNodeMask: 1,
+        };
+        let render_target_desc = d3d12::D3D12_RESOURCE_DESC {
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Alignment: 0,
+            Width: u64::from(back_buffer_width),
+            Height: back_buffer_height,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            // Typeless, so the view and the resolve can use the sRGB format.
+            Format: Self::typeless(back_buffer_format),
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: sample_count,
+                Quality: 0,
+            },
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_UNKNOWN,
+            Flags: d3d12::D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+        };
+
+        let mut render_target: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        unsafe {
+            if FAILED(device.native.CreateCommittedResource(
+                &heap_properties,
+                d3d12::D3D12_HEAP_FLAG_NONE,
+                &render_target_desc,
+                d3d12::D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut render_target as *mut *mut _ as *mut *mut _,
+            )) {
+                return Err(ContextError::MsaaRenderTargetCreateFailed);
+            }
+
+            let rtv_desc = d3d12::D3D12_RENDER_TARGET_VIEW_DESC {
+                Format: back_buffer_format,
+                ViewDimension: d3d12::D3D12_RTV_DIMENSION_TEXTURE2DMS,
+                ..mem::zeroed()
+            };
+            let rtv_descriptor = descriptor_allocator.allocate();
+            device
+                .native
+                .CreateRenderTargetView(render_target, &rtv_desc, rtv_descriptor);
+            Ok((
+                GpuResource::create(
+                    ComPtr::from_ptr(render_target),
+                    d3d12::D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+                ),
+                rtv_descriptor,
+            ))
         }
-        (
-            GpuResource::create(
-                unsafe { ComPtr::from_ptr(depth_stencil) },
-                d3d12::D3D12_RESOURCE_STATE_DEPTH_WRITE,
-            ),
-            dsv_descriptor,
-        )
     }
 }
 
 impl Drop for Context {
     fn drop(&mut self) {
+        // Objects of a lost device have already been released.
+        if self.is_device_lost {
+            return;
+        }
+
         // Wait for GPU to finish all work.
         self.command_queue.flush().unwrap();
+        if let Some(mut compute_queue) = self.compute_queue.take() {
+            compute_queue.flush().unwrap();
+        }
+        if let Some(mut copy_queue) = self.copy_queue.take() {
+            copy_queue.flush().unwrap();
+        }
+
+        self.deferred_deletes.clear();
+        self.msaa_render_target = None;
+        self.depth_stencil = None;
+        self.render_targets.clear();
 
         unsafe {
-            ManuallyDrop::drop(&mut self.depth_stencil);
-            ManuallyDrop::drop(&mut self.render_targets);
             ManuallyDrop::drop(&mut self.descriptor_allocator);
-            ManuallyDrop::drop(&mut self.swapchain);
+            self.swapchain = None;
             ManuallyDrop::drop(&mut self.command_list);
             ManuallyDrop::drop(&mut self.command_allocators);
             ManuallyDrop::drop(&mut self.command_queue);
@@ -630,6 +1666,7 @@ impl Drop for Context {
             }
 
             ManuallyDrop::drop(&mut self.device);
+            ManuallyDrop::drop(&mut self.adapter);
             ManuallyDrop::drop(&mut self.factory);
 
             #[cfg(debug_assertions)]
@@ -14,13 +14,23 @@ pub enum Error {
 }
 
 pub type CpuDescriptor = d3d12::D3D12_CPU_DESCRIPTOR_HANDLE;
+pub type GpuDescriptor = d3d12::D3D12_GPU_DESCRIPTOR_HANDLE;
 
 pub struct DescriptorHeap {
-    _native: ComPtr<d3d12::ID3D12DescriptorHeap>,
+    pub(crate) native: ComPtr<d3d12::ID3D12DescriptorHeap>,
     pub(crate) descriptor_size: u32,
+    descriptors_count: u32,
+    start_descriptor: CpuDescriptor,
+    // Only meaningful for shader visible heaps.
+    start_gpu_descriptor: GpuDescriptor,
     next_descriptor: CpuDescriptor,
 }
 
+// Descriptor heaps are free threaded and allocating from them takes &mut, so shader visible heaps
+// can be bound by command lists recorded on several threads.
+unsafe impl Send for DescriptorHeap {}
+unsafe impl Sync for DescriptorHeap {}
+
 impl DescriptorHeap {
     pub fn new(
         device: &Device,
@@ -47,26 +57,67 @@ impl DescriptorHeap {
             return Err(Error::DescriptorHeapCreateFailed);
         }
 
-        /*#[cfg(debug_assertions)]
+        let descriptor_heap = unsafe { ComPtr::from_ptr(descriptor_heap) };
+
+        #[cfg(debug_assertions)]
         {
             hr = unsafe {
-                descriptor_heap.SetName(debug_name.encode_utf16().collect::<Vec<u16>>().as_ptr())
+                descriptor_heap.SetName(
+                    debug_name
+                        .encode_utf16()
+                        .chain(Some(0))
+                        .collect::<Vec<u16>>()
+                        .as_ptr(),
+                )
             };
             if FAILED(hr) {
                 return Err(Error::DescriptorHeapSetNameFailed);
             }
-        }*/
+        }
 
-        let next_descriptor = unsafe { (*descriptor_heap).GetCPUDescriptorHandleForHeapStart() };
+        let start_descriptor = unsafe { descriptor_heap.GetCPUDescriptorHandleForHeapStart() };
+        let start_gpu_descriptor = if flags & d3d12::D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE != 0
+        {
+            unsafe { descriptor_heap.GetGPUDescriptorHandleForHeapStart() }
+        } else {
+            GpuDescriptor { ptr: 0 }
+        };
         let descriptor_size = unsafe { device.native.GetDescriptorHandleIncrementSize(type_) };
 
         Ok(DescriptorHeap {
-            _native: unsafe { ComPtr::from_ptr(descriptor_heap) },
+            native: descriptor_heap,
             descriptor_size,
-            next_descriptor,
+            descriptors_count,
+            start_descriptor,
+            start_gpu_descriptor,
+            next_descriptor: start_descriptor,
         })
     }
 
+    pub fn contains(&self, descriptor: CpuDescriptor) -> bool {
+        let start = self.start_descriptor.ptr;
+        let end = start + (self.descriptors_count * self.descriptor_size) as usize;
+        descriptor.ptr >= start && descriptor.ptr < end
+    }
+
+    pub fn cpu_descriptor(&self, index: u32) -> CpuDescriptor {
+        assert!(index < self.descriptors_count);
+        CpuDescriptor {
+            ptr: self.start_descriptor.ptr + (index * self.descriptor_size) as usize,
+        }
+    }
+
+    pub fn gpu_descriptor(&self, index: u32) -> GpuDescriptor {
+        assert!(index < self.descriptors_count);
+        assert!(
+            self.start_gpu_descriptor.ptr != 0,
+            "heap is not shader visible"
+        );
+        GpuDescriptor {
+            ptr: self.start_gpu_descriptor.ptr + u64::from(index * self.descriptor_size),
+        }
+    }
+
     pub fn allocate_cpu(&mut self, count: u32) -> CpuDescriptor {
         let handle = self.next_descriptor;
         self.next_descriptor = CpuDescriptor {
@@ -82,6 +133,8 @@ pub struct CpuDescriptorPool {
     device: Device,
     type_: d3d12::D3D12_DESCRIPTOR_HEAP_TYPE,
     heaps: Vec<DescriptorHeap>,
+    // Ranges of descriptors released back to the pool, one list per heap.
+    free_lists: Vec<Vec<(CpuDescriptor, u32)>>,
     current_heap_id: Option<usize>,
     free_descriptors_count: u32,
 }
@@ -92,12 +145,18 @@ impl CpuDescriptorPool {
             device: device.clone(),
             type_,
             heaps: Vec::new(),
+            free_lists: Vec::new(),
             current_heap_id: None,
             free_descriptors_count: DESCRIPTOR_HEAP_SIZE,
         }
     }
 
     pub fn allocate_many(&mut self, count: u32) -> CpuDescriptor {
+        // Reuse previously freed descriptors before bumping into fresh heap space.
+        if let Some(descriptor) = self.allocate_from_free_lists(count) {
+            return descriptor;
+        }
+
         let heap_id = if self.current_heap_id.is_none() || count > self.free_descriptors_count {
             // Allocate a new heap here
             let id = self.heaps.len();
@@ -111,6 +170,7 @@ impl CpuDescriptorPool {
                 )
                 .unwrap(),
             );
+            self.free_lists.push(Vec::new());
             self.current_heap_id = Some(id);
             self.free_descriptors_count = DESCRIPTOR_HEAP_SIZE;
             id
@@ -128,4 +188,36 @@ impl CpuDescriptorPool {
     pub fn allocate(&mut self) -> CpuDescriptor {
         self.allocate_many(1)
     }
+
+    pub fn free(&mut self, descriptor: CpuDescriptor, count: u32) {
+        let heap_id = self
+            .heaps
+            .iter()
+            .position(|heap| heap.contains(descriptor))
+            .expect("descriptor should belong to this pool");
+        self.free_lists[heap_id].push((descriptor, count));
+    }
+
+    fn allocate_from_free_lists(&mut self, count: u32) -> Option<CpuDescriptor> {
+        for (heap_id, free_list) in self.free_lists.iter_mut().enumerate() {
+            if let Some(index) = free_list
+                .iter()
+                .position(|&(_, free_count)| free_count >= count)
+            {
+                let (descriptor, free_count) = free_list.swap_remove(index);
+                if free_count > count {
+                    // Return the unused tail of the range to the free list.
+                    let descriptor_size = self.heaps[heap_id].descriptor_size;
+                    free_list.push((
+                        CpuDescriptor {
+                            ptr: descriptor.ptr + (count * descriptor_size) as usize,
+                        },
+                        free_count - count,
+                    ));
+                }
+                return Some(descriptor);
+            }
+        }
+        None
+    }
 }
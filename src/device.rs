@@ -1,5 +1,8 @@
 use crate::com::ComPtr;
 use crate::dxgi::Adapter;
+use crate::raytracing::{ID3D12Device5, RaytracingDevice};
+use crate::root_signature::RootSignature;
+use crate::DebugConfig;
 
 use winapi::shared::{
     minwindef,
@@ -8,6 +11,10 @@ use winapi::shared::{
 use winapi::um::{d3d12, d3d12sdklayers, d3dcommon};
 use winapi::Interface;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::rc::Rc;
 use std::{mem, ptr};
 
 #[derive(Debug)]
@@ -16,16 +23,60 @@ pub enum Error {
     DeviceSetNameFailed,
 }
 
+// Capabilities missing from winapi, see D3D12_FEATURE_DATA_D3D12_OPTIONS5 and OPTIONS7 in d3d12.h.
+const D3D12_FEATURE_D3D12_OPTIONS5: d3d12::D3D12_FEATURE = 27;
+const D3D12_FEATURE_D3D12_OPTIONS7: d3d12::D3D12_FEATURE = 32;
+
+pub const D3D12_RAYTRACING_TIER_NOT_SUPPORTED: u32 = 0;
+pub const D3D12_RAYTRACING_TIER_1_0: u32 = 10;
+pub const D3D12_RAYTRACING_TIER_1_1: u32 = 11;
+
+pub const D3D12_MESH_SHADER_TIER_NOT_SUPPORTED: u32 = 0;
+pub const D3D12_MESH_SHADER_TIER_1: u32 = 10;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FeatureDataOptions5 {
+    srv_only_tiled_resource_tier3: minwindef::BOOL,
+    render_passes_tier: u32,
+    raytracing_tier: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FeatureDataOptions7 {
+    mesh_shader_tier: u32,
+    sampler_feedback_tier: u32,
+}
+
+// A message reported by the D3D12 debug layer.
+#[derive(Debug)]
+pub struct DebugMessage {
+    pub severity: d3d12sdklayers::D3D12_MESSAGE_SEVERITY,
+    pub category: d3d12sdklayers::D3D12_MESSAGE_CATEGORY,
+    pub id: d3d12sdklayers::D3D12_MESSAGE_ID,
+    pub description: String,
+}
+
 #[derive(Clone)]
 pub struct Device {
     pub(crate) native: ComPtr<d3d12::ID3D12Device>,
     feature_level: d3dcommon::D3D_FEATURE_LEVEL,
+    // Optional capabilities, queried once at creation. Zeroed when the runtime does not know
+    // about them, which reads as not supported.
+    options: d3d12::D3D12_FEATURE_DATA_D3D12_OPTIONS,
+    options1: d3d12::D3D12_FEATURE_DATA_D3D12_OPTIONS1,
+    options5: FeatureDataOptions5,
+    options7: FeatureDataOptions7,
+    // Root signatures keyed by their serialized blob, shared by all clones of the device.
+    pub(crate) root_signatures: Rc<RefCell<HashMap<Vec<u8>, RootSignature>>>,
 }
 
 impl Device {
     pub fn new(
         adapter: &Adapter,
         min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
+        debug: &DebugConfig,
     ) -> Result<Self, Error> {
         let mut device: *mut d3d12::ID3D12Device = ptr::null_mut();
         let mut hr = unsafe {
@@ -70,7 +121,7 @@ impl Device {
         // Configure device for debugging.
         #[cfg(debug_assertions)]
         {
-            Self::configure_debug_device(&native);
+            Self::configure_debug_device(&native, debug);
 
             /*hr = unsafe {
                 device.SetName(
@@ -85,23 +136,194 @@ impl Device {
             }*/
         }
 
-        Ok(Device {
+        let mut device = Device {
             native,
             feature_level,
-        })
+            options: unsafe { mem::zeroed() },
+            options1: unsafe { mem::zeroed() },
+            options5: unsafe { mem::zeroed() },
+            options7: unsafe { mem::zeroed() },
+            root_signatures: Rc::new(RefCell::new(HashMap::new())),
+        };
+        // Each field's type is the data struct of the queried feature.
+        unsafe {
+            if let Some(options) = device.check_feature_support(d3d12::D3D12_FEATURE_D3D12_OPTIONS)
+            {
+                device.options = options;
+            }
+            if let Some(options1) =
+                device.check_feature_support(d3d12::D3D12_FEATURE_D3D12_OPTIONS1)
+            {
+                device.options1 = options1;
+            }
+            if let Some(options5) = device.check_feature_support(D3D12_FEATURE_D3D12_OPTIONS5) {
+                device.options5 = options5;
+            }
+            if let Some(options7) = device.check_feature_support(D3D12_FEATURE_D3D12_OPTIONS7) {
+                device.options7 = options7;
+            }
+        }
+        Ok(device)
+    }
+
+    /// Queries a capability without input fields. None when the device or runtime does not know
+    /// about the feature. The typed helpers below cover the common capabilities.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the `D3D12_FEATURE_DATA_*` struct matching `feature`, the runtime writes the
+    /// feature's data into it, and zeroed bytes must be a valid `T`.
+    pub unsafe fn check_feature_support<T: Copy>(
+        &self,
+        feature: d3d12::D3D12_FEATURE,
+    ) -> Option<T> {
+        let mut data: T = mem::zeroed();
+        let hr = self.native.CheckFeatureSupport(
+            feature,
+            &mut data as *mut _ as *mut _,
+            mem::size_of::<T>() as _,
+        );
+        if SUCCEEDED(hr) {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    pub fn resource_binding_tier(&self) -> d3d12::D3D12_RESOURCE_BINDING_TIER {
+        self.options.ResourceBindingTier
+    }
+
+    // One of the D3D12_RAYTRACING_TIER_* constants.
+    pub fn raytracing_tier(&self) -> u32 {
+        self.options5.raytracing_tier
     }
 
-    fn configure_debug_device(device: &ComPtr<d3d12::ID3D12Device>) {
+    pub fn supports_mesh_shaders(&self) -> bool {
+        self.options7.mesh_shader_tier >= D3D12_MESH_SHADER_TIER_1
+    }
+
+    // None when the adapter, driver or runtime cannot raytrace.
+    pub fn create_raytracing(&self) -> Option<RaytracingDevice> {
+        if self.raytracing_tier() < D3D12_RAYTRACING_TIER_1_0 {
+            return None;
+        }
+        self.native
+            .cast::<ID3D12Device5>()
+            .ok()
+            .map(|device5| RaytracingDevice::new(self, device5))
+    }
+
+    pub fn supports_wave_ops(&self) -> bool {
+        self.options1.WaveOps != minwindef::FALSE
+    }
+
+    pub fn feature_level(&self) -> d3dcommon::D3D_FEATURE_LEVEL {
+        self.feature_level
+    }
+
+    // Size and alignment a resource described by `desc` needs when placed in a heap.
+    pub fn resource_allocation_info(
+        &self,
+        desc: &d3d12::D3D12_RESOURCE_DESC,
+    ) -> d3d12::D3D12_RESOURCE_ALLOCATION_INFO {
+        unsafe { self.native.GetResourceAllocationInfo(0, 1, desc) }
+    }
+
+    // Layouts of `num_subresources` subresources starting at `first_subresource` when copied to or
+    // from a buffer at `base_offset`, with their number of rows, the unpadded size of a row in
+    // bytes, and the buffer size needed to hold them all.
+    pub fn copyable_footprints(
+        &self,
+        desc: &d3d12::D3D12_RESOURCE_DESC,
+        first_subresource: u32,
+        num_subresources: u32,
+        base_offset: u64,
+    ) -> (
+        Vec<d3d12::D3D12_PLACED_SUBRESOURCE_FOOTPRINT>,
+        Vec<u32>,
+        Vec<u64>,
+        u64,
+    ) {
+        let count = num_subresources as usize;
+        let mut layouts = vec![unsafe { mem::zeroed() }; count];
+        let mut row_counts = vec![0; count];
+        let mut row_sizes = vec![0; count];
+        let mut total_size = 0;
+        unsafe {
+            self.native.GetCopyableFootprints(
+                desc,
+                first_subresource,
+                num_subresources,
+                base_offset,
+                layouts.as_mut_ptr(),
+                row_counts.as_mut_ptr(),
+                row_sizes.as_mut_ptr(),
+                &mut total_size,
+            );
+        }
+        (layouts, row_counts, row_sizes, total_size)
+    }
+
+    // Takes the messages stored by the debug layer since the last call. Always empty when the
+    // debug layer is not enabled.
+    pub fn drain_debug_messages(&self) -> Vec<DebugMessage> {
+        let info_queue = match self.native.cast::<d3d12sdklayers::ID3D12InfoQueue>() {
+            Ok(info_queue) => info_queue,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut messages = Vec::new();
+        unsafe {
+            for index in 0..info_queue.GetNumStoredMessages() {
+                let mut length = 0;
+                if FAILED(info_queue.GetMessage(index, ptr::null_mut(), &mut length)) {
+                    continue;
+                }
+
+                // The description is stored right after the message, keep the buffer aligned.
+                let mut buffer = vec![0u64; (length + 7) / 8];
+                let message = buffer.as_mut_ptr() as *mut d3d12sdklayers::D3D12_MESSAGE;
+                if FAILED(info_queue.GetMessage(index, message, &mut length)) {
+                    continue;
+                }
+
+                let message = &*message;
+                let description = CStr::from_ptr(message.pDescription)
+                    .to_string_lossy()
+                    .into_owned();
+                messages.push(DebugMessage {
+                    severity: message.Severity,
+                    category: message.Category,
+                    id: message.ID,
+                    description,
+                });
+            }
+            info_queue.ClearStoredMessages();
+        }
+        messages
+    }
+
+    fn configure_debug_device(device: &ComPtr<d3d12::ID3D12Device>, debug: &DebugConfig) {
         unsafe {
             if let Ok(info_queue) = device.cast::<d3d12sdklayers::ID3D12InfoQueue>() {
-                info_queue.SetBreakOnSeverity(
-                    d3d12sdklayers::D3D12_MESSAGE_SEVERITY_CORRUPTION,
-                    minwindef::TRUE,
-                );
-                info_queue.SetBreakOnSeverity(
-                    d3d12sdklayers::D3D12_MESSAGE_SEVERITY_ERROR,
-                    minwindef::TRUE,
-                );
+                let severities = [
+                    (
+                        d3d12sdklayers::D3D12_MESSAGE_SEVERITY_CORRUPTION,
+                        debug.break_on_corruption,
+                    ),
+                    (
+                        d3d12sdklayers::D3D12_MESSAGE_SEVERITY_ERROR,
+                        debug.break_on_error,
+                    ),
+                    (
+                        d3d12sdklayers::D3D12_MESSAGE_SEVERITY_WARNING,
+                        debug.break_on_warning,
+                    ),
+                ];
+                for &(severity, enable) in severities.iter() {
+                    info_queue.SetBreakOnSeverity(severity, enable as _);
+                }
 
                 let mut severities: Vec<d3d12sdklayers::D3D12_MESSAGE_SEVERITY> =
                     vec![d3d12sdklayers::D3D12_MESSAGE_SEVERITY_INFO];
@@ -131,3 +353,30 @@ impl Device {
         }
     }
 }
+
+// WARP device with the debug layer enabled when installed, so tests run without a GPU. Nothing
+// breaks into the debugger, failures are left to the assertions.
+#[cfg(test)]
+pub(crate) fn create_test_device() -> Device {
+    use crate::dxgi::Factory;
+
+    unsafe {
+        let mut debug_controller: *mut d3d12sdklayers::ID3D12Debug = ptr::null_mut();
+        if SUCCEEDED(d3d12::D3D12GetDebugInterface(
+            &d3d12sdklayers::ID3D12Debug::uuidof(),
+            &mut debug_controller as *mut *mut _ as *mut *mut _,
+        )) {
+            ComPtr::from_ptr(debug_controller).EnableDebugLayer();
+        }
+    }
+
+    let debug = DebugConfig {
+        break_on_error: false,
+        break_on_corruption: false,
+        break_on_warning: false,
+        gpu_based_validation: false,
+    };
+    let factory = Factory::new(ptr::null_mut(), 0).unwrap();
+    let adapter = Adapter::new(&factory, d3dcommon::D3D_FEATURE_LEVEL_11_0, true).unwrap();
+    Device::new(&adapter, d3dcommon::D3D_FEATURE_LEVEL_11_0, &debug).unwrap()
+}
@@ -0,0 +1,320 @@
+// Minimal bindings to the DirectX Shader Compiler (dxcompiler.dll), used to compile Shader Model 6.x.
+#![allow(non_snake_case, non_upper_case_globals)]
+
+use crate::com::ComPtr;
+
+use winapi::shared::{
+    basetsd::{SIZE_T, UINT32},
+    guiddef::{REFCLSID, REFIID},
+    minwindef::{BOOL, LPCVOID, LPVOID},
+    winerror::{FAILED, HRESULT},
+};
+use winapi::um::{
+    d3d12shader::ID3D12ShaderReflection,
+    libloaderapi,
+    unknwnbase::{IUnknown, IUnknownVtbl},
+    winnt::LPCWSTR,
+};
+use winapi::{Class, Interface, RIDL};
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+use std::{mem, ptr, slice};
+
+const DXC_CP_UTF8: UINT32 = 65001;
+
+// DXC_FOURCC('D', 'X', 'I', 'L'), the container part holding the DXIL program.
+const DXC_PART_DXIL: UINT32 =
+    (b'D' as UINT32) | (b'X' as UINT32) << 8 | (b'I' as UINT32) << 16 | (b'L' as UINT32) << 24;
+
+#[repr(C)]
+struct DxcBuffer {
+    Ptr: LPCVOID,
+    Size: SIZE_T,
+    Encoding: UINT32,
+}
+
+RIDL! {#[uuid(0x73e22d93, 0xe6ce, 0x47f3, 0xb5, 0xbf, 0xf0, 0x66, 0x4f, 0x39, 0xc1, 0xb0)]
+class DxcCompiler;}
+
+RIDL! {#[uuid(0x6245d6af, 0x66e0, 0x48fd, 0x80, 0xb4, 0x4d, 0x27, 0x17, 0x96, 0x74, 0x8c)]
+class DxcUtils;}
+
+RIDL! {#[uuid(0xb9f54489, 0x55b8, 0x400c, 0xba, 0x3a, 0x16, 0x75, 0xe4, 0x72, 0x8b, 0x91)]
+class DxcContainerReflection;}
+
+RIDL! {#[uuid(0x8ba5fb08, 0x5195, 0x40e2, 0xac, 0x58, 0x0d, 0x98, 0x9c, 0x3a, 0x01, 0x02)]
+interface IDxcBlob(IDxcBlobVtbl): IUnknown(IUnknownVtbl) {
+    fn GetBufferPointer() -> LPVOID,
+    fn GetBufferSize() -> SIZE_T,
+}}
+
+RIDL! {#[uuid(0x7241d424, 0x2646, 0x4191, 0x97, 0xc0, 0x98, 0xe9, 0x6e, 0x42, 0xfc, 0x68)]
+interface IDxcBlobEncoding(IDxcBlobEncodingVtbl): IDxcBlob(IDxcBlobVtbl) {
+    fn GetEncoding(
+        pKnown: *mut BOOL,
+        pCodePage: *mut UINT32,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x7f61fc7d, 0x950d, 0x467f, 0xb3, 0xe3, 0x3c, 0x02, 0xfb, 0x49, 0x18, 0x7c)]
+interface IDxcIncludeHandler(IDxcIncludeHandlerVtbl): IUnknown(IUnknownVtbl) {
+    fn LoadSource(
+        pFilename: LPCWSTR,
+        ppIncludeSource: *mut *mut IDxcBlob,
+    ) -> HRESULT,
+}}
+
+// Only the methods up to CreateDefaultIncludeHandler are declared, the rest are never called.
+RIDL! {#[uuid(0x4605c4cb, 0x2019, 0x492a, 0xad, 0xa4, 0x65, 0xf2, 0x0b, 0xb7, 0xd6, 0x7f)]
+interface IDxcUtils(IDxcUtilsVtbl): IUnknown(IUnknownVtbl) {
+    fn CreateBlobFromBlob(
+        pBlob: *mut IDxcBlob,
+        offset: UINT32,
+        length: UINT32,
+        ppResult: *mut *mut IDxcBlob,
+    ) -> HRESULT,
+    fn CreateBlobFromPinned(
+        pData: LPCVOID,
+        size: UINT32,
+        codePage: UINT32,
+        pBlobEncoding: *mut *mut IDxcBlobEncoding,
+    ) -> HRESULT,
+    fn MoveToBlob(
+        pData: LPCVOID,
+        pIMalloc: LPVOID,
+        size: UINT32,
+        codePage: UINT32,
+        pBlobEncoding: *mut *mut IDxcBlobEncoding,
+    ) -> HRESULT,
+    fn CreateBlob(
+        pData: LPCVOID,
+        size: UINT32,
+        codePage: UINT32,
+        pBlobEncoding: *mut *mut IDxcBlobEncoding,
+    ) -> HRESULT,
+    fn LoadFile(
+        pFileName: LPCWSTR,
+        pCodePage: *mut UINT32,
+        pBlobEncoding: *mut *mut IDxcBlobEncoding,
+    ) -> HRESULT,
+    fn CreateReadOnlyStreamFromBlob(
+        pBlob: *mut IDxcBlob,
+        ppStream: *mut LPVOID,
+    ) -> HRESULT,
+    fn CreateDefaultIncludeHandler(
+        ppResult: *mut *mut IDxcIncludeHandler,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xcedb484a, 0xd4e9, 0x445a, 0xb9, 0x91, 0xca, 0x21, 0xca, 0x15, 0x7d, 0xc2)]
+interface IDxcOperationResult(IDxcOperationResultVtbl): IUnknown(IUnknownVtbl) {
+    fn GetStatus(
+        pStatus: *mut HRESULT,
+    ) -> HRESULT,
+    fn GetResult(
+        ppResult: *mut *mut IDxcBlob,
+    ) -> HRESULT,
+    fn GetErrorBuffer(
+        ppErrors: *mut *mut IDxcBlobEncoding,
+    ) -> HRESULT,
+}}
+
+// IDxcResult extends IDxcOperationResult, only the inherited methods are needed here.
+RIDL! {#[uuid(0x58346cda, 0xdde7, 0x4497, 0x94, 0x61, 0x6f, 0x87, 0xaf, 0x5e, 0x06, 0x59)]
+interface IDxcResult(IDxcResultVtbl): IDxcOperationResult(IDxcOperationResultVtbl) {}}
+
+RIDL! {#[uuid(0x228b4687, 0x5a6a, 0x4730, 0x90, 0x0c, 0x97, 0x02, 0xb2, 0x20, 0x3f, 0x54)]
+interface IDxcCompiler3(IDxcCompiler3Vtbl): IUnknown(IUnknownVtbl) {
+    fn Compile(
+        pSource: *const DxcBuffer,
+        pArguments: *mut LPCWSTR,
+        argCount: UINT32,
+        pIncludeHandler: *mut IDxcIncludeHandler,
+        riid: REFIID,
+        ppResult: *mut LPVOID,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xd2c21b26, 0x8350, 0x4bdc, 0x97, 0x6a, 0x33, 0x1c, 0xe6, 0xf4, 0xc5, 0x4c)]
+interface IDxcContainerReflection(IDxcContainerReflectionVtbl): IUnknown(IUnknownVtbl) {
+    fn Load(
+        pContainer: *mut IDxcBlob,
+    ) -> HRESULT,
+    fn GetPartCount(
+        pResult: *mut UINT32,
+    ) -> HRESULT,
+    fn GetPartKind(
+        idx: UINT32,
+        pResult: *mut UINT32,
+    ) -> HRESULT,
+    fn GetPartContent(
+        idx: UINT32,
+        ppResult: *mut *mut IDxcBlob,
+    ) -> HRESULT,
+    fn FindFirstPartKind(
+        kind: UINT32,
+        pResult: *mut UINT32,
+    ) -> HRESULT,
+    fn GetPartReflection(
+        idx: UINT32,
+        iid: REFIID,
+        ppvObject: *mut LPVOID,
+    ) -> HRESULT,
+}}
+
+type DxcCreateInstanceProc =
+    unsafe extern "system" fn(rclsid: REFCLSID, riid: REFIID, ppv: *mut LPVOID) -> HRESULT;
+
+fn create_instance<C: Class, I: Interface>() -> Result<ComPtr<I>, String> {
+    unsafe {
+        // The library is intentionally never freed, objects created from it may outlive this call.
+        let module = libloaderapi::LoadLibraryA("dxcompiler.dll\0".as_ptr() as *const _);
+        if module.is_null() {
+            return Err("Failed to load dxcompiler.dll".to_owned());
+        }
+
+        let proc_address =
+            libloaderapi::GetProcAddress(module, "DxcCreateInstance\0".as_ptr() as *const _);
+        if proc_address.is_null() {
+            return Err("Failed to find DxcCreateInstance in dxcompiler.dll".to_owned());
+        }
+        let create_instance: DxcCreateInstanceProc = mem::transmute(proc_address);
+
+        let mut instance: *mut I = ptr::null_mut();
+        let hr = create_instance(
+            &C::uuidof(),
+            &I::uuidof(),
+            &mut instance as *mut *mut _ as *mut _,
+        );
+        if FAILED(hr) {
+            return Err(format!("DxcCreateInstance failed: {:#x}", hr));
+        }
+
+        Ok(ComPtr::from_ptr(instance))
+    }
+}
+
+fn to_wide(string: &OsStr) -> Vec<u16> {
+    string.encode_wide().chain(Some(0)).collect()
+}
+
+// Compiles HLSL source to DXIL, `flags` are extra command line arguments such as -Zi. On failure
+// the error is the compiler output, like the FXC path.
+pub fn compile(
+    code: &[u8],
+    entry: &str,
+    target: &str,
+    flags: &[&str],
+    defines: &[(&str, &str)],
+    include_dir: Option<&Path>,
+) -> Result<ComPtr<IDxcBlob>, String> {
+    let compiler = create_instance::<DxcCompiler, IDxcCompiler3>()?;
+    let utils = create_instance::<DxcUtils, IDxcUtils>()?;
+
+    let mut arguments: Vec<Vec<u16>> = Vec::new();
+    arguments.push(to_wide(OsStr::new("-E")));
+    arguments.push(to_wide(OsStr::new(entry)));
+    arguments.push(to_wide(OsStr::new("-T")));
+    arguments.push(to_wide(OsStr::new(target)));
+    for flag in flags {
+        arguments.push(to_wide(OsStr::new(flag)));
+    }
+    for (name, value) in defines {
+        arguments.push(to_wide(OsStr::new("-D")));
+        arguments.push(to_wide(OsStr::new(&format!("{}={}", name, value))));
+    }
+    if let Some(include_dir) = include_dir {
+        arguments.push(to_wide(OsStr::new("-I")));
+        arguments.push(to_wide(include_dir.as_os_str()));
+    }
+    let mut argument_ptrs: Vec<LPCWSTR> = arguments.iter().map(|arg| arg.as_ptr()).collect();
+
+    // The default handler loads includes from the file system, honoring the -I directories.
+    let mut include_handler: *mut IDxcIncludeHandler = ptr::null_mut();
+    let hr = unsafe { utils.CreateDefaultIncludeHandler(&mut include_handler) };
+    if FAILED(hr) {
+        return Err(format!("Failed to create DXC include handler: {:#x}", hr));
+    }
+    let include_handler = unsafe { ComPtr::from_ptr(include_handler) };
+
+    let source = DxcBuffer {
+        Ptr: code.as_ptr() as *const _,
+        Size: code.len() as _,
+        Encoding: DXC_CP_UTF8,
+    };
+
+    let mut result: *mut IDxcResult = ptr::null_mut();
+    let hr = unsafe {
+        compiler.Compile(
+            &source,
+            argument_ptrs.as_mut_ptr(),
+            argument_ptrs.len() as _,
+            include_handler.as_ptr(),
+            &IDxcResult::uuidof(),
+            &mut result as *mut *mut _ as *mut _,
+        )
+    };
+    if FAILED(hr) {
+        return Err(format!("DXC compilation failed: {:#x}", hr));
+    }
+    let result = unsafe { ComPtr::from_ptr(result) };
+
+    let mut status = 0;
+    let hr = unsafe { result.GetStatus(&mut status) };
+    if FAILED(hr) || FAILED(status) {
+        let mut error: *mut IDxcBlobEncoding = ptr::null_mut();
+        let hr = unsafe { result.GetErrorBuffer(&mut error) };
+        if FAILED(hr) || error.is_null() {
+            return Err(format!("DXC compilation failed: {:#x}", status));
+        }
+        let message = unsafe {
+            let error = ComPtr::from_ptr(error);
+            let pointer = error.GetBufferPointer();
+            let size = error.GetBufferSize();
+            let slice = slice::from_raw_parts(pointer as *const u8, size as usize);
+            String::from_utf8_lossy(slice).into_owned()
+        };
+        return Err(message);
+    }
+
+    let mut shader: *mut IDxcBlob = ptr::null_mut();
+    let hr = unsafe { result.GetResult(&mut shader) };
+    if FAILED(hr) || shader.is_null() {
+        return Err(format!("Failed to retrieve DXC compiled shader: {:#x}", hr));
+    }
+
+    Ok(unsafe { ComPtr::from_ptr(shader) })
+}
+
+// Reflects a DXIL shader, which D3DReflect cannot read. `shader` is a DXIL container, as returned
+// by compile().
+pub fn reflect(shader: *mut IDxcBlob) -> Result<ComPtr<ID3D12ShaderReflection>, String> {
+    let container = create_instance::<DxcContainerReflection, IDxcContainerReflection>()?;
+
+    let hr = unsafe { container.Load(shader) };
+    if FAILED(hr) {
+        return Err(format!("Failed to load DXIL container: {:#x}", hr));
+    }
+
+    let mut part_index = 0;
+    let hr = unsafe { container.FindFirstPartKind(DXC_PART_DXIL, &mut part_index) };
+    if FAILED(hr) {
+        return Err(format!("Failed to find DXIL container part: {:#x}", hr));
+    }
+
+    let mut reflection: *mut ID3D12ShaderReflection = ptr::null_mut();
+    let hr = unsafe {
+        container.GetPartReflection(
+            part_index,
+            &ID3D12ShaderReflection::uuidof(),
+            &mut reflection as *mut *mut _ as *mut _,
+        )
+    };
+    if FAILED(hr) {
+        return Err(format!("Failed to reflect DXIL shader: {:#x}", hr));
+    }
+
+    Ok(unsafe { ComPtr::from_ptr(reflection) })
+}
@@ -1,11 +1,14 @@
 use crate::com::ComPtr;
 use crate::command::CommandQueue;
+use crate::sync::Event;
+
+use log::info;
 
 use winapi::ctypes::c_void;
 use winapi::shared::{
     dxgi, dxgi1_2, dxgi1_3, dxgi1_4, dxgi1_5, dxgi1_6, dxgiformat, dxgitype, minwindef,
-    windef::HWND,
-    winerror::{FAILED, SUCCEEDED},
+    windef::{HWND, RECT},
+    winerror::{self, FAILED, SUCCEEDED},
 };
 use winapi::um::{d3d12, d3dcommon};
 use winapi::Interface;
@@ -22,9 +25,39 @@ pub enum Error {
     FactoryCheckFeatureSupportFailed,
     FactoryCastFailed,
     AdapterCreateFailed,
+    AdapterNotFound,
+    AdapterSetVideoMemoryReservationFailed,
     AdapterCastFailed,
+    InvalidBackBufferCount,
+    UnsupportedAlphaMode,
     SwapchainCreateFailed,
     SwapchainCastFailed,
+    SwapchainSetFullscreenStateFailed,
+    SwapchainSetMaximumFrameLatencyFailed,
+    SwapchainSetHdrMetadataFailed,
+    SwapchainResizeFailed,
+    SwapchainGetBufferFailed,
+    // The device was removed or reset, every device object must be recreated.
+    DeviceLost,
+}
+
+#[derive(Clone, Debug)]
+pub struct AdapterInfo {
+    pub index: u32,
+    pub name: String,
+    pub vendor_id: u32,
+    pub dedicated_video_memory: usize,
+    pub shared_system_memory: usize,
+}
+
+// Presentation statistics, compare present and refresh counts between frames to detect
+// dropped frames. The sync time is a QueryPerformanceCounter value.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub present_count: u32,
+    pub present_refresh_count: u32,
+    pub sync_refresh_count: u32,
+    pub sync_qpc_time: i64,
 }
 
 pub struct Factory {
@@ -87,75 +120,125 @@ impl Factory {
         }
     }
 
+    // Highest performance hardware adapter supporting the feature level. Fails with
+    // Error::AdapterNotFound once every adapter has been enumerated.
     pub fn enum_adapter_by_gpu_preference(
         &self,
         min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
     ) -> Result<ComPtr<dxgi::IDXGIAdapter1>, Error> {
-        let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
         // Pretty much all unsafe here.
         unsafe {
-            match self.native.cast::<dxgi1_6::IDXGIFactory6>() {
-                Ok(factory6) => {
-                    let mut index = 0;
-                    loop {
-                        if SUCCEEDED(factory6.EnumAdapterByGpuPreference(
-                            index,
-                            dxgi1_6::DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE,
-                            &dxgi::IDXGIAdapter1::uuidof(),
-                            &mut adapter as *mut *mut _ as *mut *mut _,
-                        )) {
-                            index += 1;
-                            let mut desc = dxgi::DXGI_ADAPTER_DESC1 { ..mem::zeroed() };
-                            let hr = (*adapter).GetDesc1(&mut desc);
-                            if FAILED(hr) {
-                                continue;
-                            }
-
-                            // Skip the Basic Render Driver adapter.
-                            if (desc.Flags & dxgi::DXGI_ADAPTER_FLAG_SOFTWARE) != 0 {
-                                continue;
-                            }
-
-                            if SUCCEEDED(d3d12::D3D12CreateDevice(
-                                adapter as _,
-                                min_feature_level,
-                                &d3d12::ID3D12Device::uuidof(),
-                                ptr::null_mut(),
-                            )) {
-                                break;
-                            }
-                        }
-                    }
-                    if !adapter.is_null() {
-                        Ok(unsafe { ComPtr::from_ptr(adapter) })
-                    } else {
-                        Err(Error::AdapterCreateFailed)
-                    }
+            let factory6 = match self.native.cast::<dxgi1_6::IDXGIFactory6>() {
+                Ok(factory6) => factory6,
+                Err(_) => return Err(Error::FactoryCastFailed),
+            };
+
+            let mut index = 0;
+            loop {
+                let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
+                let hr = factory6.EnumAdapterByGpuPreference(
+                    index,
+                    dxgi1_6::DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE,
+                    &dxgi::IDXGIAdapter1::uuidof(),
+                    &mut adapter as *mut *mut _ as *mut *mut _,
+                );
+                if hr == winerror::DXGI_ERROR_NOT_FOUND {
+                    return Err(Error::AdapterNotFound);
+                } else if FAILED(hr) {
+                    return Err(Error::AdapterCreateFailed);
+                }
+                index += 1;
+
+                // Skipped adapters are released when going out of scope.
+                let adapter = ComPtr::from_ptr(adapter);
+                if Self::is_hardware_adapter(&adapter, min_feature_level) {
+                    return Ok(adapter);
                 }
-                Err(_) => Err(Error::FactoryCastFailed),
             }
         }
     }
 
+    // Hardware adapter supporting the feature level with the largest dedicated video memory.
     pub fn enum_adapter(
         &self,
         min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
     ) -> Result<ComPtr<dxgi::IDXGIAdapter1>, Error> {
-        // Find the adapter with the largest dedicated video memory.
-        let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
-        let mut max_dedicated_video_memeory: usize = 0;
-        let mut adapter_index = 0;
+        let mut best_adapter: Option<ComPtr<dxgi::IDXGIAdapter1>> = None;
+        let mut max_dedicated_video_memory: usize = 0;
         let mut index = 0;
         unsafe {
-            while SUCCEEDED(
-                self.native
-                    .EnumAdapters1(index, adapter as *mut *mut _ as *mut *mut _),
-            ) {
+            loop {
+                let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
+                if FAILED(
+                    self.native
+                        .EnumAdapters1(index, &mut adapter as *mut *mut _ as *mut *mut _),
+                ) {
+                    break;
+                }
                 index += 1;
 
+                let adapter = ComPtr::from_ptr(adapter);
+                if !Self::is_hardware_adapter(&adapter, min_feature_level) {
+                    continue;
+                }
+
                 let mut desc = dxgi::DXGI_ADAPTER_DESC1 { ..mem::zeroed() };
-                let hr = (*adapter).GetDesc1(&mut desc);
-                if FAILED(hr) {
+                if SUCCEEDED(adapter.GetDesc1(&mut desc))
+                    && (best_adapter.is_none()
+                        || desc.DedicatedVideoMemory > max_dedicated_video_memory)
+                {
+                    max_dedicated_video_memory = desc.DedicatedVideoMemory;
+                    best_adapter = Some(adapter);
+                }
+            }
+        }
+        best_adapter.ok_or(Error::AdapterNotFound)
+    }
+
+    // Whether the adapter is not the Basic Render Driver and can create a device at the feature
+    // level.
+    fn is_hardware_adapter(
+        adapter: &ComPtr<dxgi::IDXGIAdapter1>,
+        min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
+    ) -> bool {
+        unsafe {
+            let mut desc = dxgi::DXGI_ADAPTER_DESC1 { ..mem::zeroed() };
+            if FAILED(adapter.GetDesc1(&mut desc)) {
+                return false;
+            }
+
+            // Skip the Basic Render Driver adapter.
+            if (desc.Flags & dxgi::DXGI_ADAPTER_FLAG_SOFTWARE) != 0 {
+                return false;
+            }
+
+            SUCCEEDED(d3d12::D3D12CreateDevice(
+                adapter.as_ptr() as _,
+                min_feature_level,
+                &d3d12::ID3D12Device::uuidof(),
+                ptr::null_mut(),
+            ))
+        }
+    }
+
+    // Lists every hardware adapter, the index can be passed to Adapter::from_index.
+    pub fn enumerate_adapters(&self) -> Vec<AdapterInfo> {
+        let mut adapters = Vec::new();
+        let mut index = 0;
+        unsafe {
+            loop {
+                let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
+                if FAILED(
+                    self.native
+                        .EnumAdapters1(index, &mut adapter as *mut *mut _ as *mut *mut _),
+                ) {
+                    break;
+                }
+                index += 1;
+
+                let adapter = ComPtr::from_ptr(adapter);
+                let mut desc = dxgi::DXGI_ADAPTER_DESC1 { ..mem::zeroed() };
+                if FAILED(adapter.GetDesc1(&mut desc)) {
                     continue;
                 }
 
@@ -164,30 +247,16 @@ impl Factory {
                     continue;
                 }
 
-                if SUCCEEDED(d3d12::D3D12CreateDevice(
-                    adapter as _,
-                    min_feature_level,
-                    &d3d12::ID3D12Device::uuidof(),
-                    ptr::null_mut(),
-                )) && desc.DedicatedVideoMemory > max_dedicated_video_memeory
-                {
-                    max_dedicated_video_memeory = desc.DedicatedVideoMemory;
-                    adapter_index = index - 1;
-                }
-            }
-
-            // Need to retrieve the adapter again as it would have been reset with the last EnumAdapter1 call
-            if max_dedicated_video_memeory > 0
-                && SUCCEEDED(
-                    self.native
-                        .EnumAdapters1(adapter_index, &mut adapter as *mut *mut _ as *mut *mut _),
-                )
-            {
-                Ok(unsafe { ComPtr::from_ptr(adapter) })
-            } else {
-                Err(Error::AdapterCreateFailed)
+                adapters.push(AdapterInfo {
+                    index: index - 1,
+                    name: wide_to_string(&desc.Description),
+                    vendor_id: desc.VendorId,
+                    dedicated_video_memory: desc.DedicatedVideoMemory,
+                    shared_system_memory: desc.SharedSystemMemory,
+                });
             }
         }
+        adapters
     }
 
     pub fn enum_adapter_warp(&self) -> Result<ComPtr<dxgi::IDXGIAdapter1>, Error> {
@@ -209,6 +278,19 @@ impl Factory {
 pub struct Adapter(pub(crate) ComPtr<dxgi1_6::IDXGIAdapter4>);
 
 impl Adapter {
+    pub fn from_index(factory: &Factory, index: u32) -> Result<Self, Error> {
+        let mut adapter: *mut dxgi::IDXGIAdapter1 = ptr::null_mut();
+        let hr = unsafe {
+            factory
+                .native
+                .EnumAdapters1(index, &mut adapter as *mut *mut _ as *mut *mut _)
+        };
+        if FAILED(hr) {
+            return Err(Error::AdapterCreateFailed);
+        }
+        Self::from_adapter1(unsafe { ComPtr::from_ptr(adapter) })
+    }
+
     pub fn new(
         factory: &Factory,
         min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
@@ -222,9 +304,12 @@ impl Adapter {
                 .or_else(|_| factory.enum_adapter(min_feature_level))
                 .or_else(|_| factory.enum_adapter_warp())
         };
+        adapter.and_then(Self::from_adapter1)
+    }
 
+    fn from_adapter1(adapter: ComPtr<dxgi::IDXGIAdapter1>) -> Result<Self, Error> {
         unsafe {
-            adapter.and_then(|adapter| match adapter.cast::<dxgi1_6::IDXGIAdapter4>() {
+            match adapter.cast::<dxgi1_6::IDXGIAdapter4>() {
                 Ok(adapter4) => {
                     let mut desc = dxgi::DXGI_ADAPTER_DESC1 { ..mem::zeroed() };
                     let hr = adapter4.GetDesc1(&mut desc);
@@ -232,12 +317,8 @@ impl Adapter {
                         // This should never happen
                         panic!("Failed to get adapter description.");
                     }
-                    let device_name = {
-                        let len = desc.Description.iter().take_while(|&&c| c != 0).count();
-                        let name = <OsString as OsStringExt>::from_wide(&desc.Description[..len]);
-                        name.to_string_lossy().into_owned()
-                    };
-                    println!(
+                    let device_name = wide_to_string(&desc.Description);
+                    info!(
                         "Found D3D12 adapter '{}' with {}MB of dedicated video memory.",
                         device_name,
                         desc.DedicatedVideoMemory / 1000 / 1000
@@ -246,21 +327,198 @@ impl Adapter {
                     Ok(Adapter(adapter4))
                 }
                 Err(_) => Err(Error::AdapterCastFailed),
-            })
+            }
+        }
+    }
+
+    // Current budget and usage of both memory segment groups. The budget changes as other
+    // applications allocate, so it should be queried regularly, e.g. once per frame.
+    pub fn query_video_memory_info(&self) -> VideoMemoryInfo {
+        VideoMemoryInfo {
+            local: self.query_segment_group(MemorySegmentGroup::Local),
+            non_local: self.query_segment_group(MemorySegmentGroup::NonLocal),
+        }
+    }
+
+    fn query_segment_group(&self, group: MemorySegmentGroup) -> VideoMemorySegmentInfo {
+        let mut info = dxgi1_4::DXGI_QUERY_VIDEO_MEMORY_INFO {
+            ..unsafe { mem::zeroed() }
+        };
+        let hr = unsafe { self.0.QueryVideoMemoryInfo(0, group as _, &mut info) };
+        if FAILED(hr) {
+            panic!("Failed to query adapter video memory info.");
+        }
+        VideoMemorySegmentInfo {
+            budget: info.Budget,
+            current_usage: info.CurrentUsage,
+            available_for_reservation: info.AvailableForReservation,
+            current_reservation: info.CurrentReservation,
+        }
+    }
+
+    // Asks the OS to keep at least `bytes` of the segment group for this process, up to the
+    // available_for_reservation of the segment group.
+    pub fn set_video_memory_reservation(
+        &self,
+        group: MemorySegmentGroup,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        let hr = unsafe { self.0.SetVideoMemoryReservation(0, group as _, bytes) };
+        if SUCCEEDED(hr) {
+            Ok(())
+        } else {
+            Err(Error::AdapterSetVideoMemoryReservationFailed)
         }
     }
 }
 
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemorySegmentGroup {
+    // Video memory of discrete adapters, or all memory of integrated ones.
+    Local = dxgi1_4::DXGI_MEMORY_SEGMENT_GROUP_LOCAL,
+    // System memory visible to discrete adapters.
+    NonLocal = dxgi1_4::DXGI_MEMORY_SEGMENT_GROUP_NON_LOCAL,
+}
+
+// Sizes are in bytes. Allocating beyond the budget leads to stutter as the OS pages memory out.
+#[derive(Clone, Copy, Debug)]
+pub struct VideoMemorySegmentInfo {
+    pub budget: u64,
+    pub current_usage: u64,
+    pub available_for_reservation: u64,
+    pub current_reservation: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VideoMemoryInfo {
+    pub local: VideoMemorySegmentInfo,
+    pub non_local: VideoMemorySegmentInfo,
+}
+
+pub struct OutputInfo {
+    pub device_name: String,
+    pub desktop_coordinates: RECT,
+    pub modes: Vec<dxgi1_2::DXGI_MODE_DESC1>,
+}
+
+// Lists the outputs (monitors) attached to the adapter, with the display modes they support
+// for the given format.
+pub fn enumerate_outputs(adapter: &Adapter, format: dxgiformat::DXGI_FORMAT) -> Vec<OutputInfo> {
+    let mut outputs = Vec::new();
+    let mut index = 0;
+    unsafe {
+        loop {
+            let mut output: *mut dxgi::IDXGIOutput = ptr::null_mut();
+            if FAILED(adapter.0.EnumOutputs(index, &mut output)) {
+                break;
+            }
+            index += 1;
+
+            let output = ComPtr::from_ptr(output);
+            let mut desc = dxgi::DXGI_OUTPUT_DESC { ..mem::zeroed() };
+            if FAILED(output.GetDesc(&mut desc)) {
+                continue;
+            }
+
+            let mut modes = Vec::new();
+            if let Ok(output1) = output.cast::<dxgi1_2::IDXGIOutput1>() {
+                // First call retrieves the number of modes, second one fills them in.
+                let mut count = 0;
+                if SUCCEEDED(output1.GetDisplayModeList1(format, 0, &mut count, ptr::null_mut()))
+                    && count > 0
+                {
+                    modes.resize(count as usize, mem::zeroed());
+                    if FAILED(output1.GetDisplayModeList1(
+                        format,
+                        0,
+                        &mut count,
+                        modes.as_mut_ptr(),
+                    )) {
+                        modes.clear();
+                    }
+                    modes.truncate(count as usize);
+                }
+            }
+
+            outputs.push(OutputInfo {
+                device_name: wide_to_string(&desc.DeviceName),
+                desktop_coordinates: desc.DesktopCoordinates,
+                modes,
+            });
+        }
+    }
+    outputs
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().take_while(|&&c| c != 0).count();
+    let name = <OsString as OsStringExt>::from_wide(&wide[..len]);
+    name.to_string_lossy().into_owned()
+}
+
+// D3D12 only supports the flip presentation model. Both effects can be combined with tearing.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SwapEffect {
+    // Back buffer content is undefined after presenting, every frame must be fully rendered.
+    FlipDiscard = dxgi::DXGI_SWAP_EFFECT_FLIP_DISCARD,
+    // Back buffers keep their content, so each one holds the frame presented back buffer count
+    // presents ago. Partial presents must bring every area changed since then up to date.
+    FlipSequential = dxgi::DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
+}
+
+// CIE 1931 xy chromaticity coordinates of the mastering display's primaries and white point.
+#[derive(Clone, Copy, Debug)]
+pub struct ColorPrimaries {
+    pub red: [f32; 2],
+    pub green: [f32; 2],
+    pub blue: [f32; 2],
+    pub white_point: [f32; 2],
+}
+
+impl ColorPrimaries {
+    pub const REC709: ColorPrimaries = ColorPrimaries {
+        red: [0.64, 0.33],
+        green: [0.30, 0.60],
+        blue: [0.15, 0.06],
+        white_point: [0.3127, 0.3290],
+    };
+    pub const REC2020: ColorPrimaries = ColorPrimaries {
+        red: [0.708, 0.292],
+        green: [0.170, 0.797],
+        blue: [0.131, 0.046],
+        white_point: [0.3127, 0.3290],
+    };
+}
+
+// How the alpha channel of the back buffers is used when composing them with what is behind.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AlphaMode {
+    Unspecified = dxgi1_2::DXGI_ALPHA_MODE_UNSPECIFIED,
+    // Colors are already multiplied by alpha, needs Swapchain::new_for_composition.
+    Premultiplied = dxgi1_2::DXGI_ALPHA_MODE_PREMULTIPLIED,
+    // Colors are not multiplied by alpha, needs Swapchain::new_for_composition.
+    Straight = dxgi1_2::DXGI_ALPHA_MODE_STRAIGHT,
+    // The back buffers are opaque.
+    Ignore = dxgi1_2::DXGI_ALPHA_MODE_IGNORE,
+}
+
 pub struct SwapchainProperties {
     pub(crate) window_handle: HWND,
     pub(crate) back_buffer_count: u32,
     pub(crate) back_buffer_width: u32,
     pub(crate) back_buffer_height: u32,
     pub(crate) back_buffer_format: dxgiformat::DXGI_FORMAT,
+    pub(crate) swap_effect: SwapEffect,
+    pub(crate) alpha_mode: AlphaMode,
     pub(crate) is_tearing_supported: bool,
+    pub(crate) is_waitable: bool,
 }
 
-pub struct Swapchain(pub(crate) ComPtr<dxgi1_5::IDXGISwapChain4>);
+// The event is signaled whenever the swapchain is ready for a new frame, only when waitable.
+pub struct Swapchain(pub(crate) ComPtr<dxgi1_5::IDXGISwapChain4>, Option<Event>);
 
 impl Swapchain {
     pub fn new(
@@ -268,27 +526,14 @@ impl Swapchain {
         command_queue: &CommandQueue,
         properties: SwapchainProperties,
     ) -> Result<Self, Error> {
+        // Window swapchains cannot blend with what is behind the window.
+        match properties.alpha_mode {
+            AlphaMode::Unspecified | AlphaMode::Ignore => {}
+            _ => return Err(Error::UnsupportedAlphaMode),
+        }
+
+        let desc = Self::desc(&properties)?;
         unsafe {
-            let desc = dxgi1_2::DXGI_SWAP_CHAIN_DESC1 {
-                Width: properties.back_buffer_width,
-                Height: properties.back_buffer_height,
-                Format: properties.back_buffer_format,
-                Stereo: minwindef::FALSE,
-                SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
-                    Count: 1,
-                    Quality: 0,
-                },
-                BufferUsage: dxgitype::DXGI_USAGE_RENDER_TARGET_OUTPUT,
-                BufferCount: properties.back_buffer_count,
-                Scaling: dxgi1_2::DXGI_SCALING_STRETCH,
-                SwapEffect: dxgi::DXGI_SWAP_EFFECT_FLIP_DISCARD,
-                AlphaMode: dxgi1_2::DXGI_ALPHA_MODE_UNSPECIFIED,
-                Flags: if properties.is_tearing_supported {
-                    dxgi::DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING
-                } else {
-                    0
-                },
-            };
             let fullscreen_desc = dxgi1_2::DXGI_SWAP_CHAIN_FULLSCREEN_DESC {
                 Windowed: minwindef::TRUE,
                 ..mem::zeroed()
@@ -304,12 +549,129 @@ impl Swapchain {
             )) {
                 return Err(Error::SwapchainCreateFailed);
             }
-            let swapchain1 = unsafe { ComPtr::from_ptr(swapchain) };
-            if let Ok(swapchain4) = swapchain1.cast::<dxgi1_5::IDXGISwapChain4>() {
-                Ok(Swapchain(swapchain4))
-            } else {
-                Err(Error::SwapchainCastFailed)
+            Self::from_swapchain1(ComPtr::from_ptr(swapchain), &properties)
+        }
+    }
+
+    // Swapchain without a window, for transparent windows and overlays. Its buffers are shown
+    // once set as the content of a DirectComposition visual, so the window handle of the
+    // properties is ignored. Premultiplied and straight alpha modes require this path.
+    pub fn new_for_composition(
+        factory: &Factory,
+        command_queue: &CommandQueue,
+        properties: SwapchainProperties,
+    ) -> Result<Self, Error> {
+        let desc = Self::desc(&properties)?;
+        unsafe {
+            let mut swapchain: *mut dxgi1_2::IDXGISwapChain1 = ptr::null_mut();
+            if FAILED(factory.native.CreateSwapChainForComposition(
+                command_queue.native.as_ptr() as *mut _,
+                &desc,
+                ptr::null_mut(),
+                &mut swapchain as *mut *mut _ as *mut *mut _,
+            )) {
+                return Err(Error::SwapchainCreateFailed);
             }
+            Self::from_swapchain1(ComPtr::from_ptr(swapchain), &properties)
+        }
+    }
+
+    fn desc(properties: &SwapchainProperties) -> Result<dxgi1_2::DXGI_SWAP_CHAIN_DESC1, Error> {
+        // Flip model swapchains need at least two buffers.
+        if properties.back_buffer_count < 2
+            || properties.back_buffer_count > dxgi::DXGI_MAX_SWAP_CHAIN_BUFFERS
+        {
+            return Err(Error::InvalidBackBufferCount);
+        }
+
+        Ok(dxgi1_2::DXGI_SWAP_CHAIN_DESC1 {
+            Width: properties.back_buffer_width,
+            Height: properties.back_buffer_height,
+            Format: properties.back_buffer_format,
+            Stereo: minwindef::FALSE,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            BufferUsage: dxgitype::DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            BufferCount: properties.back_buffer_count,
+            Scaling: dxgi1_2::DXGI_SCALING_STRETCH,
+            SwapEffect: properties.swap_effect as _,
+            AlphaMode: properties.alpha_mode as _,
+            Flags: Self::flags(properties.is_tearing_supported, properties.is_waitable),
+        })
+    }
+
+    fn from_swapchain1(
+        swapchain1: ComPtr<dxgi1_2::IDXGISwapChain1>,
+        properties: &SwapchainProperties,
+    ) -> Result<Self, Error> {
+        let swapchain4 = match swapchain1.cast::<dxgi1_5::IDXGISwapChain4>() {
+            Ok(swapchain4) => swapchain4,
+            Err(_) => return Err(Error::SwapchainCastFailed),
+        };
+
+        // Queue at most one frame so waiting on the event keeps input latency low.
+        let frame_latency_event = if properties.is_waitable {
+            if FAILED(unsafe { swapchain4.SetMaximumFrameLatency(1) }) {
+                return Err(Error::SwapchainSetMaximumFrameLatencyFailed);
+            }
+            Some(Event {
+                handle: unsafe { swapchain4.GetFrameLatencyWaitableObject() },
+            })
+        } else {
+            None
+        };
+
+        Ok(Swapchain(swapchain4, frame_latency_event))
+    }
+
+    // Creation flags, which ResizeBuffers() must be given again. Tearing and frame latency waits
+    // are independent and can be combined.
+    pub fn flags(is_tearing_supported: bool, is_waitable: bool) -> u32 {
+        let mut flags = 0;
+        if is_tearing_supported {
+            flags |= dxgi::DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING;
+        }
+        if is_waitable {
+            flags |= dxgi::DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT;
+        }
+        flags
+    }
+
+    // Every reference to the back buffers must have been released before resizing. The flags must
+    // match the ones the swapchain was created with.
+    pub fn resize(
+        &self,
+        back_buffer_count: u32,
+        width: u32,
+        height: u32,
+        format: dxgiformat::DXGI_FORMAT,
+        is_tearing_supported: bool,
+        is_waitable: bool,
+    ) -> Result<(), Error> {
+        let hr = unsafe {
+            self.0.ResizeBuffers(
+                back_buffer_count,
+                width,
+                height,
+                format,
+                Self::flags(is_tearing_supported, is_waitable),
+            )
+        };
+        if hr == winerror::DXGI_ERROR_DEVICE_REMOVED || hr == winerror::DXGI_ERROR_DEVICE_RESET {
+            Err(Error::DeviceLost)
+        } else if FAILED(hr) {
+            Err(Error::SwapchainResizeFailed)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Blocks until the swapchain can accept a new frame, returns right away if not waitable.
+    pub fn wait_for_frame_latency(&self) {
+        if let Some(event) = self.1 {
+            event.wait(1000);
         }
     }
 
@@ -367,7 +729,145 @@ impl Swapchain {
         color_space
     }
 
+    // Describes the mastering display and the content light levels to the output, so it can tone
+    // map HDR10 content. Luminances are in nits.
+    pub fn set_hdr_metadata(
+        &self,
+        max_nits: f32,
+        min_nits: f32,
+        max_content_light_level: u16,
+        max_frame_average_light_level: u16,
+        primaries: &ColorPrimaries,
+    ) -> Result<(), Error> {
+        // Chromaticities are in units of 0.00002, luminances in units of 0.0001 nits.
+        let chromaticity = |xy: [f32; 2]| [(xy[0] * 50000.0) as u16, (xy[1] * 50000.0) as u16];
+        let mut metadata = dxgi1_5::DXGI_HDR_METADATA_HDR10 {
+            RedPrimary: chromaticity(primaries.red),
+            GreenPrimary: chromaticity(primaries.green),
+            BluePrimary: chromaticity(primaries.blue),
+            WhitePoint: chromaticity(primaries.white_point),
+            MaxMasteringLuminance: (max_nits * 10000.0) as _,
+            MinMasteringLuminance: (min_nits * 10000.0) as _,
+            MaxContentLightLevel: max_content_light_level,
+            MaxFrameAverageLightLevel: max_frame_average_light_level,
+        };
+        let hr = unsafe {
+            self.0.SetHDRMetaData(
+                dxgi1_5::DXGI_HDR_METADATA_TYPE_HDR10,
+                mem::size_of::<dxgi1_5::DXGI_HDR_METADATA_HDR10>() as _,
+                &mut metadata as *mut _ as *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(())
+        } else {
+            Err(Error::SwapchainSetHdrMetadataFailed)
+        }
+    }
+
+    // Stops sending HDR metadata to the output, for SDR color spaces.
+    pub fn clear_hdr_metadata(&self) -> Result<(), Error> {
+        let hr = unsafe {
+            self.0
+                .SetHDRMetaData(dxgi1_5::DXGI_HDR_METADATA_TYPE_NONE, 0, ptr::null_mut())
+        };
+        if SUCCEEDED(hr) {
+            Ok(())
+        } else {
+            Err(Error::SwapchainSetHdrMetadataFailed)
+        }
+    }
+
+    pub fn set_fullscreen_state(&self, fullscreen: bool) -> Result<(), Error> {
+        let hr = unsafe {
+            self.0.SetFullscreenState(
+                if fullscreen {
+                    minwindef::TRUE
+                } else {
+                    minwindef::FALSE
+                },
+                ptr::null_mut(),
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(())
+        } else {
+            Err(Error::SwapchainSetFullscreenStateFailed)
+        }
+    }
+
+    // Whether the swapchain is in exclusive fullscreen mode.
+    pub fn is_fullscreen(&self) -> bool {
+        let mut fullscreen = minwindef::FALSE;
+        let hr = unsafe { self.0.GetFullscreenState(&mut fullscreen, ptr::null_mut()) };
+        SUCCEEDED(hr) && fullscreen != minwindef::FALSE
+    }
+
+    // Size of the desktop area of the output the swapchain is mostly displayed on.
+    pub fn get_output_desktop_size(&self) -> Option<(u32, u32)> {
+        let mut output: *mut dxgi::IDXGIOutput = ptr::null_mut();
+        unsafe {
+            if FAILED(self.0.GetContainingOutput(&mut output)) {
+                return None;
+            }
+            let output = ComPtr::from_ptr(output);
+            let mut desc = dxgi::DXGI_OUTPUT_DESC { ..mem::zeroed() };
+            if FAILED(output.GetDesc(&mut desc)) {
+                return None;
+            }
+            let rect = desc.DesktopCoordinates;
+            Some(((rect.right - rect.left) as _, (rect.bottom - rect.top) as _))
+        }
+    }
+
+    // Not available until the swapchain has presented, nor while composed in a window on
+    // systems that do not report windowed statistics.
+    pub fn get_frame_statistics(&self) -> Option<FrameStats> {
+        let mut stats = dxgi::DXGI_FRAME_STATISTICS {
+            ..unsafe { mem::zeroed() }
+        };
+        let hr = unsafe { self.0.GetFrameStatistics(&mut stats) };
+        if SUCCEEDED(hr) {
+            Some(FrameStats {
+                present_count: stats.PresentCount,
+                present_refresh_count: stats.PresentRefreshCount,
+                sync_refresh_count: stats.SyncRefreshCount,
+                sync_qpc_time: unsafe { *stats.SyncQPCTime.QuadPart() },
+            })
+        } else {
+            None
+        }
+    }
+
     pub fn get_current_back_buffer_index(&self) -> u32 {
         unsafe { self.0.GetCurrentBackBufferIndex() }
     }
+
+    pub fn get_buffer(&self, index: u32) -> Result<ComPtr<d3d12::ID3D12Resource>, Error> {
+        let mut buffer: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let hr = unsafe {
+            self.0.GetBuffer(
+                index,
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut buffer as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(unsafe { ComPtr::from_ptr(buffer) })
+        } else if hr == winerror::DXGI_ERROR_DEVICE_REMOVED
+            || hr == winerror::DXGI_ERROR_DEVICE_RESET
+        {
+            Err(Error::DeviceLost)
+        } else {
+            Err(Error::SwapchainGetBufferFailed)
+        }
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        if let Some(event) = self.1 {
+            event.close();
+        }
+    }
 }
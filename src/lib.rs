@@ -1,21 +1,83 @@
+//! D3D12 renderer. `Context` is the single supported entry point: it owns the device, queues,
+//! swapchain and frame resources, and recovers from device removal. Create one from
+//! `ContextParams`, preferably through `ContextParams::builder`, with `Context::new` for a winit
+//! window, `Context::from_hwnd` for any other Win32 window or `Context::new_headless` to render
+//! without presenting. Buffers, textures, pipelines and other GPU objects are created from
+//! `Context::device`.
+
 use winapi::shared::dxgiformat;
 use winapi::um::d3dcommon;
 
 mod buffer;
+#[cfg(feature = "renderdoc")]
+mod capture;
 mod com;
 mod command;
 mod context;
 mod descriptor;
 mod device;
+mod dxc;
 mod dxgi;
+mod hash;
 mod memory;
+mod mesh;
+mod mips;
 mod pso;
+mod query;
+mod raytracing;
 mod resource;
 mod root_signature;
 mod sync;
+mod texture;
 mod timer;
 
-pub use self::context::Context;
+pub use self::buffer::{
+    BufferCopyRegion, BufferUsage, Error as BufferError, GpuBuffer, IndexType, ReadbackBuffer,
+    UploadBuffer,
+};
+#[cfg(feature = "renderdoc")]
+pub use self::capture::{Error as CaptureError, RenderDoc};
+pub use self::command::{
+    CommandAllocator, CommandAllocatorError, CommandContext, CommandContextPool, CommandList,
+    CommandListError, CommandListType, CommandQueue, CommandQueueError, CommandSignature,
+    CommandSignatureBuilder, CommandSignatureError, GraphicsCommandList,
+};
+pub use self::context::{ClearFlags, Context, ContextError};
+pub use self::descriptor::{
+    CpuDescriptor, CpuDescriptorPool, DescriptorHeap, Error as DescriptorError, GpuDescriptor,
+};
+pub use self::device::{
+    DebugMessage, Device, Error as DeviceError, D3D12_MESH_SHADER_TIER_1,
+    D3D12_MESH_SHADER_TIER_NOT_SUPPORTED, D3D12_RAYTRACING_TIER_1_0, D3D12_RAYTRACING_TIER_1_1,
+    D3D12_RAYTRACING_TIER_NOT_SUPPORTED,
+};
+pub use self::dxgi::{
+    AdapterInfo, FrameStats, MemorySegmentGroup, OutputInfo, SwapEffect, VideoMemoryInfo,
+    VideoMemorySegmentInfo,
+};
+pub use self::memory::{Error as MemoryError, Heap};
+pub use self::mesh::{Mesh, StandardVertex};
+pub use self::mips::MipGenerator;
+pub use self::pso::{
+    BlendState, ConstantBufferLayout, DepthStencilState, InputElement, InputLayout, PSOKind,
+    PipelineStage, PipelineState, PipelineStateBuilder, PipelineStateFlags, PsoCache,
+    RasterizerState, Shader, ShaderCompilerFlags, ShaderModel, ShaderReflectionError,
+    ShaderVariable,
+};
+pub use self::query::{Error as QueryError, QueryHeap, TimestampQueries};
+pub use self::raytracing::{
+    AccelerationStructure, Error as RaytracingError, Instance, RaytracingDevice, TriangleGeometry,
+};
+pub use self::resource::{GpuResource, GpuResourceError};
+pub use self::root_signature::{
+    DescriptorRange, DescriptorRangeFlags, DescriptorRangeType, RootDescriptorFlags,
+    RootDescriptorType, RootParameter, RootSignature, RootSignatureBuilder, RootSignatureError,
+    StaticSampler,
+};
+pub use self::sync::{Error as FenceError, GpuTimeline};
+#[cfg(feature = "image")]
+pub use self::texture::load_image;
+pub use self::texture::{load_dds, Error as TextureError};
 pub use self::timer::GameTimer;
 
 use bitflags::bitflags;
@@ -24,6 +86,10 @@ bitflags! {
     pub struct ContextFlags: u32 {
         const ALLOW_TEARING = 0b0000_0001;
         const ENABLE_HDR = 0b0000_0010;
+        const ENABLE_COMPUTE_QUEUE = 0b0000_0100;
+        const ENABLE_COPY_QUEUE = 0b0000_1000;
+        const USE_WARP = 0b0001_0000;
+        const WAITABLE_SWAPCHAIN = 0b0010_0000;
     }
 }
 
@@ -32,11 +98,45 @@ pub struct ContextParams {
     pub window_title: String,
     pub window_width: u32,
     pub window_height: u32,
+    /// Swapchain format, one of R8G8B8A8_UNORM, B8G8R8A8_UNORM (or their sRGB variants),
+    /// R10G10B10A2_UNORM or R16G16B16A16_FLOAT. Defaults to R10G10B10A2_UNORM for HDR10 output
+    /// when ContextFlags::ENABLE_HDR is set, and to R8G8B8A8_UNORM otherwise.
     pub back_buffer_format: dxgiformat::DXGI_FORMAT,
     pub depth_buffer_format: dxgiformat::DXGI_FORMAT,
+    /// Presentation model of the swapchain (defaults to SwapEffect::FlipDiscard).
+    pub swap_effect: SwapEffect,
+    /// Number of swapchain buffers, must be in the range 2..=16 (defaults to 3).
     pub back_buffer_count: u32,
+    /// Number of samples per pixel, 1 disables multisampling.
+    pub msaa_sample_count: u32,
     pub min_feature_level: d3dcommon::D3D_FEATURE_LEVEL,
+    /// Index of the adapter to render with, as listed by Context::enumerate_adapters. None picks
+    /// the highest performance hardware adapter. Ignored with ContextFlags::USE_WARP.
+    pub adapter_index: Option<u32>,
     pub flags: ContextFlags,
+    /// Debug layer settings, only used in debug builds.
+    pub debug: DebugConfig,
+}
+
+/// Debug layer behavior. Breaks on errors and corruptions by default, GPU based validation
+/// catches more errors but slows the GPU down considerably.
+#[derive(Clone, Copy, Debug)]
+pub struct DebugConfig {
+    pub break_on_error: bool,
+    pub break_on_corruption: bool,
+    pub break_on_warning: bool,
+    pub gpu_based_validation: bool,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            break_on_error: true,
+            break_on_corruption: true,
+            break_on_warning: false,
+            gpu_based_validation: false,
+        }
+    }
 }
 
 impl ContextParams {
@@ -50,12 +150,156 @@ impl ContextParams {
             window_title,
             window_width,
             window_height,
-            back_buffer_format: dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM,
+            back_buffer_format: if flags.contains(ContextFlags::ENABLE_HDR) {
+                dxgiformat::DXGI_FORMAT_R10G10B10A2_UNORM
+            } else {
+                dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM
+            },
             depth_buffer_format: dxgiformat::DXGI_FORMAT_D32_FLOAT,
+            swap_effect: SwapEffect::FlipDiscard,
             back_buffer_count: 3,
+            msaa_sample_count: 1,
             min_feature_level: d3dcommon::D3D_FEATURE_LEVEL_11_0,
+            adapter_index: None,
             flags,
+            debug: DebugConfig::default(),
+        }
+    }
+
+    pub fn builder(window_title: &str) -> ContextParamsBuilder {
+        ContextParamsBuilder::new(window_title)
+    }
+}
+
+/// Builds validated ContextParams, starting from the ContextParams::new defaults for a 1280x720
+/// window without flags.
+#[derive(Clone, Debug)]
+pub struct ContextParamsBuilder {
+    params: ContextParams,
+    // Unless set, picked from the HDR flag when building.
+    back_buffer_format: Option<dxgiformat::DXGI_FORMAT>,
+}
+
+impl ContextParamsBuilder {
+    pub fn new(window_title: &str) -> Self {
+        Self {
+            params: ContextParams::new(window_title.to_string(), 1280, 720, ContextFlags::empty()),
+            back_buffer_format: None,
+        }
+    }
+
+    pub fn window_size(mut self, width: u32, height: u32) -> Self {
+        self.params.window_width = width;
+        self.params.window_height = height;
+        self
+    }
+
+    pub fn back_buffer_format(mut self, format: dxgiformat::DXGI_FORMAT) -> Self {
+        self.back_buffer_format = Some(format);
+        self
+    }
+
+    pub fn depth_format(mut self, format: dxgiformat::DXGI_FORMAT) -> Self {
+        self.params.depth_buffer_format = format;
+        self
+    }
+
+    pub fn swap_effect(mut self, swap_effect: SwapEffect) -> Self {
+        self.params.swap_effect = swap_effect;
+        self
+    }
+
+    pub fn back_buffer_count(mut self, count: u32) -> Self {
+        self.params.back_buffer_count = count;
+        self
+    }
+
+    pub fn msaa_sample_count(mut self, count: u32) -> Self {
+        self.params.msaa_sample_count = count;
+        self
+    }
+
+    pub fn min_feature_level(mut self, feature_level: d3dcommon::D3D_FEATURE_LEVEL) -> Self {
+        self.params.min_feature_level = feature_level;
+        self
+    }
+
+    pub fn adapter_index(mut self, index: u32) -> Self {
+        self.params.adapter_index = Some(index);
+        self
+    }
+
+    pub fn debug_config(mut self, debug: DebugConfig) -> Self {
+        self.params.debug = debug;
+        self
+    }
+
+    /// Adds to the flags set so far.
+    pub fn flags(mut self, flags: ContextFlags) -> Self {
+        self.params.flags |= flags;
+        self
+    }
+
+    pub fn hdr(self, enable: bool) -> Self {
+        self.flag(ContextFlags::ENABLE_HDR, enable)
+    }
+
+    pub fn allow_tearing(self, enable: bool) -> Self {
+        self.flag(ContextFlags::ALLOW_TEARING, enable)
+    }
+
+    pub fn compute_queue(self, enable: bool) -> Self {
+        self.flag(ContextFlags::ENABLE_COMPUTE_QUEUE, enable)
+    }
+
+    pub fn copy_queue(self, enable: bool) -> Self {
+        self.flag(ContextFlags::ENABLE_COPY_QUEUE, enable)
+    }
+
+    pub fn warp(self, enable: bool) -> Self {
+        self.flag(ContextFlags::USE_WARP, enable)
+    }
+
+    pub fn waitable_swapchain(self, enable: bool) -> Self {
+        self.flag(ContextFlags::WAITABLE_SWAPCHAIN, enable)
+    }
+
+    fn flag(mut self, flag: ContextFlags, enable: bool) -> Self {
+        self.params.flags.set(flag, enable);
+        self
+    }
+
+    /// Fails on combinations Context creation would reject or silently downgrade, such as HDR
+    /// with an 8 bit back buffer format. MSAA support is only known once the device exists.
+    pub fn build(self) -> Result<ContextParams, ContextError> {
+        let mut params = self.params;
+        let hdr = params.flags.contains(ContextFlags::ENABLE_HDR);
+        params.back_buffer_format = match self.back_buffer_format {
+            Some(format) => format,
+            None if hdr => dxgiformat::DXGI_FORMAT_R10G10B10A2_UNORM,
+            None => dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM,
+        };
+
+        match params.back_buffer_format {
+            dxgiformat::DXGI_FORMAT_R10G10B10A2_UNORM
+            | dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT => {}
+            dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM
+            | dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+            | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM
+            | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+                if !hdr => {}
+            _ => return Err(ContextError::UnsupportedBackBufferFormat),
+        }
+        if params.back_buffer_count < 2 || params.back_buffer_count > 16 {
+            return Err(ContextError::InvalidBackBufferCount);
+        }
+        if params.msaa_sample_count == 0 {
+            return Err(ContextError::UnsupportedMsaaSampleCount);
+        }
+        if params.window_width == 0 || params.window_height == 0 {
+            return Err(ContextError::InvalidWindowSize);
         }
+        Ok(params)
     }
 }
 
@@ -6,11 +6,13 @@ use winapi::shared::{dxgiformat, dxgitype, winerror::SUCCEEDED};
 use winapi::um::d3d12;
 use winapi::Interface;
 
+use std::collections::VecDeque;
 use std::ptr;
 
 #[derive(Debug)]
 pub enum Error {
     MemoryCreateFailed,
+    HeapCreateFailed,
 }
 
 #[derive(Copy, Clone)]
@@ -75,6 +77,55 @@ impl Memory {
     }
 }
 
+// Explicit heap that placed resources are sub-allocated from, see GpuResource::create_placed.
+// Offsets into it must honor the alignment reported by Device::resource_allocation_info.
+pub struct Heap {
+    pub(crate) native: ComPtr<d3d12::ID3D12Heap>,
+    size: u64,
+}
+
+impl Heap {
+    pub fn new(
+        device: &Device,
+        size: u64,
+        type_: d3d12::D3D12_HEAP_TYPE,
+        flags: d3d12::D3D12_HEAP_FLAGS,
+    ) -> Result<Self, Error> {
+        let mut heap: *mut d3d12::ID3D12Heap = ptr::null_mut();
+        let heap_desc = d3d12::D3D12_HEAP_DESC {
+            SizeInBytes: size,
+            Properties: d3d12::D3D12_HEAP_PROPERTIES {
+                Type: type_,
+                CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+                CreationNodeMask: 1,
+                VisibleNodeMask: 1,
+            },
+            Alignment: u64::from(d3d12::D3D12_DEFAULT_RESOURCE_PLACEMENT_ALIGNMENT),
+            Flags: flags,
+        };
+        let hr = unsafe {
+            device.native.CreateHeap(
+                &heap_desc,
+                &d3d12::ID3D12Heap::uuidof(),
+                &mut heap as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(Heap {
+                native: unsafe { ComPtr::from_ptr(heap) },
+                size,
+            })
+        } else {
+            Err(Error::HeapCreateFailed)
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
 pub struct MemoryAllocator {
     device: Device,
     allocations: Vec<Memory>,
@@ -97,3 +148,146 @@ impl MemoryAllocator {
         self.allocations.last().unwrap()
     }
 }
+
+// Size of each upload heap page the linear allocator sub-allocates from.
+const LINEAR_ALLOCATOR_PAGE_SIZE: u64 = 0x0020_0000; // 2MB
+
+pub struct LinearAllocation {
+    pub(crate) resource: ComPtr<d3d12::ID3D12Resource>,
+    pub offset: u64,
+    pub cpu_ptr: *mut u8,
+    pub gpu_va: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+}
+
+struct LinearAllocationPage {
+    memory: Memory,
+    cpu_ptr: *mut u8,
+    gpu_va: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    size: u64,
+}
+
+impl LinearAllocationPage {
+    fn new(device: &Device, size: u64) -> Self {
+        let memory = Memory::new(device, AllocationType::CpuWritable, size).unwrap();
+        // Upload pages stay mapped for their whole lifetime.
+        let cpu_ptr = memory.resource.map().unwrap();
+        let gpu_va = memory.resource.gpu_virtual_address();
+        LinearAllocationPage {
+            memory,
+            cpu_ptr,
+            gpu_va,
+            size,
+        }
+    }
+}
+
+impl Drop for LinearAllocationPage {
+    fn drop(&mut self) {
+        self.memory.resource.unmap();
+    }
+}
+
+// Sub-allocates transient upload memory out of large pages, recycling each page once the
+// fence value of the frame that last used it has been reached.
+pub struct LinearAllocator {
+    device: Device,
+    current_page: Option<LinearAllocationPage>,
+    current_offset: u64,
+    full_pages: Vec<LinearAllocationPage>,
+    large_pages: Vec<LinearAllocationPage>,
+    retired_pages: VecDeque<(u64, LinearAllocationPage)>,
+    retired_large_pages: VecDeque<(u64, LinearAllocationPage)>,
+    available_pages: Vec<LinearAllocationPage>,
+}
+
+impl LinearAllocator {
+    pub fn new(device: Device) -> Self {
+        LinearAllocator {
+            device,
+            current_page: None,
+            current_offset: 0,
+            full_pages: Vec::new(),
+            large_pages: Vec::new(),
+            retired_pages: VecDeque::new(),
+            retired_large_pages: VecDeque::new(),
+            available_pages: Vec::new(),
+        }
+    }
+
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> LinearAllocation {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+        let aligned_size = (size + alignment - 1) & !(alignment - 1);
+
+        // Allocations bigger than a page get a dedicated page of their own.
+        if aligned_size > LINEAR_ALLOCATOR_PAGE_SIZE {
+            let page = LinearAllocationPage::new(&self.device, aligned_size);
+            let allocation = LinearAllocation {
+                resource: page.memory.resource.native.clone(),
+                offset: 0,
+                cpu_ptr: page.cpu_ptr,
+                gpu_va: page.gpu_va,
+            };
+            self.large_pages.push(page);
+            return allocation;
+        }
+
+        self.current_offset = (self.current_offset + alignment - 1) & !(alignment - 1);
+        let needs_new_page = match self.current_page {
+            Some(ref page) => self.current_offset + aligned_size > page.size,
+            None => true,
+        };
+        if needs_new_page {
+            if let Some(page) = self.current_page.take() {
+                self.full_pages.push(page);
+            }
+            let page = self.available_pages.pop().unwrap_or_else(|| {
+                LinearAllocationPage::new(&self.device, LINEAR_ALLOCATOR_PAGE_SIZE)
+            });
+            self.current_page = Some(page);
+            self.current_offset = 0;
+        }
+
+        let page = self.current_page.as_ref().unwrap();
+        let allocation = LinearAllocation {
+            resource: page.memory.resource.native.clone(),
+            offset: self.current_offset,
+            cpu_ptr: unsafe { page.cpu_ptr.add(self.current_offset as usize) },
+            gpu_va: page.gpu_va + self.current_offset,
+        };
+        self.current_offset += aligned_size;
+        allocation
+    }
+
+    // Retires every page used since the last call behind `fence_value` and recycles the
+    // pages whose fence value has been reached by the GPU.
+    pub fn cleanup_used_pages(&mut self, fence_value: u64, completed_fence_value: u64) {
+        if let Some(page) = self.current_page.take() {
+            self.full_pages.push(page);
+        }
+        self.current_offset = 0;
+
+        for page in self.full_pages.drain(..) {
+            self.retired_pages.push_back((fence_value, page));
+        }
+        for page in self.large_pages.drain(..) {
+            self.retired_large_pages.push_back((fence_value, page));
+        }
+
+        while let Some(&(retired_fence_value, _)) = self.retired_pages.front() {
+            if retired_fence_value > completed_fence_value {
+                break;
+            }
+            let (_, page) = self.retired_pages.pop_front().unwrap();
+            self.available_pages.push(page);
+        }
+        while let Some(&(retired_fence_value, _)) = self.retired_large_pages.front() {
+            if retired_fence_value > completed_fence_value {
+                break;
+            }
+            self.retired_large_pages.pop_front();
+        }
+    }
+}
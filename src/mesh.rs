@@ -0,0 +1,137 @@
+use crate::buffer::{Error, GpuBuffer};
+use crate::command::{CommandContextPool, CommandQueue};
+use crate::device::Device;
+
+use winapi::shared::dxgiformat;
+use winapi::um::d3d12;
+
+// Vertex with the attributes most lit and textured meshes need, matching an HLSL input of
+// float3 POSITION, float3 NORMAL and float2 TEXCOORD.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StandardVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl StandardVertex {
+    // Semantic names are static, so the descriptions can be kept around freely.
+    pub fn input_layout() -> [d3d12::D3D12_INPUT_ELEMENT_DESC; 3] {
+        let element =
+            |semantic_name: &'static [u8], format, offset| d3d12::D3D12_INPUT_ELEMENT_DESC {
+                SemanticName: semantic_name.as_ptr() as *const _,
+                SemanticIndex: 0,
+                Format: format,
+                InputSlot: 0,
+                AlignedByteOffset: offset,
+                InputSlotClass: d3d12::D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+                InstanceDataStepRate: 0,
+            };
+        [
+            element(b"POSITION\0", dxgiformat::DXGI_FORMAT_R32G32B32_FLOAT, 0),
+            element(b"NORMAL\0", dxgiformat::DXGI_FORMAT_R32G32B32_FLOAT, 12),
+            element(b"TEXCOORD\0", dxgiformat::DXGI_FORMAT_R32G32_FLOAT, 24),
+        ]
+    }
+}
+
+// Indexed triangle list of standard vertices. Triangles are wound clockwise when seen from their
+// front, the D3D12 default, in a left-handed coordinate system.
+pub struct Mesh {
+    pub vertex_buffer: GpuBuffer,
+    pub index_buffer: GpuBuffer,
+    pub index_count: u32,
+}
+
+impl Mesh {
+    // Uploads the geometry, blocking until the copies have completed.
+    pub fn new(
+        device: &Device,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+        vertices: &[StandardVertex],
+        indices: &[u16],
+    ) -> Result<Self, Error> {
+        Ok(Mesh {
+            vertex_buffer: GpuBuffer::vertex(device, command_queue, pool, vertices)?,
+            index_buffer: GpuBuffer::index(device, command_queue, pool, indices)?,
+            index_count: indices.len() as u32,
+        })
+    }
+
+    // Unit cube centered on the origin, each face with its own normals and full texture.
+    pub fn cube(
+        device: &Device,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+    ) -> Result<Self, Error> {
+        // Normal, then right and up directions as seen from outside the face.
+        let faces = [
+            ([0.0, 0.0, -1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, 1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ];
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for &(normal, right, up) in faces.iter() {
+            push_face(&mut vertices, &mut indices, normal, right, up);
+        }
+        Self::new(device, command_queue, pool, &vertices, &indices)
+    }
+
+    // Unit quad in the XY plane centered on the origin, facing the negative Z axis.
+    pub fn quad(
+        device: &Device,
+        command_queue: &mut CommandQueue,
+        pool: &mut CommandContextPool,
+    ) -> Result<Self, Error> {
+        let mut vertices = Vec::with_capacity(4);
+        let mut indices = Vec::with_capacity(6);
+        push_face(
+            &mut vertices,
+            &mut indices,
+            [0.0, 0.0, -1.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+        );
+        // The quad lies on the origin rather than half a unit in front of it.
+        for vertex in vertices.iter_mut() {
+            vertex.position[2] = 0.0;
+        }
+        Self::new(device, command_queue, pool, &vertices, &indices)
+    }
+}
+
+// Appends a unit square half a unit away from the origin along `normal`, with the top left
+// corner of the texture at its top left.
+fn push_face(
+    vertices: &mut Vec<StandardVertex>,
+    indices: &mut Vec<u16>,
+    normal: [f32; 3],
+    right: [f32; 3],
+    up: [f32; 3],
+) {
+    let base = vertices.len() as u16;
+    let corners = [
+        (-0.5, -0.5, [0.0, 1.0]),
+        (-0.5, 0.5, [0.0, 0.0]),
+        (0.5, 0.5, [1.0, 0.0]),
+        (0.5, -0.5, [1.0, 1.0]),
+    ];
+    for &(x, y, uv) in corners.iter() {
+        let mut position = [0.0; 3];
+        for axis in 0..3 {
+            position[axis] = normal[axis] * 0.5 + right[axis] * x + up[axis] * y;
+        }
+        vertices.push(StandardVertex {
+            position,
+            normal,
+            uv,
+        });
+    }
+    indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+}
@@ -0,0 +1,225 @@
+use crate::command::GraphicsCommandList;
+use crate::descriptor::DescriptorHeap;
+use crate::device::Device;
+use crate::pso::{PipelineStage, PipelineState, Shader, ShaderCompilerFlags, ShaderModel};
+use crate::resource::GpuResource;
+use crate::root_signature::{
+    DescriptorRange, DescriptorRangeType, RootParameter, RootSignature, RootSignatureBuilder,
+    StaticSampler,
+};
+
+use winapi::um::d3d12;
+
+use std::mem;
+
+// Each dispatch downsamples one mip level into the next with a bilinear sample.
+const GENERATE_MIPS_SHADER: &str = r#"
+Texture2D<float4> SourceMip : register(t0);
+RWTexture2D<float4> DestMip : register(u0);
+SamplerState LinearClamp : register(s0);
+
+cbuffer Constants : register(b0)
+{
+    float2 TexelSize;
+}
+
+[numthreads(8, 8, 1)]
+void main(uint3 id : SV_DispatchThreadID)
+{
+    float2 uv = TexelSize * (id.xy + 0.5);
+    DestMip[id.xy] = SourceMip.SampleLevel(LinearClamp, uv, 0);
+}
+"#;
+
+const THREAD_GROUP_SIZE: u32 = 8;
+
+// Enough for a 16384x16384 texture, the largest 2D texture D3D12 supports.
+const MAX_MIP_LEVELS: u32 = 15;
+
+// Fills the mip chain of 2D textures from their top mip with compute passes. Textures must have
+// been created with D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS in a typed UAV compatible format
+// such as R8G8B8A8_UNORM.
+pub struct MipGenerator {
+    root_signature: RootSignature,
+    pipeline_state: PipelineState,
+    descriptor_heap: DescriptorHeap,
+}
+
+impl MipGenerator {
+    pub fn new(device: &Device) -> Self {
+        let srv_ranges = [DescriptorRange::new(DescriptorRangeType::Srv, 1, 0, 0, 0)];
+        let uav_ranges = [DescriptorRange::new(DescriptorRangeType::Uav, 1, 0, 0, 0)];
+        let root_signature = RootSignatureBuilder::default()
+            .add_parameter(RootParameter::new_constants(
+                2,
+                0,
+                0,
+                d3d12::D3D12_SHADER_VISIBILITY_ALL,
+            ))
+            .add_parameter(RootParameter::new_descriptor_table(
+                d3d12::D3D12_SHADER_VISIBILITY_ALL,
+                &srv_ranges,
+            ))
+            .add_parameter(RootParameter::new_descriptor_table(
+                d3d12::D3D12_SHADER_VISIBILITY_ALL,
+                &uav_ranges,
+            ))
+            .add_static_sampler(StaticSampler::linear_clamp(
+                0,
+                0,
+                d3d12::D3D12_SHADER_VISIBILITY_ALL,
+            ))
+            .build(device.clone())
+            .expect("Failed to create mip generation root signature.");
+
+        let shader = Shader::from_code(
+            GENERATE_MIPS_SHADER.as_bytes(),
+            "main",
+            PipelineStage::Compute,
+            ShaderModel::V5_0,
+            ShaderCompilerFlags::empty(),
+            &[],
+            None,
+        );
+        let pipeline_state = PipelineState::create_compute(device, &root_signature, &shader);
+
+        // One SRV and one UAV per generated mip level.
+        let descriptor_heap = DescriptorHeap::new(
+            device,
+            d3d12::D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+            d3d12::D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+            2 * MAX_MIP_LEVELS,
+            "Adamant::MipGenerator::DescriptorHeap",
+        )
+        .expect("Failed to create mip generation descriptor heap.");
+
+        MipGenerator {
+            root_signature,
+            pipeline_state,
+            descriptor_heap,
+        }
+    }
+
+    // Records the passes into `command_list` and leaves the texture in the pixel shader resource
+    // state. The descriptors are rewritten by every call, so the recorded work must have
+    // completed on the GPU before generating mips again.
+    pub fn generate(
+        &self,
+        device: &Device,
+        command_list: &GraphicsCommandList,
+        texture: &mut GpuResource,
+    ) {
+        let desc = unsafe { texture.native.GetDesc() };
+        let mip_levels = u32::from(desc.MipLevels);
+        assert!(
+            mip_levels <= MAX_MIP_LEVELS + 1,
+            "too many mip levels to generate"
+        );
+        if mip_levels < 2 {
+            return;
+        }
+        assert!(
+            texture.subresource_states.is_empty(),
+            "mips are generated from textures whose subresources share a state"
+        );
+
+        // Every mip starts as a UAV, each one becomes readable once it has been written.
+        let mut barriers = Vec::with_capacity(1);
+        if texture.usage_state != d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS {
+            barriers.push(transition_barrier(
+                texture,
+                d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                texture.usage_state,
+                d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            ));
+        }
+
+        command_list.set_compute_root_signature(&self.root_signature);
+        command_list.set_pipeline_state(&self.pipeline_state);
+        command_list.set_descriptor_heaps(&[&self.descriptor_heap]);
+
+        for mip in 1..mip_levels {
+            barriers.push(transition_barrier(
+                texture,
+                mip - 1,
+                d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                d3d12::D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+            ));
+            command_list.insert_resource_barriers(&barriers);
+            barriers.clear();
+
+            let srv_index = 2 * (mip - 1);
+            let uav_index = srv_index + 1;
+            texture.create_mip_shader_resource_view(
+                device,
+                self.descriptor_heap.cpu_descriptor(srv_index),
+                desc.Format,
+                mip - 1,
+                1,
+            );
+            texture.create_mip_unordered_access_view(
+                device,
+                self.descriptor_heap.cpu_descriptor(uav_index),
+                desc.Format,
+                mip,
+            );
+
+            let width = u32::max((desc.Width >> mip) as u32, 1);
+            let height = u32::max(desc.Height >> mip, 1);
+            let texel_size = [1.0 / width as f32, 1.0 / height as f32];
+            command_list.set_compute_root_32bit_constants(
+                0,
+                &[texel_size[0].to_bits(), texel_size[1].to_bits()],
+                0,
+            );
+            command_list.set_compute_root_descriptor_table(
+                1,
+                self.descriptor_heap.gpu_descriptor(srv_index),
+            );
+            command_list.set_compute_root_descriptor_table(
+                2,
+                self.descriptor_heap.gpu_descriptor(uav_index),
+            );
+            command_list.dispatch(
+                (width + THREAD_GROUP_SIZE - 1) / THREAD_GROUP_SIZE,
+                (height + THREAD_GROUP_SIZE - 1) / THREAD_GROUP_SIZE,
+                1,
+            );
+        }
+
+        barriers.push(transition_barrier(
+            texture,
+            mip_levels - 1,
+            d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            d3d12::D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+        ));
+        barriers.push(transition_barrier(
+            texture,
+            d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            d3d12::D3D12_RESOURCE_STATE_NON_PIXEL_SHADER_RESOURCE,
+            d3d12::D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        ));
+        command_list.insert_resource_barriers(&barriers);
+        texture.usage_state = d3d12::D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE;
+    }
+}
+
+fn transition_barrier(
+    resource: &GpuResource,
+    subresource: u32,
+    state_before: d3d12::D3D12_RESOURCE_STATES,
+    state_after: d3d12::D3D12_RESOURCE_STATES,
+) -> d3d12::D3D12_RESOURCE_BARRIER {
+    let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+        Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+        Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        u: unsafe { mem::zeroed() },
+    };
+    *unsafe { barrier.u.Transition_mut() } = d3d12::D3D12_RESOURCE_TRANSITION_BARRIER {
+        pResource: resource.native.as_ptr(),
+        Subresource: subresource,
+        StateBefore: state_before,
+        StateAfter: state_after,
+    };
+    barrier
+}
@@ -1,17 +1,25 @@
 use crate::com::ComPtr;
 use crate::device::Device;
+use crate::dxc;
+use crate::hash::StableHasher;
 use crate::root_signature::RootSignature;
 use crate::Blob;
 
 use winapi::shared::{
     dxgiformat, dxgitype,
-    winerror::{FAILED, SUCCEEDED},
+    minwindef::{self, LPCVOID, UINT},
+    winerror::{self, FAILED, HRESULT, SUCCEEDED},
 };
-use winapi::um::{d3d12, d3dcommon, d3dcompiler};
+use winapi::um::{d3d12, d3d12shader, d3dcommon, d3dcompiler, winnt::LPCSTR};
 use winapi::Interface;
 
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
 use std::{mem, ptr, slice};
 
 use bitflags::bitflags;
@@ -26,6 +34,49 @@ bitflags! {
     pub struct ShaderCompilerFlags: u32 {
         const DEBUG = d3dcompiler::D3DCOMPILE_DEBUG;
         const SKIP_OPTIMIZATION = d3dcompiler::D3DCOMPILE_SKIP_OPTIMIZATION;
+        const PACK_MATRIX_ROW_MAJOR = d3dcompiler::D3DCOMPILE_PACK_MATRIX_ROW_MAJOR;
+        const PACK_MATRIX_COLUMN_MAJOR = d3dcompiler::D3DCOMPILE_PACK_MATRIX_COLUMN_MAJOR;
+        const ENABLE_STRICTNESS = d3dcompiler::D3DCOMPILE_ENABLE_STRICTNESS;
+        // The optimization level is a two bit field, level 1 is zero and the compiler default.
+        const OPTIMIZATION_LEVEL0 = d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL0;
+        const OPTIMIZATION_LEVEL1 = d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL1;
+        const OPTIMIZATION_LEVEL2 = d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL2;
+        const OPTIMIZATION_LEVEL3 = d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL3;
+        const WARNINGS_ARE_ERRORS = d3dcompiler::D3DCOMPILE_WARNINGS_ARE_ERRORS;
+    }
+}
+
+impl ShaderCompilerFlags {
+    // Equivalent DXC command line arguments.
+    fn dxc_arguments(self) -> Vec<&'static str> {
+        let mut arguments = Vec::new();
+        if self.contains(ShaderCompilerFlags::DEBUG) {
+            arguments.push("-Zi");
+        }
+        if self.contains(ShaderCompilerFlags::SKIP_OPTIMIZATION) {
+            arguments.push("-Od");
+        } else {
+            let level = self.bits() & ShaderCompilerFlags::OPTIMIZATION_LEVEL2.bits();
+            arguments.push(match level {
+                d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL0 => "-O0",
+                d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL2 => "-O2",
+                d3dcompiler::D3DCOMPILE_OPTIMIZATION_LEVEL3 => "-O3",
+                _ => "-O1",
+            });
+        }
+        if self.contains(ShaderCompilerFlags::PACK_MATRIX_ROW_MAJOR) {
+            arguments.push("-Zpr");
+        }
+        if self.contains(ShaderCompilerFlags::PACK_MATRIX_COLUMN_MAJOR) {
+            arguments.push("-Zpc");
+        }
+        if self.contains(ShaderCompilerFlags::ENABLE_STRICTNESS) {
+            arguments.push("-Ges");
+        }
+        if self.contains(ShaderCompilerFlags::WARNINGS_ARE_ERRORS) {
+            arguments.push("-WX");
+        }
+        arguments
     }
 }
 
@@ -39,16 +90,206 @@ pub enum PipelineStage {
     Compute,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ShaderModel {
     V5_0,
     V5_1,
     V6_0,
+    V6_1,
+    V6_2,
+    V6_3,
+    V6_4,
+    V6_5,
+}
+
+// ID3DInclude implementation resolving #include "..." directives relative to the including file,
+// then to a base directory, and #include <...> directives relative to the base directory only.
+#[repr(C)]
+struct ShaderInclude {
+    base: d3dcommon::ID3DInclude,
+    include_dir: PathBuf,
+    // Directory and contents of the opened files, keyed by the data pointer handed to the
+    // compiler, which passes it back as the parent of nested includes.
+    files: RefCell<HashMap<usize, (PathBuf, Vec<u8>)>>,
+}
+
+static SHADER_INCLUDE_VTBL: d3dcommon::ID3DIncludeVtbl = d3dcommon::ID3DIncludeVtbl {
+    Open: shader_include_open,
+    Close: shader_include_close,
+};
+
+impl ShaderInclude {
+    fn new(include_dir: &Path) -> Self {
+        ShaderInclude {
+            base: d3dcommon::ID3DInclude {
+                lpVtbl: &SHADER_INCLUDE_VTBL,
+            },
+            include_dir: include_dir.to_path_buf(),
+            files: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+unsafe extern "system" fn shader_include_open(
+    this: *mut d3dcommon::ID3DInclude,
+    include_type: d3dcommon::D3D_INCLUDE_TYPE,
+    file_name: LPCSTR,
+    parent_data: LPCVOID,
+    data: *mut LPCVOID,
+    bytes: *mut UINT,
+) -> HRESULT {
+    let include = &*(this as *const ShaderInclude);
+    let file_name = CStr::from_ptr(file_name).to_string_lossy();
+
+    // The parent is unknown for includes of the source being compiled, which lives in
+    // include_dir when compiled from a file.
+    let parent_dir = if include_type == d3dcommon::D3D_INCLUDE_LOCAL {
+        include
+            .files
+            .borrow()
+            .get(&(parent_data as usize))
+            .map(|(dir, _)| dir.clone())
+    } else {
+        None
+    };
+
+    let path = parent_dir
+        .iter()
+        .chain(Some(&include.include_dir))
+        .map(|dir| dir.join(&*file_name))
+        .find(|path| path.is_file());
+    let path = match path {
+        Some(path) => path,
+        None => return winerror::E_FAIL,
+    };
+
+    match fs::read(&path) {
+        Ok(contents) => {
+            *data = contents.as_ptr() as LPCVOID;
+            *bytes = contents.len() as _;
+            let dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+            include
+                .files
+                .borrow_mut()
+                .insert(contents.as_ptr() as usize, (dir, contents));
+            winerror::S_OK
+        }
+        Err(_) => winerror::E_FAIL,
+    }
+}
+
+unsafe extern "system" fn shader_include_close(
+    this: *mut d3dcommon::ID3DInclude,
+    data: LPCVOID,
+) -> HRESULT {
+    let include = &*(this as *const ShaderInclude);
+    include.files.borrow_mut().remove(&(data as usize));
+    winerror::S_OK
+}
+
+// Vertex shader input read from the shader's input signature, see Shader::reflect_input_layout.
+pub struct InputElement {
+    pub semantic_name: CString,
+    pub semantic_index: u32,
+    pub format: dxgiformat::DXGI_FORMAT,
+}
+
+impl InputElement {
+    // Per vertex element of input slot 0, right after the previous element. The description
+    // points to the semantic name, so it is only valid as long as this element.
+    pub fn desc(&self) -> d3d12::D3D12_INPUT_ELEMENT_DESC {
+        d3d12::D3D12_INPUT_ELEMENT_DESC {
+            SemanticName: self.semantic_name.as_ptr(),
+            SemanticIndex: self.semantic_index,
+            Format: self.format,
+            InputSlot: 0,
+            AlignedByteOffset: d3d12::D3D12_APPEND_ALIGNED_ELEMENT,
+            InputSlotClass: d3d12::D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+            InstanceDataStepRate: 0,
+        }
+    }
+}
+
+// Owns the semantic names of a vertex input layout, so its descriptions stay valid for as long
+// as the layout. Pass descs() to PipelineStateBuilder::with_input_layout.
+#[derive(Default)]
+pub struct InputLayout {
+    elements: Vec<InputElement>,
+    descs: Vec<d3d12::D3D12_INPUT_ELEMENT_DESC>,
+}
+
+impl InputLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Appends a per vertex element of input slot 0, right after the previous element.
+    pub fn element(
+        mut self,
+        semantic_name: &str,
+        semantic_index: u32,
+        format: dxgiformat::DXGI_FORMAT,
+    ) -> Self {
+        let element = InputElement {
+            semantic_name: CString::new(semantic_name).expect("Semantic name contains a nul byte."),
+            semantic_index,
+            format,
+        };
+        // The name lives on the heap, the pointer survives moves of the element.
+        self.descs.push(element.desc());
+        self.elements.push(element);
+        self
+    }
+
+    pub fn elements(&self) -> &[InputElement] {
+        &self.elements
+    }
+
+    pub fn descs(&self) -> &[d3d12::D3D12_INPUT_ELEMENT_DESC] {
+        &self.descs
+    }
+}
+
+// Variable of a constant buffer, offsets and sizes are in bytes.
+#[derive(Clone, Debug)]
+pub struct ShaderVariable {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub class: d3dcommon::D3D_SHADER_VARIABLE_CLASS,
+    pub type_: d3dcommon::D3D_SHADER_VARIABLE_TYPE,
+    pub rows: u32,
+    pub columns: u32,
+    // Array length, 0 when the variable is not an array.
+    pub elements: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConstantBufferLayout {
+    pub name: String,
+    pub size: u32,
+    pub variables: Vec<ShaderVariable>,
+}
+
+impl ConstantBufferLayout {
+    pub fn variable(&self, name: &str) -> Option<&ShaderVariable> {
+        self.variables.iter().find(|variable| variable.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderReflectionError {
+    // Carries why the DXC container reflection failed, when D3DReflect failed as well.
+    ReflectFailed(String),
+    GetDescFailed,
 }
 
-#[repr(transparent)]
 pub struct Shader {
     bytecode: d3d12::D3D12_SHADER_BYTECODE,
+    // Owns the memory the bytecode points to.
+    blob: Blob,
+    // Reflected on first use.
+    constant_buffers: RefCell<Option<Vec<ConstantBufferLayout>>>,
 }
 
 impl Shader {
@@ -60,6 +301,8 @@ impl Shader {
                     pShaderBytecode: blob.0.GetBufferPointer(),
                 }
             },
+            blob,
+            constant_buffers: RefCell::new(None),
         }
     }
 
@@ -69,6 +312,8 @@ impl Shader {
         stage: PipelineStage,
         model: ShaderModel,
         flags: ShaderCompilerFlags,
+        defines: &[(&str, &str)],
+        include_dir: Option<&Path>,
     ) -> Shader {
         let target = {
             let stage = match stage {
@@ -82,11 +327,62 @@ impl Shader {
                 ShaderModel::V5_0 => "5_0",
                 ShaderModel::V5_1 => "5_1",
                 ShaderModel::V6_0 => "6_0",
+                ShaderModel::V6_1 => "6_1",
+                ShaderModel::V6_2 => "6_2",
+                ShaderModel::V6_3 => "6_3",
+                ShaderModel::V6_4 => "6_4",
+                ShaderModel::V6_5 => "6_5",
             };
 
-            format!("{}_{}\0", stage, model)
+            format!("{}_{}", stage, model)
         };
 
+        // FXC cannot emit Shader Model 6, those go through DXC instead.
+        if model >= ShaderModel::V6_0 {
+            let shader = dxc::compile(
+                code,
+                entry,
+                &target,
+                &flags.dxc_arguments(),
+                defines,
+                include_dir,
+            )
+            .unwrap_or_else(|message| panic!("Failed to compile shader: {}", message));
+            // IDxcBlob shares its IID and layout with ID3DBlob.
+            let shader = shader.into_ptr() as *mut d3dcommon::ID3DBlob;
+            return Self::from_blob(Blob(unsafe { ComPtr::from_ptr(shader) }));
+        }
+        let target = format!("{}\0", target);
+        let entry = CString::new(entry).expect("entry point should not contain nul bytes");
+
+        // The macro strings must stay alive until compilation is done.
+        let define_strings: Vec<(CString, CString)> = defines
+            .iter()
+            .map(|(name, value)| {
+                (
+                    CString::new(*name).expect("define name should not contain nul bytes"),
+                    CString::new(*value).expect("define value should not contain nul bytes"),
+                )
+            })
+            .collect();
+        let mut macros: Vec<d3dcommon::D3D_SHADER_MACRO> = define_strings
+            .iter()
+            .map(|(name, value)| d3dcommon::D3D_SHADER_MACRO {
+                Name: name.as_ptr(),
+                Definition: value.as_ptr(),
+            })
+            .collect();
+        // The macro array is terminated by a null entry.
+        macros.push(d3dcommon::D3D_SHADER_MACRO {
+            Name: ptr::null(),
+            Definition: ptr::null(),
+        });
+
+        let mut include = include_dir.map(ShaderInclude::new);
+        let include_ptr = include
+            .as_mut()
+            .map_or(ptr::null_mut(), |include| include as *mut _ as *mut _);
+
         let mut shader: *mut d3dcommon::ID3DBlob = ptr::null_mut();
         let mut error: *mut d3dcommon::ID3DBlob = ptr::null_mut();
 
@@ -94,9 +390,9 @@ impl Shader {
             d3dcompiler::D3DCompile(
                 code.as_ptr() as *const _,
                 code.len() as _,
-                ptr::null(),     // Source Name: NOT USED
-                ptr::null(),     // Defines: NOT USED
-                ptr::null_mut(), // Includes: NOT USED
+                ptr::null(), // Source Name: NOT USED
+                macros.as_ptr(),
+                include_ptr,
                 entry.as_ptr() as *const _,
                 target.as_ptr() as *const _,
                 flags.bits(),
@@ -121,17 +417,300 @@ impl Shader {
         Self::from_blob(Blob(unsafe { ComPtr::from_ptr(shader) }))
     }
 
+    // Input layout matching the vertex shader's input signature, without system values such as
+    // SV_VertexID. Shader Model 6 shaders are reflected through dxcompiler.dll.
+    pub fn reflect_input_layout(&self) -> Result<Vec<InputElement>, ShaderReflectionError> {
+        let (reflection, shader_desc) = self.reflect()?;
+
+        let mut elements = Vec::with_capacity(shader_desc.InputParameters as usize);
+        for index in 0..shader_desc.InputParameters {
+            let mut parameter: d3d12shader::D3D12_SIGNATURE_PARAMETER_DESC =
+                unsafe { mem::zeroed() };
+            if FAILED(unsafe { reflection.GetInputParameterDesc(index, &mut parameter) }) {
+                return Err(ShaderReflectionError::GetDescFailed);
+            }
+            // System values are generated by the input assembler, not read from vertex buffers.
+            if parameter.SystemValueType != d3dcommon::D3D_NAME_UNDEFINED {
+                continue;
+            }
+
+            elements.push(InputElement {
+                semantic_name: unsafe { CStr::from_ptr(parameter.SemanticName) }.to_owned(),
+                semantic_index: parameter.SemanticIndex,
+                format: input_format(parameter.ComponentType, parameter.Mask),
+            });
+        }
+        Ok(elements)
+    }
+
+    // Layout of every constant buffer the shader uses, with the same restrictions as
+    // reflect_input_layout. Lets named parameters be written at their offsets in an upload buffer.
+    pub fn reflect_constant_buffers(
+        &self,
+    ) -> Result<Ref<[ConstantBufferLayout]>, ShaderReflectionError> {
+        if self.constant_buffers.borrow().is_none() {
+            let constant_buffers = self.reflect_constant_buffer_layouts()?;
+            *self.constant_buffers.borrow_mut() = Some(constant_buffers);
+        }
+        Ok(Ref::map(
+            self.constant_buffers.borrow(),
+            |constant_buffers| constant_buffers.as_ref().unwrap().as_slice(),
+        ))
+    }
+
+    fn reflect_constant_buffer_layouts(
+        &self,
+    ) -> Result<Vec<ConstantBufferLayout>, ShaderReflectionError> {
+        let (reflection, shader_desc) = self.reflect()?;
+
+        let mut constant_buffers = Vec::with_capacity(shader_desc.ConstantBuffers as usize);
+        for index in 0..shader_desc.ConstantBuffers {
+            unsafe {
+                // Objects returned by the reflection interface are owned by it, not ref counted.
+                let constant_buffer = &*reflection.GetConstantBufferByIndex(index);
+                let mut buffer_desc: d3d12shader::D3D12_SHADER_BUFFER_DESC = mem::zeroed();
+                if FAILED(constant_buffer.GetDesc(&mut buffer_desc)) {
+                    return Err(ShaderReflectionError::GetDescFailed);
+                }
+                // Texture and structured buffers have no constant layout to fill in.
+                if buffer_desc.Type != d3dcommon::D3D_CT_CBUFFER {
+                    continue;
+                }
+
+                let mut variables = Vec::with_capacity(buffer_desc.Variables as usize);
+                for variable_index in 0..buffer_desc.Variables {
+                    let variable = &*constant_buffer.GetVariableByIndex(variable_index);
+                    let mut variable_desc: d3d12shader::D3D12_SHADER_VARIABLE_DESC = mem::zeroed();
+                    let mut type_desc: d3d12shader::D3D12_SHADER_TYPE_DESC = mem::zeroed();
+                    if FAILED(variable.GetDesc(&mut variable_desc))
+                        || FAILED((*variable.GetType()).GetDesc(&mut type_desc))
+                    {
+                        return Err(ShaderReflectionError::GetDescFailed);
+                    }
+                    variables.push(ShaderVariable {
+                        name: CStr::from_ptr(variable_desc.Name)
+                            .to_string_lossy()
+                            .into_owned(),
+                        offset: variable_desc.StartOffset,
+                        size: variable_desc.Size,
+                        class: type_desc.Class,
+                        type_: type_desc.Type,
+                        rows: type_desc.Rows,
+                        columns: type_desc.Columns,
+                        elements: type_desc.Elements,
+                    });
+                }
+
+                constant_buffers.push(ConstantBufferLayout {
+                    name: CStr::from_ptr(buffer_desc.Name)
+                        .to_string_lossy()
+                        .into_owned(),
+                    size: buffer_desc.Size,
+                    variables,
+                });
+            }
+        }
+        Ok(constant_buffers)
+    }
+
+    // D3DReflect only reads FXC bytecode, DXIL goes through the DXC container reflection.
+    fn reflect(
+        &self,
+    ) -> Result<
+        (
+            ComPtr<d3d12shader::ID3D12ShaderReflection>,
+            d3d12shader::D3D12_SHADER_DESC,
+        ),
+        ShaderReflectionError,
+    > {
+        let mut reflection: *mut d3d12shader::ID3D12ShaderReflection = ptr::null_mut();
+        let hr = unsafe {
+            d3dcompiler::D3DReflect(
+                self.bytecode.pShaderBytecode,
+                self.bytecode.BytecodeLength,
+                &d3d12shader::ID3D12ShaderReflection::uuidof(),
+                &mut reflection as *mut *mut _ as *mut *mut _,
+            )
+        };
+        let reflection = if SUCCEEDED(hr) {
+            unsafe { ComPtr::from_ptr(reflection) }
+        } else {
+            // IDxcBlob shares its IID and layout with ID3DBlob.
+            dxc::reflect(self.blob.0.as_ptr() as *mut dxc::IDxcBlob)
+                .map_err(ShaderReflectionError::ReflectFailed)?
+        };
+
+        let mut shader_desc: d3d12shader::D3D12_SHADER_DESC = unsafe { mem::zeroed() };
+        if FAILED(unsafe { reflection.GetDesc(&mut shader_desc) }) {
+            return Err(ShaderReflectionError::GetDescFailed);
+        }
+        Ok((reflection, shader_desc))
+    }
+
+    // Includes are resolved relative to `include_dir`, or to the directory of the file if none.
     pub fn from_file(
+        path: &Path,
         entry: &str,
         stage: PipelineStage,
         model: ShaderModel,
         flags: ShaderCompilerFlags,
+        defines: &[(&str, &str)],
+        include_dir: Option<&Path>,
     ) -> Shader {
-        let file = File::open("foo.txt").unwrap();
+        let file = File::open(path).unwrap();
         let mut buf_reader = BufReader::new(file);
         let mut contents = Vec::new();
         buf_reader.read_to_end(&mut contents).unwrap();
-        Self::from_code(&contents, entry, stage, model, flags)
+        let include_dir = include_dir.or_else(|| path.parent());
+        Self::from_code(&contents, entry, stage, model, flags, defines, include_dir)
+    }
+}
+
+// 32 bit format with as many components as the signature mask has bits set.
+fn input_format(
+    component_type: d3dcommon::D3D_REGISTER_COMPONENT_TYPE,
+    mask: u8,
+) -> dxgiformat::DXGI_FORMAT {
+    let formats = match component_type {
+        d3dcommon::D3D_REGISTER_COMPONENT_FLOAT32 => [
+            dxgiformat::DXGI_FORMAT_R32_FLOAT,
+            dxgiformat::DXGI_FORMAT_R32G32_FLOAT,
+            dxgiformat::DXGI_FORMAT_R32G32B32_FLOAT,
+            dxgiformat::DXGI_FORMAT_R32G32B32A32_FLOAT,
+        ],
+        d3dcommon::D3D_REGISTER_COMPONENT_UINT32 => [
+            dxgiformat::DXGI_FORMAT_R32_UINT,
+            dxgiformat::DXGI_FORMAT_R32G32_UINT,
+            dxgiformat::DXGI_FORMAT_R32G32B32_UINT,
+            dxgiformat::DXGI_FORMAT_R32G32B32A32_UINT,
+        ],
+        d3dcommon::D3D_REGISTER_COMPONENT_SINT32 => [
+            dxgiformat::DXGI_FORMAT_R32_SINT,
+            dxgiformat::DXGI_FORMAT_R32G32_SINT,
+            dxgiformat::DXGI_FORMAT_R32G32B32_SINT,
+            dxgiformat::DXGI_FORMAT_R32G32B32A32_SINT,
+        ],
+        _ => return dxgiformat::DXGI_FORMAT_UNKNOWN,
+    };
+    match mask.count_ones() {
+        count @ 1..=4 => formats[count as usize - 1],
+        _ => dxgiformat::DXGI_FORMAT_UNKNOWN,
+    }
+}
+
+// Rasterizer presets for PipelineStateBuilder::with_rasterizer_state. Clockwise triangles are
+// front facing, as in the D3D12 defaults.
+pub struct RasterizerState;
+
+impl RasterizerState {
+    // Solid fill with back faces culled, the D3D12 default.
+    pub fn default_solid() -> d3d12::D3D12_RASTERIZER_DESC {
+        d3d12::D3D12_RASTERIZER_DESC {
+            FillMode: d3d12::D3D12_FILL_MODE_SOLID,
+            CullMode: d3d12::D3D12_CULL_MODE_BACK,
+            FrontCounterClockwise: minwindef::FALSE,
+            DepthBias: d3d12::D3D12_DEFAULT_DEPTH_BIAS as _,
+            DepthBiasClamp: d3d12::D3D12_DEFAULT_DEPTH_BIAS_CLAMP,
+            SlopeScaledDepthBias: d3d12::D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS,
+            DepthClipEnable: minwindef::TRUE,
+            MultisampleEnable: minwindef::FALSE,
+            AntialiasedLineEnable: minwindef::FALSE,
+            ForcedSampleCount: 0,
+            ConservativeRaster: d3d12::D3D12_CONSERVATIVE_RASTERIZATION_MODE_OFF,
+        }
+    }
+
+    pub fn wireframe() -> d3d12::D3D12_RASTERIZER_DESC {
+        d3d12::D3D12_RASTERIZER_DESC {
+            FillMode: d3d12::D3D12_FILL_MODE_WIREFRAME,
+            CullMode: d3d12::D3D12_CULL_MODE_NONE,
+            ..Self::default_solid()
+        }
+    }
+
+    pub fn no_cull() -> d3d12::D3D12_RASTERIZER_DESC {
+        d3d12::D3D12_RASTERIZER_DESC {
+            CullMode: d3d12::D3D12_CULL_MODE_NONE,
+            ..Self::default_solid()
+        }
+    }
+
+    pub fn cull_front() -> d3d12::D3D12_RASTERIZER_DESC {
+        d3d12::D3D12_RASTERIZER_DESC {
+            CullMode: d3d12::D3D12_CULL_MODE_FRONT,
+            ..Self::default_solid()
+        }
+    }
+}
+
+// Blend presets for PipelineStateBuilder::with_blend_state, applied to every render target.
+pub struct BlendState;
+
+impl BlendState {
+    // Writes the source color as is, the D3D12 default.
+    pub fn opaque() -> d3d12::D3D12_BLEND_DESC {
+        let render_target = d3d12::D3D12_RENDER_TARGET_BLEND_DESC {
+            BlendEnable: minwindef::FALSE,
+            LogicOpEnable: minwindef::FALSE,
+            SrcBlend: d3d12::D3D12_BLEND_ONE,
+            DestBlend: d3d12::D3D12_BLEND_ZERO,
+            BlendOp: d3d12::D3D12_BLEND_OP_ADD,
+            SrcBlendAlpha: d3d12::D3D12_BLEND_ONE,
+            DestBlendAlpha: d3d12::D3D12_BLEND_ZERO,
+            BlendOpAlpha: d3d12::D3D12_BLEND_OP_ADD,
+            LogicOp: d3d12::D3D12_LOGIC_OP_NOOP,
+            RenderTargetWriteMask: d3d12::D3D12_COLOR_WRITE_ENABLE_ALL as _,
+        };
+        d3d12::D3D12_BLEND_DESC {
+            AlphaToCoverageEnable: minwindef::FALSE,
+            IndependentBlendEnable: minwindef::FALSE,
+            RenderTarget: [render_target; 8],
+        }
+    }
+
+    // Blends non premultiplied colors over the render target by their alpha.
+    pub fn alpha_blend() -> d3d12::D3D12_BLEND_DESC {
+        let mut desc = Self::opaque();
+        for render_target in desc.RenderTarget.iter_mut() {
+            render_target.BlendEnable = minwindef::TRUE;
+            render_target.SrcBlend = d3d12::D3D12_BLEND_SRC_ALPHA;
+            render_target.DestBlend = d3d12::D3D12_BLEND_INV_SRC_ALPHA;
+            render_target.DestBlendAlpha = d3d12::D3D12_BLEND_INV_SRC_ALPHA;
+        }
+        desc
+    }
+}
+
+// Depth stencil presets for PipelineStateBuilder::with_depth_stencil_state.
+pub struct DepthStencilState;
+
+impl DepthStencilState {
+    // Depth test and writes with a less comparison and no stencil, the D3D12 default.
+    pub fn default_depth() -> d3d12::D3D12_DEPTH_STENCIL_DESC {
+        let face = d3d12::D3D12_DEPTH_STENCILOP_DESC {
+            StencilFailOp: d3d12::D3D12_STENCIL_OP_KEEP,
+            StencilDepthFailOp: d3d12::D3D12_STENCIL_OP_KEEP,
+            StencilPassOp: d3d12::D3D12_STENCIL_OP_KEEP,
+            StencilFunc: d3d12::D3D12_COMPARISON_FUNC_ALWAYS,
+        };
+        d3d12::D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: minwindef::TRUE,
+            DepthWriteMask: d3d12::D3D12_DEPTH_WRITE_MASK_ALL,
+            DepthFunc: d3d12::D3D12_COMPARISON_FUNC_LESS,
+            StencilEnable: minwindef::FALSE,
+            StencilReadMask: d3d12::D3D12_DEFAULT_STENCIL_READ_MASK as _,
+            StencilWriteMask: d3d12::D3D12_DEFAULT_STENCIL_WRITE_MASK as _,
+            FrontFace: face,
+            BackFace: face,
+        }
+    }
+
+    pub fn disabled() -> d3d12::D3D12_DEPTH_STENCIL_DESC {
+        d3d12::D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: minwindef::FALSE,
+            DepthWriteMask: d3d12::D3D12_DEPTH_WRITE_MASK_ZERO,
+            ..Self::default_depth()
+        }
     }
 }
 
@@ -140,9 +719,13 @@ pub enum PSOKind {
     Compute,
 }
 
-#[repr(transparent)]
 pub struct PipelineStateBuilder {
     desc: d3d12::D3D12_GRAPHICS_PIPELINE_STATE_DESC,
+    // Only used by PSOKind::Compute, along with the root signature, node mask and flags.
+    cs: d3d12::D3D12_SHADER_BYTECODE,
+    root_signature_hash: u64,
+    // Storage for the input layout set by with_input_elements.
+    input_element_descs: Vec<d3d12::D3D12_INPUT_ELEMENT_DESC>,
 }
 
 impl PipelineStateBuilder {
@@ -240,6 +823,14 @@ impl PipelineStateBuilder {
         self
     }
 
+    // Typically fed with Shader::reflect_input_layout. The elements must outlive build().
+    pub fn with_input_elements(mut self, elements: &[InputElement]) -> PipelineStateBuilder {
+        self.input_element_descs = elements.iter().map(InputElement::desc).collect();
+        self.desc.InputLayout.NumElements = self.input_element_descs.len() as _;
+        self.desc.InputLayout.pInputElementDescs = self.input_element_descs.as_ptr();
+        self
+    }
+
     pub fn with_vertex_shader(mut self, shader: &Shader) -> PipelineStateBuilder {
         self.desc.VS = shader.bytecode;
         self
@@ -250,29 +841,144 @@ impl PipelineStateBuilder {
         self
     }
 
-    pub fn with_root_signature(mut self, root_signature: RootSignature) -> PipelineStateBuilder {
-        self.desc.pRootSignature = root_signature.0.as_ptr();
+    pub fn with_compute_shader(mut self, shader: &Shader) -> PipelineStateBuilder {
+        self.cs = shader.bytecode;
+        self
+    }
+
+    // The root signature must outlive the built pipeline state.
+    pub fn with_root_signature(mut self, root_signature: &RootSignature) -> PipelineStateBuilder {
+        self.desc.pRootSignature = root_signature.native.as_ptr();
+        self.root_signature_hash = root_signature.hash;
         self
     }
 
     pub fn build(self, device: &Device, kind: PSOKind) -> PipelineState {
-        let mut pso: *mut d3d12::ID3D12PipelineState = ptr::null_mut();
         match kind {
-            PSOKind::Graphics => unsafe {
-                if FAILED(device.native.CreateGraphicsPipelineState(
-                    &self.desc,
-                    &d3d12::ID3D12RootSignature::uuidof(),
-                    &mut pso as *mut *mut _ as *mut *mut _,
-                )) {
-                    panic!("Failed to create D3D12 graphics pipeline state object.");
+            PSOKind::Graphics => {
+                let mut pso: *mut d3d12::ID3D12PipelineState = ptr::null_mut();
+                unsafe {
+                    if FAILED(device.native.CreateGraphicsPipelineState(
+                        &self.desc,
+                        &d3d12::ID3D12PipelineState::uuidof(),
+                        &mut pso as *mut *mut _ as *mut *mut _,
+                    )) {
+                        panic!("Failed to create D3D12 graphics pipeline state object.");
+                    }
                 }
-            },
-            PSOKind::Compute => unimplemented!(),
+                PipelineState(unsafe { ComPtr::from_ptr(pso) })
+            }
+            PSOKind::Compute => PipelineState::create_compute_from_desc(
+                device,
+                &d3d12::D3D12_COMPUTE_PIPELINE_STATE_DESC {
+                    pRootSignature: self.desc.pRootSignature,
+                    CS: self.cs,
+                    NodeMask: self.desc.NodeMask,
+                    CachedPSO: unsafe { mem::zeroed() },
+                    Flags: self.desc.Flags,
+                },
+            ),
         }
+    }
 
-        // TODO: Cache compiled psos
+    // Hashes the description by value: shaders by bytecode contents, the root signature by its
+    // serialized blob. Identical descriptions hash the same across runs and compiler versions,
+    // as the hash names pipelines in the on-disk library.
+    fn hash_desc(&self) -> u64 {
+        let desc = &self.desc;
+        let mut hasher = StableHasher::default();
 
-        PipelineState(unsafe { ComPtr::from_ptr(pso) })
+        for shader in &[desc.VS, desc.PS, desc.DS, desc.HS, desc.GS] {
+            if !shader.pShaderBytecode.is_null() {
+                let bytecode = unsafe {
+                    slice::from_raw_parts(
+                        shader.pShaderBytecode as *const u8,
+                        shader.BytecodeLength,
+                    )
+                };
+                bytecode.hash(&mut hasher);
+            } else {
+                0usize.hash(&mut hasher);
+            }
+        }
+        self.root_signature_hash.hash(&mut hasher);
+
+        let blend = &desc.BlendState;
+        blend.AlphaToCoverageEnable.hash(&mut hasher);
+        blend.IndependentBlendEnable.hash(&mut hasher);
+        for target in &blend.RenderTarget {
+            target.BlendEnable.hash(&mut hasher);
+            target.LogicOpEnable.hash(&mut hasher);
+            target.SrcBlend.hash(&mut hasher);
+            target.DestBlend.hash(&mut hasher);
+            target.BlendOp.hash(&mut hasher);
+            target.SrcBlendAlpha.hash(&mut hasher);
+            target.DestBlendAlpha.hash(&mut hasher);
+            target.BlendOpAlpha.hash(&mut hasher);
+            target.LogicOp.hash(&mut hasher);
+            target.RenderTargetWriteMask.hash(&mut hasher);
+        }
+        desc.SampleMask.hash(&mut hasher);
+
+        let rasterizer = &desc.RasterizerState;
+        rasterizer.FillMode.hash(&mut hasher);
+        rasterizer.CullMode.hash(&mut hasher);
+        rasterizer.FrontCounterClockwise.hash(&mut hasher);
+        rasterizer.DepthBias.hash(&mut hasher);
+        rasterizer.DepthBiasClamp.to_bits().hash(&mut hasher);
+        rasterizer.SlopeScaledDepthBias.to_bits().hash(&mut hasher);
+        rasterizer.DepthClipEnable.hash(&mut hasher);
+        rasterizer.MultisampleEnable.hash(&mut hasher);
+        rasterizer.AntialiasedLineEnable.hash(&mut hasher);
+        rasterizer.ForcedSampleCount.hash(&mut hasher);
+        rasterizer.ConservativeRaster.hash(&mut hasher);
+
+        let depth_stencil = &desc.DepthStencilState;
+        depth_stencil.DepthEnable.hash(&mut hasher);
+        depth_stencil.DepthWriteMask.hash(&mut hasher);
+        depth_stencil.DepthFunc.hash(&mut hasher);
+        depth_stencil.StencilEnable.hash(&mut hasher);
+        depth_stencil.StencilReadMask.hash(&mut hasher);
+        depth_stencil.StencilWriteMask.hash(&mut hasher);
+        for face in &[depth_stencil.FrontFace, depth_stencil.BackFace] {
+            face.StencilFailOp.hash(&mut hasher);
+            face.StencilDepthFailOp.hash(&mut hasher);
+            face.StencilPassOp.hash(&mut hasher);
+            face.StencilFunc.hash(&mut hasher);
+        }
+
+        let elements = if desc.InputLayout.pInputElementDescs.is_null() {
+            &[]
+        } else {
+            unsafe {
+                slice::from_raw_parts(
+                    desc.InputLayout.pInputElementDescs,
+                    desc.InputLayout.NumElements as usize,
+                )
+            }
+        };
+        elements.len().hash(&mut hasher);
+        for element in elements {
+            unsafe { CStr::from_ptr(element.SemanticName) }.hash(&mut hasher);
+            element.SemanticIndex.hash(&mut hasher);
+            element.Format.hash(&mut hasher);
+            element.InputSlot.hash(&mut hasher);
+            element.AlignedByteOffset.hash(&mut hasher);
+            element.InputSlotClass.hash(&mut hasher);
+            element.InstanceDataStepRate.hash(&mut hasher);
+        }
+
+        desc.IBStripCutValue.hash(&mut hasher);
+        desc.PrimitiveTopologyType.hash(&mut hasher);
+        desc.NumRenderTargets.hash(&mut hasher);
+        desc.RTVFormats.hash(&mut hasher);
+        desc.DSVFormat.hash(&mut hasher);
+        desc.SampleDesc.Count.hash(&mut hasher);
+        desc.SampleDesc.Quality.hash(&mut hasher);
+        desc.NodeMask.hash(&mut hasher);
+        desc.Flags.hash(&mut hasher);
+
+        hasher.finish()
     }
 }
 
@@ -284,8 +990,256 @@ impl Default for PipelineStateBuilder {
                 ..mem::zeroed()
             }
         };
-        PipelineStateBuilder { desc }
+        PipelineStateBuilder {
+            desc,
+            cs: unsafe { mem::zeroed() },
+            root_signature_hash: 0,
+            input_element_descs: Vec::new(),
+        }
     }
 }
 
-pub struct PipelineState(ComPtr<d3d12::ID3D12PipelineState>);
+#[derive(Clone)]
+pub struct PipelineState(pub(crate) ComPtr<d3d12::ID3D12PipelineState>);
+
+// Pipeline states are immutable once created, so they can be bound from any recording thread.
+unsafe impl Send for PipelineState {}
+unsafe impl Sync for PipelineState {}
+
+impl PipelineState {
+    pub fn create_compute(
+        device: &Device,
+        root_signature: &RootSignature,
+        shader: &Shader,
+    ) -> PipelineState {
+        let desc = d3d12::D3D12_COMPUTE_PIPELINE_STATE_DESC {
+            pRootSignature: root_signature.native.as_ptr(),
+            CS: shader.bytecode,
+            NodeMask: 0,
+            CachedPSO: unsafe { mem::zeroed() },
+            Flags: d3d12::D3D12_PIPELINE_STATE_FLAG_NONE,
+        };
+        Self::create_compute_from_desc(device, &desc)
+    }
+
+    fn create_compute_from_desc(
+        device: &Device,
+        desc: &d3d12::D3D12_COMPUTE_PIPELINE_STATE_DESC,
+    ) -> PipelineState {
+        let mut pso: *mut d3d12::ID3D12PipelineState = ptr::null_mut();
+        unsafe {
+            if FAILED(device.native.CreateComputePipelineState(
+                desc,
+                &d3d12::ID3D12PipelineState::uuidof(),
+                &mut pso as *mut *mut _ as *mut *mut _,
+            )) {
+                panic!("Failed to create D3D12 compute pipeline state object.");
+            }
+        }
+        PipelineState(unsafe { ComPtr::from_ptr(pso) })
+    }
+}
+
+// Graphics pipeline states keyed by the hash of their description. When created with a file path,
+// compiled states are also stored in an ID3D12PipelineLibrary serialized to that file on save.
+pub struct PsoCache {
+    states: HashMap<u64, PipelineState>,
+    library: Option<ComPtr<d3d12::ID3D12PipelineLibrary>>,
+    path: Option<PathBuf>,
+    // The library references the blob it was created from for its whole lifetime.
+    _library_data: Vec<u8>,
+}
+
+impl PsoCache {
+    pub fn new() -> Self {
+        PsoCache {
+            states: HashMap::new(),
+            library: None,
+            path: None,
+            _library_data: Vec::new(),
+        }
+    }
+
+    // Falls back to an in-memory cache if the device does not support pipeline libraries.
+    pub fn with_library(device: &Device, path: &Path) -> Self {
+        let device1 = match device.native.cast::<d3d12::ID3D12Device1>() {
+            Ok(device1) => device1,
+            Err(_) => return Self::new(),
+        };
+
+        let create_library = |data: &[u8]| {
+            let mut library: *mut d3d12::ID3D12PipelineLibrary = ptr::null_mut();
+            let hr = unsafe {
+                device1.CreatePipelineLibrary(
+                    data.as_ptr() as *const _,
+                    data.len(),
+                    &d3d12::ID3D12PipelineLibrary::uuidof(),
+                    &mut library as *mut *mut _ as *mut *mut _,
+                )
+            };
+            if SUCCEEDED(hr) {
+                Some(unsafe { ComPtr::from_ptr(library) })
+            } else {
+                None
+            }
+        };
+
+        // A library serialized by a different driver or adapter is rejected, start over then.
+        let mut library_data = fs::read(path).unwrap_or_default();
+        let mut library = create_library(&library_data);
+        if library.is_none() && !library_data.is_empty() {
+            library_data.clear();
+            library = create_library(&library_data);
+        }
+
+        PsoCache {
+            states: HashMap::new(),
+            library,
+            path: Some(path.to_path_buf()),
+            _library_data: library_data,
+        }
+    }
+
+    pub fn get_or_create(
+        &mut self,
+        device: &Device,
+        builder: PipelineStateBuilder,
+    ) -> PipelineState {
+        let hash = builder.hash_desc();
+        if let Some(state) = self.states.get(&hash) {
+            return state.clone();
+        }
+
+        let state = match &self.library {
+            Some(library) => {
+                let name: Vec<u16> = format!("{:016x}", hash)
+                    .encode_utf16()
+                    .chain(Some(0))
+                    .collect();
+                let mut pso: *mut d3d12::ID3D12PipelineState = ptr::null_mut();
+                let hr = unsafe {
+                    library.LoadGraphicsPipeline(
+                        name.as_ptr(),
+                        &builder.desc,
+                        &d3d12::ID3D12PipelineState::uuidof(),
+                        &mut pso as *mut *mut _ as *mut *mut _,
+                    )
+                };
+                if SUCCEEDED(hr) {
+                    PipelineState(unsafe { ComPtr::from_ptr(pso) })
+                } else {
+                    let state = builder.build(device, PSOKind::Graphics);
+                    unsafe {
+                        library.StorePipeline(name.as_ptr(), state.0.as_ptr());
+                    }
+                    state
+                }
+            }
+            None => builder.build(device, PSOKind::Graphics),
+        };
+
+        self.states.insert(hash, state.clone());
+        state
+    }
+
+    // Writes the pipeline library to disk, if the cache has one.
+    pub fn save(&self) -> io::Result<()> {
+        let (library, path) = match (&self.library, &self.path) {
+            (Some(library), Some(path)) => (library, path),
+            _ => return Ok(()),
+        };
+
+        let mut data = vec![0u8; unsafe { library.GetSerializedSize() }];
+        let hr = unsafe { library.Serialize(data.as_mut_ptr() as *mut _, data.len()) };
+        if FAILED(hr) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Failed to serialize pipeline library: {:#x}", hr),
+            ));
+        }
+        fs::write(path, data)
+    }
+}
+
+impl Default for PsoCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Opens `file_name` the way the compiler does, returning its contents and data pointer.
+    fn open(
+        include: &mut ShaderInclude,
+        include_type: d3dcommon::D3D_INCLUDE_TYPE,
+        file_name: &str,
+        parent_data: LPCVOID,
+    ) -> Option<(String, LPCVOID)> {
+        let file_name = CString::new(file_name).unwrap();
+        let mut data: LPCVOID = ptr::null();
+        let mut bytes: UINT = 0;
+        let hr = unsafe {
+            shader_include_open(
+                include as *mut _ as *mut _,
+                include_type,
+                file_name.as_ptr(),
+                parent_data,
+                &mut data,
+                &mut bytes,
+            )
+        };
+        if FAILED(hr) {
+            return None;
+        }
+        let contents = unsafe { slice::from_raw_parts(data as *const u8, bytes as usize) };
+        Some((String::from_utf8_lossy(contents).into_owned(), data))
+    }
+
+    #[test]
+    fn local_includes_resolve_relative_to_the_including_file() {
+        let root = std::env::temp_dir().join(format!("adamant_include_{}", std::process::id()));
+        fs::create_dir_all(root.join("lighting")).unwrap();
+        fs::write(root.join("lighting").join("brdf.hlsli"), "brdf").unwrap();
+        fs::write(root.join("lighting").join("common.hlsli"), "nested").unwrap();
+        fs::write(root.join("common.hlsli"), "root").unwrap();
+
+        let mut include = ShaderInclude::new(&root);
+        let (contents, brdf) = open(
+            &mut include,
+            d3dcommon::D3D_INCLUDE_LOCAL,
+            "lighting/brdf.hlsli",
+            ptr::null(),
+        )
+        .unwrap();
+        assert_eq!(contents, "brdf");
+
+        let (contents, _) = open(
+            &mut include,
+            d3dcommon::D3D_INCLUDE_LOCAL,
+            "common.hlsli",
+            brdf,
+        )
+        .unwrap();
+        assert_eq!(contents, "nested");
+        let (contents, _) = open(
+            &mut include,
+            d3dcommon::D3D_INCLUDE_SYSTEM,
+            "common.hlsli",
+            brdf,
+        )
+        .unwrap();
+        assert_eq!(contents, "root");
+        assert!(open(
+            &mut include,
+            d3dcommon::D3D_INCLUDE_LOCAL,
+            "missing.hlsli",
+            brdf
+        )
+        .is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
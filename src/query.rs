@@ -0,0 +1,135 @@
+use crate::buffer::ReadbackBuffer;
+use crate::com::ComPtr;
+use crate::command::CommandQueue;
+use crate::device::Device;
+
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::um::d3d12;
+use winapi::Interface;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::{mem, ptr};
+
+#[derive(Debug)]
+pub enum Error {
+    QueryHeapCreateFailed,
+    ReadbackBufferCreateFailed,
+}
+
+pub struct QueryHeap {
+    pub(crate) native: ComPtr<d3d12::ID3D12QueryHeap>,
+    count: u32,
+}
+
+impl QueryHeap {
+    pub fn new(
+        device: &Device,
+        type_: d3d12::D3D12_QUERY_HEAP_TYPE,
+        count: u32,
+    ) -> Result<Self, Error> {
+        let mut heap: *mut d3d12::ID3D12QueryHeap = ptr::null_mut();
+        let desc = d3d12::D3D12_QUERY_HEAP_DESC {
+            Type: type_,
+            Count: count,
+            NodeMask: 0,
+        };
+        let hr = unsafe {
+            device.native.CreateQueryHeap(
+                &desc,
+                &d3d12::ID3D12QueryHeap::uuidof(),
+                &mut heap as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(QueryHeap {
+                native: unsafe { ComPtr::from_ptr(heap) },
+                count,
+            })
+        } else {
+            Err(Error::QueryHeapCreateFailed)
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+// Pairs of timestamps around labeled GPU regions, written with CommandContext::begin_timestamp
+// and end_timestamp and copied to the CPU with CommandContext::resolve_timestamps.
+pub struct TimestampQueries {
+    pub(crate) heap: QueryHeap,
+    pub(crate) readback: ReadbackBuffer,
+    // Label of each begin/end query pair, in query order.
+    labels: RefCell<Vec<String>>,
+}
+
+impl TimestampQueries {
+    pub fn new(device: &Device, max_regions: u32) -> Result<Self, Error> {
+        let heap = QueryHeap::new(
+            device,
+            d3d12::D3D12_QUERY_HEAP_TYPE_TIMESTAMP,
+            2 * max_regions,
+        )?;
+        let readback = ReadbackBuffer::create(
+            device,
+            u64::from(2 * max_regions) * mem::size_of::<u64>() as u64,
+        )
+        .map_err(|_| Error::ReadbackBufferCreateFailed)?;
+        Ok(TimestampQueries {
+            heap,
+            readback,
+            labels: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Index of the query to write at the start of a new region.
+    pub(crate) fn begin(&self, label: &str) -> u32 {
+        let mut labels = self.labels.borrow_mut();
+        let index = 2 * labels.len() as u32;
+        assert!(index < self.heap.count(), "too many timestamp regions");
+        labels.push(label.to_owned());
+        index
+    }
+
+    // Index of the query to write at the end of the last region started with `label`.
+    pub(crate) fn end(&self, label: &str) -> u32 {
+        let labels = self.labels.borrow();
+        let region = labels
+            .iter()
+            .rposition(|region_label| region_label == label)
+            .expect("no timestamp region started with this label");
+        2 * region as u32 + 1
+    }
+
+    // Number of queries written so far, all of them are resolved.
+    pub(crate) fn query_count(&self) -> u32 {
+        2 * self.labels.borrow().len() as u32
+    }
+
+    // Milliseconds spent in each region, once the resolved queries have completed on
+    // `command_queue`. Regions sharing a label are summed. Starts over for the next frame.
+    pub fn read(&self, command_queue: &CommandQueue) -> HashMap<String, f64> {
+        let frequency = command_queue.timestamp_frequency() as f64;
+        let data = self
+            .readback
+            .read()
+            .expect("Failed to map timestamp readback buffer.");
+        let ticks: Vec<u64> = data
+            .chunks_exact(mem::size_of::<u64>())
+            .map(|bytes| {
+                let mut tick = [0u8; 8];
+                tick.copy_from_slice(bytes);
+                u64::from_ne_bytes(tick)
+            })
+            .collect();
+
+        let mut timings = HashMap::new();
+        for (region, label) in self.labels.borrow_mut().drain(..).enumerate() {
+            let elapsed = ticks[2 * region + 1].saturating_sub(ticks[2 * region]);
+            *timings.entry(label).or_insert(0.0) += elapsed as f64 * 1000.0 / frequency;
+        }
+        timings
+    }
+}
@@ -0,0 +1,549 @@
+// DirectX Raytracing acceleration structures. winapi stops at ID3D12Device2 and
+// ID3D12GraphicsCommandList1, so the interfaces and structures introduced by DXR are declared here.
+#![allow(non_snake_case, non_upper_case_globals)]
+
+use crate::com::ComPtr;
+use crate::command::GraphicsCommandList;
+use crate::device::Device;
+use crate::resource::GpuResource;
+
+use winapi::ctypes::c_void;
+use winapi::shared::{
+    basetsd::{SIZE_T, UINT64},
+    dxgiformat, dxgitype,
+    guiddef::{REFGUID, REFIID},
+    minwindef::UINT,
+    winerror::{HRESULT, SUCCEEDED},
+};
+use winapi::um::{
+    d3d12::{self, ID3D12Device2, ID3D12Device2Vtbl},
+    d3d12::{ID3D12GraphicsCommandList1, ID3D12GraphicsCommandList1Vtbl},
+    winnt::HANDLE,
+};
+use winapi::{Interface, RIDL};
+
+use std::{mem, ptr};
+
+const D3D12_RESOURCE_STATE_RAYTRACING_ACCELERATION_STRUCTURE: d3d12::D3D12_RESOURCE_STATES =
+    0x40_0000;
+const D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BYTE_ALIGNMENT: u64 = 256;
+
+const D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_TOP_LEVEL: u32 = 0;
+const D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_BOTTOM_LEVEL: u32 = 1;
+const D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_PREFER_FAST_TRACE: u32 = 0x4;
+const D3D12_ELEMENTS_LAYOUT_ARRAY: u32 = 0;
+const D3D12_RAYTRACING_GEOMETRY_TYPE_TRIANGLES: u32 = 0;
+const D3D12_RAYTRACING_GEOMETRY_FLAG_NONE: u32 = 0;
+const D3D12_RAYTRACING_GEOMETRY_FLAG_OPAQUE: u32 = 0x1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct D3D12_GPU_VIRTUAL_ADDRESS_AND_STRIDE {
+    StartAddress: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    StrideInBytes: UINT64,
+}
+
+// Also the largest member of the geometry union, AABB geometry is not supported.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct D3D12_RAYTRACING_GEOMETRY_TRIANGLES_DESC {
+    Transform3x4: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    IndexFormat: dxgiformat::DXGI_FORMAT,
+    VertexFormat: dxgiformat::DXGI_FORMAT,
+    IndexCount: UINT,
+    VertexCount: UINT,
+    IndexBuffer: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    VertexBuffer: D3D12_GPU_VIRTUAL_ADDRESS_AND_STRIDE,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct D3D12_RAYTRACING_GEOMETRY_DESC {
+    Type: u32,
+    Flags: u32,
+    Triangles: D3D12_RAYTRACING_GEOMETRY_TRIANGLES_DESC,
+}
+
+// The last member is a union of the instance descriptions address, for top level structures,
+// and of a pointer to the geometry descriptions, for bottom level ones.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS {
+    Type: u32,
+    Flags: u32,
+    NumDescs: UINT,
+    DescsLayout: u32,
+    Descs: UINT64,
+}
+
+#[repr(C)]
+struct D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_DESC {
+    DestAccelerationStructureData: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    Inputs: D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS,
+    SourceAccelerationStructureData: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    ScratchAccelerationStructureData: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+}
+
+#[repr(C)]
+struct D3D12_RAYTRACING_ACCELERATION_STRUCTURE_PREBUILD_INFO {
+    ResultDataMaxSizeInBytes: UINT64,
+    ScratchDataSizeInBytes: UINT64,
+    UpdateScratchDataSizeInBytes: UINT64,
+}
+
+// The 24 bit instance ID and contribution to the hit group index share their UINT with the 8 bit
+// instance mask and flags respectively.
+#[repr(C)]
+struct D3D12_RAYTRACING_INSTANCE_DESC {
+    Transform: [f32; 12],
+    InstanceIDAndMask: UINT,
+    InstanceContributionToHitGroupIndexAndFlags: UINT,
+    AccelerationStructure: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+}
+
+// Methods that are never called are declared with untyped pointers, only their slot matters.
+RIDL! {#[uuid(0x81dadc15, 0x2bad, 0x4392, 0x93, 0xc5, 0x10, 0x13, 0x45, 0xc4, 0xaa, 0x98)]
+interface ID3D12Device3(ID3D12Device3Vtbl): ID3D12Device2(ID3D12Device2Vtbl) {
+    fn OpenExistingHeapFromAddress(
+        pAddress: *const c_void,
+        riid: REFIID,
+        ppvHeap: *mut *mut c_void,
+    ) -> HRESULT,
+    fn OpenExistingHeapFromFileMapping(
+        hFileMapping: HANDLE,
+        riid: REFIID,
+        ppvHeap: *mut *mut c_void,
+    ) -> HRESULT,
+    fn EnqueueMakeResident(
+        Flags: u32,
+        NumObjects: UINT,
+        ppObjects: *const *mut c_void,
+        pFenceToSignal: *mut c_void,
+        FenceValueToSignal: UINT64,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xe865df17, 0xa9ee, 0x46f9, 0xa4, 0x63, 0x30, 0x98, 0x31, 0x5a, 0xa2, 0xe5)]
+interface ID3D12Device4(ID3D12Device4Vtbl): ID3D12Device3(ID3D12Device3Vtbl) {
+    fn CreateCommandList1(
+        nodeMask: UINT,
+        type_: u32,
+        flags: u32,
+        riid: REFIID,
+        ppCommandList: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateProtectedResourceSession(
+        pDesc: *const c_void,
+        riid: REFIID,
+        ppSession: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateCommittedResource1(
+        pHeapProperties: *const c_void,
+        HeapFlags: u32,
+        pDesc: *const c_void,
+        InitialResourceState: u32,
+        pOptimizedClearValue: *const c_void,
+        pProtectedSession: *mut c_void,
+        riidResource: REFIID,
+        ppvResource: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateHeap1(
+        pDesc: *const c_void,
+        pProtectedSession: *mut c_void,
+        riid: REFIID,
+        ppvHeap: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateReservedResource1(
+        pDesc: *const c_void,
+        InitialState: u32,
+        pOptimizedClearValue: *const c_void,
+        pProtectedSession: *mut c_void,
+        riid: REFIID,
+        ppvResource: *mut *mut c_void,
+    ) -> HRESULT,
+    fn GetResourceAllocationInfo1(
+        pResult: *mut c_void,
+        visibleMask: UINT,
+        numResourceDescs: UINT,
+        pResourceDescs: *const c_void,
+        pResourceAllocationInfo1: *mut c_void,
+    ) -> *mut c_void,
+}}
+
+RIDL! {#[uuid(0x8b4f173b, 0x2fea, 0x4b80, 0x8f, 0x58, 0x43, 0x07, 0x19, 0x1a, 0xb9, 0x5d)]
+interface ID3D12Device5(ID3D12Device5Vtbl): ID3D12Device4(ID3D12Device4Vtbl) {
+    fn CreateLifetimeTracker(
+        pOwner: *mut c_void,
+        riid: REFIID,
+        ppvTracker: *mut *mut c_void,
+    ) -> HRESULT,
+    fn RemoveDevice() -> (),
+    fn EnumerateMetaCommands(
+        pNumMetaCommands: *mut UINT,
+        pDescs: *mut c_void,
+    ) -> HRESULT,
+    fn EnumerateMetaCommandParameters(
+        CommandId: REFGUID,
+        Stage: u32,
+        pTotalStructureSizeInBytes: *mut UINT,
+        pParameterCount: *mut UINT,
+        pParameterDescs: *mut c_void,
+    ) -> HRESULT,
+    fn CreateMetaCommand(
+        CommandId: REFGUID,
+        NodeMask: UINT,
+        pCreationParametersData: *const c_void,
+        CreationParametersDataSizeInBytes: SIZE_T,
+        riid: REFIID,
+        ppMetaCommand: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateStateObject(
+        pDesc: *const c_void,
+        riid: REFIID,
+        ppStateObject: *mut *mut c_void,
+    ) -> HRESULT,
+    fn GetRaytracingAccelerationStructurePrebuildInfo(
+        pDesc: *const D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS,
+        pInfo: *mut D3D12_RAYTRACING_ACCELERATION_STRUCTURE_PREBUILD_INFO,
+    ) -> (),
+    fn CheckDriverMatchingIdentifier(
+        SerializedDataType: u32,
+        pIdentifierToCheck: *const c_void,
+    ) -> u32,
+}}
+
+RIDL! {#[uuid(0x38c3e585, 0xff17, 0x412c, 0x91, 0x50, 0x4f, 0xc6, 0xf9, 0xd7, 0x2a, 0x28)]
+interface ID3D12GraphicsCommandList2(ID3D12GraphicsCommandList2Vtbl):
+    ID3D12GraphicsCommandList1(ID3D12GraphicsCommandList1Vtbl) {
+    fn WriteBufferImmediate(
+        Count: UINT,
+        pParams: *const c_void,
+        pModes: *const u32,
+    ) -> (),
+}}
+
+RIDL! {#[uuid(0x6fda83a7, 0xb84c, 0x4e38, 0x9a, 0xc8, 0xc7, 0xbd, 0x22, 0x01, 0x6b, 0x3d)]
+interface ID3D12GraphicsCommandList3(ID3D12GraphicsCommandList3Vtbl):
+    ID3D12GraphicsCommandList2(ID3D12GraphicsCommandList2Vtbl) {
+    fn SetProtectedResourceSession(
+        pProtectedResourceSession: *mut c_void,
+    ) -> (),
+}}
+
+RIDL! {#[uuid(0x8754318e, 0xd3a9, 0x4541, 0x98, 0xcf, 0x64, 0x5b, 0x50, 0xdc, 0x48, 0x74)]
+interface ID3D12GraphicsCommandList4(ID3D12GraphicsCommandList4Vtbl):
+    ID3D12GraphicsCommandList3(ID3D12GraphicsCommandList3Vtbl) {
+    fn BeginRenderPass(
+        NumRenderTargets: UINT,
+        pRenderTargets: *const c_void,
+        pDepthStencil: *const c_void,
+        Flags: u32,
+    ) -> (),
+    fn EndRenderPass() -> (),
+    fn InitializeMetaCommand(
+        pMetaCommand: *mut c_void,
+        pInitializationParametersData: *const c_void,
+        InitializationParametersDataSizeInBytes: SIZE_T,
+    ) -> (),
+    fn ExecuteMetaCommand(
+        pMetaCommand: *mut c_void,
+        pExecutionParametersData: *const c_void,
+        ExecutionParametersDataSizeInBytes: SIZE_T,
+    ) -> (),
+    fn BuildRaytracingAccelerationStructure(
+        pDesc: *const D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_DESC,
+        NumPostbuildInfoDescs: UINT,
+        pPostbuildInfoDescs: *const c_void,
+    ) -> (),
+    fn EmitRaytracingAccelerationStructurePostbuildInfo(
+        pDesc: *const c_void,
+        NumSourceAccelerationStructures: UINT,
+        pSourceAccelerationStructureData: *const d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+    ) -> (),
+    fn CopyRaytracingAccelerationStructure(
+        DestAccelerationStructureData: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+        SourceAccelerationStructureData: d3d12::D3D12_GPU_VIRTUAL_ADDRESS,
+        Mode: u32,
+    ) -> (),
+    fn SetPipelineState1(
+        pStateObject: *mut c_void,
+    ) -> (),
+    fn DispatchRays(
+        pDesc: *const c_void,
+    ) -> (),
+}}
+
+#[derive(Debug)]
+pub enum Error {
+    CommandListCastFailed,
+    BufferCreateFailed,
+    InstanceBufferMapFailed,
+}
+
+// Triangles of a bottom level acceleration structure. The buffers must be in the
+// NON_PIXEL_SHADER_RESOURCE state while the build executes.
+pub struct TriangleGeometry<'a> {
+    pub vertex_buffer: &'a GpuResource,
+    pub vertex_stride: u64,
+    pub vertex_count: u32,
+    // Format of the vertex positions, e.g. DXGI_FORMAT_R32G32B32_FLOAT.
+    pub vertex_format: dxgiformat::DXGI_FORMAT,
+    // None for non indexed triangle lists.
+    pub index_buffer: Option<&'a GpuResource>,
+    pub index_count: u32,
+    pub index_format: dxgiformat::DXGI_FORMAT,
+    // Skips any hit shaders for this geometry.
+    pub opaque: bool,
+}
+
+// Placement of a bottom level acceleration structure in a top level one.
+pub struct Instance<'a> {
+    pub acceleration_structure: &'a AccelerationStructure,
+    // Row major 3x4 object to world transform.
+    pub transform: [f32; 12],
+    // Only the low 24 bits are used.
+    pub instance_id: u32,
+    pub mask: u8,
+    // Only the low 24 bits are used.
+    pub hit_group_index: u32,
+    // D3D12_RAYTRACING_INSTANCE_FLAGS.
+    pub flags: u8,
+}
+
+// Result of a build, along with the buffers the GPU reads while building. All of them must be
+// kept alive until the build has completed on the GPU.
+pub struct AccelerationStructure {
+    pub(crate) result: GpuResource,
+    scratch: Option<GpuResource>,
+    instances: Option<GpuResource>,
+}
+
+impl AccelerationStructure {
+    pub fn gpu_virtual_address(&self) -> d3d12::D3D12_GPU_VIRTUAL_ADDRESS {
+        self.result.gpu_virtual_address()
+    }
+
+    // Releases the buffers only needed while building, once the build has completed.
+    pub fn release_build_buffers(&mut self) {
+        self.scratch = None;
+        self.instances = None;
+    }
+}
+
+pub struct RaytracingDevice {
+    device: Device,
+    pub(crate) native: ComPtr<ID3D12Device5>,
+}
+
+impl RaytracingDevice {
+    pub(crate) fn new(device: &Device, native: ComPtr<ID3D12Device5>) -> Self {
+        RaytracingDevice {
+            device: device.clone(),
+            native,
+        }
+    }
+
+    // Records the build of a bottom level acceleration structure on a direct or compute list.
+    pub fn build_bottom_level_acceleration_structure(
+        &self,
+        command_list: &GraphicsCommandList,
+        geometries: &[TriangleGeometry],
+    ) -> Result<AccelerationStructure, Error> {
+        let descs: Vec<D3D12_RAYTRACING_GEOMETRY_DESC> = geometries
+            .iter()
+            .map(|geometry| D3D12_RAYTRACING_GEOMETRY_DESC {
+                Type: D3D12_RAYTRACING_GEOMETRY_TYPE_TRIANGLES,
+                Flags: if geometry.opaque {
+                    D3D12_RAYTRACING_GEOMETRY_FLAG_OPAQUE
+                } else {
+                    D3D12_RAYTRACING_GEOMETRY_FLAG_NONE
+                },
+                Triangles: D3D12_RAYTRACING_GEOMETRY_TRIANGLES_DESC {
+                    Transform3x4: 0,
+                    IndexFormat: geometry
+                        .index_buffer
+                        .map_or(dxgiformat::DXGI_FORMAT_UNKNOWN, |_| geometry.index_format),
+                    VertexFormat: geometry.vertex_format,
+                    IndexCount: geometry.index_buffer.map_or(0, |_| geometry.index_count),
+                    VertexCount: geometry.vertex_count,
+                    IndexBuffer: geometry
+                        .index_buffer
+                        .map_or(0, |index_buffer| index_buffer.gpu_virtual_address()),
+                    VertexBuffer: D3D12_GPU_VIRTUAL_ADDRESS_AND_STRIDE {
+                        StartAddress: geometry.vertex_buffer.gpu_virtual_address(),
+                        StrideInBytes: geometry.vertex_stride,
+                    },
+                },
+            })
+            .collect();
+
+        let inputs = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS {
+            Type: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_BOTTOM_LEVEL,
+            Flags: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_PREFER_FAST_TRACE,
+            NumDescs: descs.len() as _,
+            DescsLayout: D3D12_ELEMENTS_LAYOUT_ARRAY,
+            Descs: descs.as_ptr() as UINT64,
+        };
+        self.build_acceleration_structure(command_list, &inputs, None)
+    }
+
+    // Records the build of a top level acceleration structure on a direct or compute list. The
+    // bottom level structures must have been built before.
+    pub fn build_top_level_acceleration_structure(
+        &self,
+        command_list: &GraphicsCommandList,
+        instances: &[Instance],
+    ) -> Result<AccelerationStructure, Error> {
+        let descs: Vec<D3D12_RAYTRACING_INSTANCE_DESC> = instances
+            .iter()
+            .map(|instance| D3D12_RAYTRACING_INSTANCE_DESC {
+                Transform: instance.transform,
+                InstanceIDAndMask: (instance.instance_id & 0xff_ffff)
+                    | (u32::from(instance.mask) << 24),
+                InstanceContributionToHitGroupIndexAndFlags: (instance.hit_group_index & 0xff_ffff)
+                    | (u32::from(instance.flags) << 24),
+                AccelerationStructure: instance.acceleration_structure.gpu_virtual_address(),
+            })
+            .collect();
+
+        // The GPU reads the instances straight from an upload buffer.
+        let size = mem::size_of_val(descs.as_slice()) as u64;
+        let instance_buffer = self.create_buffer(
+            size.max(1),
+            d3d12::D3D12_HEAP_TYPE_UPLOAD,
+            d3d12::D3D12_RESOURCE_FLAG_NONE,
+            d3d12::D3D12_RESOURCE_STATE_GENERIC_READ,
+        )?;
+        let data = instance_buffer
+            .map()
+            .map_err(|_| Error::InstanceBufferMapFailed)?;
+        unsafe { ptr::copy_nonoverlapping(descs.as_ptr() as *const u8, data, size as usize) };
+        instance_buffer.unmap();
+
+        let inputs = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS {
+            Type: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_TYPE_TOP_LEVEL,
+            Flags: D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BUILD_FLAG_PREFER_FAST_TRACE,
+            NumDescs: descs.len() as _,
+            DescsLayout: D3D12_ELEMENTS_LAYOUT_ARRAY,
+            Descs: instance_buffer.gpu_virtual_address(),
+        };
+        self.build_acceleration_structure(command_list, &inputs, Some(instance_buffer))
+    }
+
+    fn build_acceleration_structure(
+        &self,
+        command_list: &GraphicsCommandList,
+        inputs: &D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_INPUTS,
+        instances: Option<GpuResource>,
+    ) -> Result<AccelerationStructure, Error> {
+        let command_list4 = command_list
+            .0
+            .cast::<ID3D12GraphicsCommandList4>()
+            .map_err(|_| Error::CommandListCastFailed)?;
+
+        let mut prebuild_info = D3D12_RAYTRACING_ACCELERATION_STRUCTURE_PREBUILD_INFO {
+            ResultDataMaxSizeInBytes: 0,
+            ScratchDataSizeInBytes: 0,
+            UpdateScratchDataSizeInBytes: 0,
+        };
+        unsafe {
+            self.native
+                .GetRaytracingAccelerationStructurePrebuildInfo(inputs, &mut prebuild_info)
+        };
+
+        let align = |size: u64| {
+            let alignment = D3D12_RAYTRACING_ACCELERATION_STRUCTURE_BYTE_ALIGNMENT;
+            (size.max(1) + alignment - 1) & !(alignment - 1)
+        };
+        let result = self.create_buffer(
+            align(prebuild_info.ResultDataMaxSizeInBytes),
+            d3d12::D3D12_HEAP_TYPE_DEFAULT,
+            d3d12::D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+            D3D12_RESOURCE_STATE_RAYTRACING_ACCELERATION_STRUCTURE,
+        )?;
+        let scratch = self.create_buffer(
+            align(prebuild_info.ScratchDataSizeInBytes),
+            d3d12::D3D12_HEAP_TYPE_DEFAULT,
+            d3d12::D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+            d3d12::D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+        )?;
+
+        let desc = D3D12_BUILD_RAYTRACING_ACCELERATION_STRUCTURE_DESC {
+            DestAccelerationStructureData: result.gpu_virtual_address(),
+            Inputs: *inputs,
+            SourceAccelerationStructureData: 0,
+            ScratchAccelerationStructureData: scratch.gpu_virtual_address(),
+        };
+        unsafe {
+            command_list4.BuildRaytracingAccelerationStructure(&desc, 0, ptr::null());
+
+            // Later builds and traces reading the structure must wait for this build.
+            let mut barrier = d3d12::D3D12_RESOURCE_BARRIER {
+                Type: d3d12::D3D12_RESOURCE_BARRIER_TYPE_UAV,
+                Flags: d3d12::D3D12_RESOURCE_BARRIER_FLAG_NONE,
+                u: mem::zeroed(),
+            };
+            *barrier.u.UAV_mut() = d3d12::D3D12_RESOURCE_UAV_BARRIER {
+                pResource: result.native.as_ptr(),
+            };
+            command_list4.ResourceBarrier(1, &barrier);
+        }
+
+        Ok(AccelerationStructure {
+            result,
+            scratch: Some(scratch),
+            instances,
+        })
+    }
+
+    fn create_buffer(
+        &self,
+        size: u64,
+        heap_type: d3d12::D3D12_HEAP_TYPE,
+        flags: d3d12::D3D12_RESOURCE_FLAGS,
+        state: d3d12::D3D12_RESOURCE_STATES,
+    ) -> Result<GpuResource, Error> {
+        let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let resource_desc = d3d12::D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            DepthOrArraySize: 1,
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_BUFFER,
+            Flags: flags,
+            Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+            Height: 1,
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            MipLevels: 1,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Width: size,
+        };
+        let heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
+            Type: heap_type,
+            CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 1,
+            VisibleNodeMask: 1,
+        };
+        let hr = unsafe {
+            self.device.native.CreateCommittedResource(
+                &heap_properties,
+                d3d12::D3D12_HEAP_FLAG_NONE,
+                &resource_desc,
+                state,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(GpuResource::create(
+                unsafe { ComPtr::from_ptr(resource) },
+                state,
+            ))
+        } else {
+            Err(Error::BufferCreateFailed)
+        }
+    }
+}
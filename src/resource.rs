@@ -1,20 +1,50 @@
 use crate::com::ComPtr;
+use crate::descriptor::CpuDescriptor;
+use crate::device::Device;
+use crate::memory::Heap;
 
-use winapi::shared::winerror::SUCCEEDED;
+use winapi::shared::{dxgiformat, dxgitype, winerror::SUCCEEDED};
 use winapi::um::d3d12;
+use winapi::Interface;
 
-use std::ptr;
+use std::collections::VecDeque;
+use std::{mem, ptr};
+
+// D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING (macro in d3d12.h, not exposed by winapi)
+const D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING: u32 = 0x1688;
 
 #[derive(Debug)]
 pub enum GpuResourceError {
+    GpuResourceCreateFailed,
     GpuResourceMapFailed,
 }
 
+// Subresource index standing for every subresource of a resource.
+pub const ALL_SUBRESOURCES: u32 = d3d12::D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES;
+
+// Data of one texture subresource, pitches are in bytes.
+pub struct SubresourceData<'a> {
+    pub data: &'a [u8],
+    pub row_pitch: usize,
+    pub slice_pitch: usize,
+}
+
+// The resource is released when dropped, like any other ComPtr owner.
 pub struct GpuResource {
     pub(crate) native: ComPtr<d3d12::ID3D12Resource>,
     pub(crate) usage_state: d3d12::D3D12_RESOURCE_STATES,
+    // State of each subresource, empty while they all are in usage_state.
+    pub(crate) subresource_states: Vec<d3d12::D3D12_RESOURCE_STATES>,
+    // Target state of a split transition that has begun but not ended yet.
+    pub(crate) transitioning_state: Option<d3d12::D3D12_RESOURCE_STATES>,
 }
 
+// Resources are free threaded and the tracked states are only changed through &mut, so resources
+// can be referenced by command lists recorded on several threads. Transitions must still be
+// recorded in submission order, which is up to the caller when recording in parallel.
+unsafe impl Send for GpuResource {}
+unsafe impl Sync for GpuResource {}
+
 impl GpuResource {
     pub fn create(
         resource: ComPtr<d3d12::ID3D12Resource>,
@@ -23,6 +53,308 @@ impl GpuResource {
         GpuResource {
             native: resource,
             usage_state: state,
+            subresource_states: Vec::new(),
+            transitioning_state: None,
+        }
+    }
+
+    // Creates a resource in existing heap memory instead of an implicit heap of its own. The heap
+    // must outlive the resource.
+    pub fn create_placed(
+        device: &Device,
+        heap: &Heap,
+        offset: u64,
+        desc: &d3d12::D3D12_RESOURCE_DESC,
+        initial_state: d3d12::D3D12_RESOURCE_STATES,
+    ) -> Result<Self, GpuResourceError> {
+        let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let hr = unsafe {
+            device.native.CreatePlacedResource(
+                heap.native.as_ptr(),
+                offset,
+                desc,
+                initial_state,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(GpuResource::create(
+                unsafe { ComPtr::from_ptr(resource) },
+                initial_state,
+            ))
+        } else {
+            Err(GpuResourceError::GpuResourceCreateFailed)
+        }
+    }
+
+    pub fn create_texture_2d(
+        device: &Device,
+        format: dxgiformat::DXGI_FORMAT,
+        width: u32,
+        height: u32,
+        mip_levels: u16,
+        flags: d3d12::D3D12_RESOURCE_FLAGS,
+        initial_state: d3d12::D3D12_RESOURCE_STATES,
+    ) -> Result<Self, GpuResourceError> {
+        Self::create_texture_2d_array(
+            device,
+            format,
+            width,
+            height,
+            1,
+            mip_levels,
+            flags,
+            initial_state,
+        )
+    }
+
+    // Cube maps are arrays of six slices per cube, in +X, -X, +Y, -Y, +Z, -Z face order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_texture_2d_array(
+        device: &Device,
+        format: dxgiformat::DXGI_FORMAT,
+        width: u32,
+        height: u32,
+        array_size: u16,
+        mip_levels: u16,
+        flags: d3d12::D3D12_RESOURCE_FLAGS,
+        initial_state: d3d12::D3D12_RESOURCE_STATES,
+    ) -> Result<Self, GpuResourceError> {
+        let mut resource: *mut d3d12::ID3D12Resource = ptr::null_mut();
+        let resource_desc = d3d12::D3D12_RESOURCE_DESC {
+            Dimension: d3d12::D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Alignment: 0,
+            Width: u64::from(width),
+            Height: height,
+            DepthOrArraySize: array_size,
+            MipLevels: mip_levels,
+            Format: format,
+            SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Layout: d3d12::D3D12_TEXTURE_LAYOUT_UNKNOWN,
+            Flags: flags,
+        };
+        let heap_properties = d3d12::D3D12_HEAP_PROPERTIES {
+            Type: d3d12::D3D12_HEAP_TYPE_DEFAULT,
+            CPUPageProperty: d3d12::D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: d3d12::D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 1,
+            VisibleNodeMask: 1,
+        };
+        let hr = unsafe {
+            device.native.CreateCommittedResource(
+                &heap_properties,
+                d3d12::D3D12_HEAP_FLAG_NONE,
+                &resource_desc,
+                initial_state,
+                ptr::null(),
+                &d3d12::ID3D12Resource::uuidof(),
+                &mut resource as *mut *mut _ as *mut *mut _,
+            )
+        };
+        if SUCCEEDED(hr) {
+            Ok(GpuResource::create(
+                unsafe { ComPtr::from_ptr(resource) },
+                initial_state,
+            ))
+        } else {
+            Err(GpuResourceError::GpuResourceCreateFailed)
+        }
+    }
+
+    pub fn create_shader_resource_view(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        format: dxgiformat::DXGI_FORMAT,
+    ) {
+        // All mips down to the least detailed
+        self.create_mip_shader_resource_view(device, descriptor, format, 0, u32::max_value());
+    }
+
+    pub fn create_mip_shader_resource_view(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        format: dxgiformat::DXGI_FORMAT,
+        most_detailed_mip: u32,
+        mip_levels: u32,
+    ) {
+        unsafe {
+            let mut srv_desc = d3d12::D3D12_SHADER_RESOURCE_VIEW_DESC {
+                Format: format,
+                ViewDimension: d3d12::D3D12_SRV_DIMENSION_TEXTURE2D,
+                Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                ..mem::zeroed()
+            };
+            *srv_desc.u.Texture2D_mut() = d3d12::D3D12_TEX2D_SRV {
+                MostDetailedMip: most_detailed_mip,
+                MipLevels: mip_levels,
+                PlaneSlice: 0,
+                ResourceMinLODClamp: 0.0,
+            };
+            device
+                .native
+                .CreateShaderResourceView(self.native.as_ptr(), &srv_desc, descriptor);
+        }
+    }
+
+    pub fn create_unordered_access_view(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        format: dxgiformat::DXGI_FORMAT,
+    ) {
+        self.create_mip_unordered_access_view(device, descriptor, format, 0);
+    }
+
+    pub fn create_mip_unordered_access_view(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        format: dxgiformat::DXGI_FORMAT,
+        mip_slice: u32,
+    ) {
+        unsafe {
+            let mut uav_desc = d3d12::D3D12_UNORDERED_ACCESS_VIEW_DESC {
+                Format: format,
+                ViewDimension: d3d12::D3D12_UAV_DIMENSION_TEXTURE2D,
+                ..mem::zeroed()
+            };
+            *uav_desc.u.Texture2D_mut() = d3d12::D3D12_TEX2D_UAV {
+                MipSlice: mip_slice,
+                PlaneSlice: 0,
+            };
+            device.native.CreateUnorderedAccessView(
+                self.native.as_ptr(),
+                ptr::null_mut(),
+                &uav_desc,
+                descriptor,
+            );
+        }
+    }
+
+    // Structured buffer view of `num_elements` elements of `stride` bytes, starting at element
+    // `first_element`.
+    pub fn create_structured_srv(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        first_element: u64,
+        num_elements: u32,
+        stride: u32,
+    ) {
+        unsafe {
+            let mut srv_desc = d3d12::D3D12_SHADER_RESOURCE_VIEW_DESC {
+                Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+                ViewDimension: d3d12::D3D12_SRV_DIMENSION_BUFFER,
+                Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                ..mem::zeroed()
+            };
+            *srv_desc.u.Buffer_mut() = d3d12::D3D12_BUFFER_SRV {
+                FirstElement: first_element,
+                NumElements: num_elements,
+                StructureByteStride: stride,
+                Flags: d3d12::D3D12_BUFFER_SRV_FLAG_NONE,
+            };
+            device
+                .native
+                .CreateShaderResourceView(self.native.as_ptr(), &srv_desc, descriptor);
+        }
+    }
+
+    // Structured buffer view for compute writes. Append and consume buffers keep their hidden
+    // counter as a u32 at `counter_offset` bytes into the counter resource, which must be aligned
+    // to D3D12_UAV_COUNTER_PLACEMENT_ALIGNMENT.
+    pub fn create_structured_uav(
+        &self,
+        device: &Device,
+        descriptor: CpuDescriptor,
+        first_element: u64,
+        num_elements: u32,
+        stride: u32,
+        counter: Option<(&GpuResource, u64)>,
+    ) {
+        let (counter_resource, counter_offset) = match counter {
+            Some((counter_resource, counter_offset)) => {
+                (counter_resource.native.as_ptr(), counter_offset)
+            }
+            None => (ptr::null_mut(), 0),
+        };
+        unsafe {
+            let mut uav_desc = d3d12::D3D12_UNORDERED_ACCESS_VIEW_DESC {
+                Format: dxgiformat::DXGI_FORMAT_UNKNOWN,
+                ViewDimension: d3d12::D3D12_UAV_DIMENSION_BUFFER,
+                ..mem::zeroed()
+            };
+            *uav_desc.u.Buffer_mut() = d3d12::D3D12_BUFFER_UAV {
+                FirstElement: first_element,
+                NumElements: num_elements,
+                StructureByteStride: stride,
+                CounterOffsetInBytes: counter_offset,
+                Flags: d3d12::D3D12_BUFFER_UAV_FLAG_NONE,
+            };
+            device.native.CreateUnorderedAccessView(
+                self.native.as_ptr(),
+                counter_resource,
+                &uav_desc,
+                descriptor,
+            );
+        }
+    }
+
+    // Number of mips times array slices times planes, depth stencil formats with a stencil plane
+    // have two planes.
+    pub fn subresource_count(&self) -> u32 {
+        let desc = unsafe { self.native.GetDesc() };
+        if desc.Dimension == d3d12::D3D12_RESOURCE_DIMENSION_BUFFER {
+            return 1;
+        }
+
+        let array_size = if desc.Dimension == d3d12::D3D12_RESOURCE_DIMENSION_TEXTURE3D {
+            1
+        } else {
+            u32::from(desc.DepthOrArraySize)
+        };
+        let plane_count = match desc.Format {
+            dxgiformat::DXGI_FORMAT_R24G8_TYPELESS
+            | dxgiformat::DXGI_FORMAT_D24_UNORM_S8_UINT
+            | dxgiformat::DXGI_FORMAT_R32G8X24_TYPELESS
+            | dxgiformat::DXGI_FORMAT_D32_FLOAT_S8X24_UINT => 2,
+            _ => 1,
+        };
+        u32::from(desc.MipLevels) * array_size * plane_count
+    }
+
+    pub fn gpu_virtual_address(&self) -> d3d12::D3D12_GPU_VIRTUAL_ADDRESS {
+        unsafe { self.native.GetGPUVirtualAddress() }
+    }
+
+    // Size in bytes of a buffer, for textures this is only the width of the top mip.
+    pub fn size(&self) -> u64 {
+        unsafe { self.native.GetDesc().Width }
+    }
+
+    pub fn vertex_buffer_view(&self, stride: u32) -> d3d12::D3D12_VERTEX_BUFFER_VIEW {
+        d3d12::D3D12_VERTEX_BUFFER_VIEW {
+            BufferLocation: self.gpu_virtual_address(),
+            SizeInBytes: self.size() as _,
+            StrideInBytes: stride,
+        }
+    }
+
+    pub fn index_buffer_view(
+        &self,
+        format: dxgiformat::DXGI_FORMAT,
+    ) -> d3d12::D3D12_INDEX_BUFFER_VIEW {
+        d3d12::D3D12_INDEX_BUFFER_VIEW {
+            BufferLocation: self.gpu_virtual_address(),
+            SizeInBytes: self.size() as _,
+            Format: format,
         }
     }
 
@@ -42,3 +374,99 @@ impl GpuResource {
         unsafe { self.native.Unmap(0, ptr::null()) }
     }
 }
+
+// Keeps replaced resources alive until the commands referencing them have completed on the GPU,
+// instead of waiting for the queue to go idle before releasing them.
+#[derive(Default)]
+pub struct DeferredDeleteQueue {
+    // Ordered by fence value, as values are signaled in increasing order.
+    pending: VecDeque<(GpuResource, u64)>,
+}
+
+impl DeferredDeleteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `fence_value` is the value signaled after the last submission using the resource.
+    pub fn push(&mut self, resource: GpuResource, fence_value: u64) {
+        self.pending.push_back((resource, fence_value));
+    }
+
+    // Releases the resources whose fence value has been reached, typically polled once per frame.
+    pub fn collect(&mut self, completed_value: u64) {
+        while let Some(&(_, fence_value)) = self.pending.front() {
+            if fence_value > completed_value {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    // Releases every resource, only once the GPU is idle or gone.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::create_test_device;
+
+    use winapi::um::d3d12sdklayers;
+
+    // The debug layer is an optional Windows component (Graphics Tools), leaks can only be
+    // reported with it. Run with `cargo test -- --ignored` where it is installed.
+    #[test]
+    #[ignore = "needs the D3D12 debug layer, installed with the Graphics Tools optional feature"]
+    fn dropped_resources_are_released() {
+        let device = create_test_device();
+        let debug_device = device
+            .native
+            .cast::<d3d12sdklayers::ID3D12DebugDevice>()
+            .expect("The D3D12 debug layer is not installed, leaks cannot be reported.");
+
+        let mut deferred_deletes = DeferredDeleteQueue::new();
+        for n in 0..64 {
+            let resource = GpuResource::create_texture_2d(
+                &device,
+                dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM,
+                64,
+                64,
+                1,
+                d3d12::D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                d3d12::D3D12_RESOURCE_STATE_COMMON,
+            )
+            .unwrap();
+            // Half of them go through the deferred delete queue, like resized render targets.
+            if n % 2 == 0 {
+                deferred_deletes.push(resource, n);
+            }
+        }
+        deferred_deletes.collect(u64::max_value());
+        assert!(deferred_deletes.is_empty());
+
+        device.drain_debug_messages();
+        unsafe {
+            debug_device.ReportLiveDeviceObjects(
+                d3d12sdklayers::D3D12_RLDO_DETAIL | d3d12sdklayers::D3D12_RLDO_IGNORE_INTERNAL,
+            );
+        }
+        let leaks: Vec<_> = device
+            .drain_debug_messages()
+            .into_iter()
+            .filter(|message| message.id == d3d12sdklayers::D3D12_MESSAGE_ID_LIVE_RESOURCE)
+            .map(|message| message.description)
+            .collect();
+        assert!(leaks.is_empty(), "Leaked resources: {:?}", leaks);
+    }
+}
@@ -1,37 +1,245 @@
 use crate::com::ComPtr;
 use crate::device::Device;
+use crate::hash::StableHasher;
 
-use winapi::shared::winerror::FAILED;
+use winapi::shared::winerror::{FAILED, SUCCEEDED};
 use winapi::um::{d3d12, d3dcommon};
 use winapi::Interface;
 
-use std::{mem, ptr};
+use std::hash::{Hash, Hasher};
+use std::{mem, ptr, slice};
+
+use bitflags::bitflags;
+
+#[derive(Debug)]
+pub enum RootSignatureError {
+    // Carries the diagnostic emitted by the serializer.
+    SerializeFailed(String),
+    CreateFailed,
+    // The parameter at `parameter_index` pushed the signature past the 64 DWORD limit.
+    TooManyDwords {
+        parameter_index: usize,
+        dword_count: u32,
+    },
+}
+
+const MAX_ROOT_SIGNATURE_DWORDS: u32 = 64;
+
+// Only honored with root signature 1.1, ignored when the device falls back to 1.0.
+bitflags! {
+    pub struct DescriptorRangeFlags: u32 {
+        const DESCRIPTORS_VOLATILE = d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DESCRIPTORS_VOLATILE;
+        const DATA_VOLATILE = d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_VOLATILE;
+        const DATA_STATIC_WHILE_SET_AT_EXECUTE = d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE;
+        const DATA_STATIC = d3d12::D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC;
+    }
+}
+
+bitflags! {
+    pub struct RootDescriptorFlags: u32 {
+        const DATA_VOLATILE = d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_VOLATILE;
+        const DATA_STATIC_WHILE_SET_AT_EXECUTE = d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE;
+        const DATA_STATIC = d3d12::D3D12_ROOT_DESCRIPTOR_FLAG_DATA_STATIC;
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DescriptorRangeType {
+    Srv = d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+    Uav = d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+    Cbv = d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_CBV,
+    Sampler = d3d12::D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER,
+}
 
 #[repr(transparent)]
-pub struct DescriptorRange(d3d12::D3D12_DESCRIPTOR_RANGE);
+pub struct DescriptorRange(d3d12::D3D12_DESCRIPTOR_RANGE1);
+impl DescriptorRange {
+    pub fn new(
+        range_type: DescriptorRangeType,
+        num_descriptors: u32,
+        base_register: u32,
+        register_space: u32,
+        offset: u32,
+    ) -> Self {
+        // Root signature 1.0 semantics unless opted out with with_flags(), as no flags means
+        // static descriptors and data in 1.1. Sampler ranges cannot have data flags.
+        let flags = match range_type {
+            DescriptorRangeType::Sampler => DescriptorRangeFlags::DESCRIPTORS_VOLATILE,
+            _ => DescriptorRangeFlags::DESCRIPTORS_VOLATILE | DescriptorRangeFlags::DATA_VOLATILE,
+        };
+        DescriptorRange(d3d12::D3D12_DESCRIPTOR_RANGE1 {
+            RangeType: range_type as _,
+            NumDescriptors: num_descriptors,
+            BaseShaderRegister: base_register,
+            RegisterSpace: register_space,
+            Flags: flags.bits(),
+            OffsetInDescriptorsFromTableStart: offset,
+        })
+    }
+
+    // Places the range directly after the previous one in the descriptor table.
+    pub fn new_appended(
+        range_type: DescriptorRangeType,
+        num_descriptors: u32,
+        base_register: u32,
+        register_space: u32,
+    ) -> Self {
+        Self::new(
+            range_type,
+            num_descriptors,
+            base_register,
+            register_space,
+            d3d12::D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+        )
+    }
+
+    // Replaces the volatile defaults, e.g. with an empty set for fully static descriptors and data.
+    pub fn with_flags(mut self, flags: DescriptorRangeFlags) -> Self {
+        self.0.Flags = flags.bits();
+        self
+    }
+
+    fn to_version_1_0(&self) -> d3d12::D3D12_DESCRIPTOR_RANGE {
+        d3d12::D3D12_DESCRIPTOR_RANGE {
+            RangeType: self.0.RangeType,
+            NumDescriptors: self.0.NumDescriptors,
+            BaseShaderRegister: self.0.BaseShaderRegister,
+            RegisterSpace: self.0.RegisterSpace,
+            OffsetInDescriptorsFromTableStart: self.0.OffsetInDescriptorsFromTableStart,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RootDescriptorType {
+    Cbv = d3d12::D3D12_ROOT_PARAMETER_TYPE_CBV,
+    Srv = d3d12::D3D12_ROOT_PARAMETER_TYPE_SRV,
+    Uav = d3d12::D3D12_ROOT_PARAMETER_TYPE_UAV,
+}
 
 #[repr(transparent)]
-pub struct RootParameter(d3d12::D3D12_ROOT_PARAMETER);
+pub struct RootParameter(d3d12::D3D12_ROOT_PARAMETER1);
 impl RootParameter {
     pub fn new_descriptor_table(
         visibility: d3d12::D3D12_SHADER_VISIBILITY,
         ranges: &[DescriptorRange],
     ) -> Self {
         let mut parameter = unsafe {
-            d3d12::D3D12_ROOT_PARAMETER {
+            d3d12::D3D12_ROOT_PARAMETER1 {
                 ParameterType: d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
                 ShaderVisibility: visibility,
                 ..mem::zeroed()
             }
         };
 
-        *unsafe { parameter.u.DescriptorTable_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR_TABLE {
+        *unsafe { parameter.u.DescriptorTable_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR_TABLE1 {
             NumDescriptorRanges: ranges.len() as _,
             pDescriptorRanges: ranges.as_ptr() as *const _,
         };
 
         RootParameter(parameter)
     }
+
+    pub fn new_constants(
+        num_32bit_values: u32,
+        register: u32,
+        space: u32,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    ) -> Self {
+        let mut parameter = unsafe {
+            d3d12::D3D12_ROOT_PARAMETER1 {
+                ParameterType: d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                ShaderVisibility: visibility,
+                ..mem::zeroed()
+            }
+        };
+
+        *unsafe { parameter.u.Constants_mut() } = d3d12::D3D12_ROOT_CONSTANTS {
+            ShaderRegister: register,
+            RegisterSpace: space,
+            Num32BitValues: num_32bit_values,
+        };
+
+        RootParameter(parameter)
+    }
+
+    pub fn new_descriptor(
+        type_: RootDescriptorType,
+        register: u32,
+        space: u32,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    ) -> Self {
+        let mut parameter = unsafe {
+            d3d12::D3D12_ROOT_PARAMETER1 {
+                ParameterType: type_ as _,
+                ShaderVisibility: visibility,
+                ..mem::zeroed()
+            }
+        };
+
+        *unsafe { parameter.u.Descriptor_mut() } = d3d12::D3D12_ROOT_DESCRIPTOR1 {
+            ShaderRegister: register,
+            RegisterSpace: space,
+            // Root signature 1.0 semantics unless opted out with with_descriptor_flags().
+            Flags: RootDescriptorFlags::DATA_VOLATILE.bits(),
+        };
+
+        RootParameter(parameter)
+    }
+
+    // Only valid for root descriptors (CBV, SRV or UAV parameters). Replaces the DATA_VOLATILE
+    // default.
+    pub fn with_descriptor_flags(mut self, flags: RootDescriptorFlags) -> Self {
+        assert!(
+            self.0.ParameterType != d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS
+                && self.0.ParameterType != d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            "descriptor flags only apply to root descriptors"
+        );
+        unsafe { self.0.u.Descriptor_mut() }.Flags = flags.bits();
+        self
+    }
+
+    fn dword_cost(&self) -> u32 {
+        match self.0.ParameterType {
+            d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS => unsafe {
+                self.0.u.Constants().Num32BitValues
+            },
+            d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[repr(transparent)]
+pub struct StaticSampler(d3d12::D3D12_STATIC_SAMPLER_DESC);
+impl StaticSampler {
+    pub fn new(desc: d3d12::D3D12_STATIC_SAMPLER_DESC) -> Self {
+        StaticSampler(desc)
+    }
+
+    pub fn linear_clamp(
+        register: u32,
+        space: u32,
+        visibility: d3d12::D3D12_SHADER_VISIBILITY,
+    ) -> Self {
+        StaticSampler(d3d12::D3D12_STATIC_SAMPLER_DESC {
+            Filter: d3d12::D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+            AddressU: d3d12::D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+            AddressV: d3d12::D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+            AddressW: d3d12::D3D12_TEXTURE_ADDRESS_MODE_CLAMP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: 1,
+            ComparisonFunc: d3d12::D3D12_COMPARISON_FUNC_ALWAYS,
+            BorderColor: d3d12::D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+            MinLOD: 0.0,
+            MaxLOD: d3d12::D3D12_FLOAT32_MAX,
+            ShaderRegister: register,
+            RegisterSpace: space,
+            ShaderVisibility: visibility,
+        })
+    }
 }
 
 // Maximum 64 DWORDS divied up amongst all root parameters.
@@ -41,15 +249,28 @@ impl RootParameter {
 // Static samplers = 0 DWORDS (compiled into shader)
 pub struct RootSignatureBuilder {
     parameters: Vec<RootParameter>,
+    static_samplers: Vec<StaticSampler>,
+    dword_count: u32,
+    // Index of the first parameter exceeding the DWORD budget, reported on build.
+    overflow_parameter: Option<usize>,
 }
 
 impl RootSignatureBuilder {
     pub fn add_parameter(mut self, parameter: RootParameter) -> RootSignatureBuilder {
+        self.dword_count += parameter.dword_cost();
+        if self.dword_count > MAX_ROOT_SIGNATURE_DWORDS && self.overflow_parameter.is_none() {
+            self.overflow_parameter = Some(self.parameters.len());
+        }
         self.parameters.push(parameter);
         self
     }
 
-    pub fn build(self, device: Device) -> RootSignature {
+    pub fn add_static_sampler(mut self, sampler: StaticSampler) -> RootSignatureBuilder {
+        self.static_samplers.push(sampler);
+        self
+    }
+
+    pub fn build(self, device: Device) -> Result<RootSignature, RootSignatureError> {
         self.build_with_flags(device, d3d12::D3D12_ROOT_SIGNATURE_FLAG_NONE)
     }
 
@@ -57,50 +278,270 @@ impl RootSignatureBuilder {
         self,
         device: Device,
         flags: d3d12::D3D12_ROOT_SIGNATURE_FLAGS,
-    ) -> RootSignature {
-        let mut desc = unsafe { d3d12::D3D12_ROOT_SIGNATURE_DESC { ..mem::zeroed() } };
-        desc.NumParameters = self.parameters.len() as _;
-        desc.pParameters = self.parameters.as_ptr() as *const _;
-        desc.Flags = flags;
+    ) -> Result<RootSignature, RootSignatureError> {
+        if let Some(parameter_index) = self.overflow_parameter {
+            return Err(RootSignatureError::TooManyDwords {
+                parameter_index,
+                dword_count: self.dword_count,
+            });
+        }
 
         let mut out_blob: *mut d3dcommon::ID3DBlob = ptr::null_mut();
         let mut error_blob: *mut d3dcommon::ID3DBlob = ptr::null_mut();
         let mut signature: *mut d3d12::ID3D12RootSignature = ptr::null_mut();
-        unsafe {
-            if FAILED(d3d12::D3D12SerializeRootSignature(
-                &desc,
-                d3d12::D3D_ROOT_SIGNATURE_VERSION_1,
-                &mut out_blob as *mut *mut _ as *mut *mut _,
-                &mut error_blob as *mut *mut _ as *mut *mut _,
-            )) {
-                panic!("Failed to serialize root signature.");
+        let hr = if is_root_signature_1_1_supported(&device) {
+            let mut desc = unsafe {
+                d3d12::D3D12_VERSIONED_ROOT_SIGNATURE_DESC {
+                    Version: d3d12::D3D_ROOT_SIGNATURE_VERSION_1_1,
+                    ..mem::zeroed()
+                }
+            };
+            *unsafe { desc.u.Desc_1_1_mut() } = d3d12::D3D12_ROOT_SIGNATURE_DESC1 {
+                NumParameters: self.parameters.len() as _,
+                pParameters: self.parameters.as_ptr() as *const _,
+                NumStaticSamplers: self.static_samplers.len() as _,
+                pStaticSamplers: self.static_samplers.as_ptr() as *const _,
+                Flags: flags,
+            };
+            unsafe {
+                d3d12::D3D12SerializeVersionedRootSignature(
+                    &desc,
+                    &mut out_blob as *mut *mut _ as *mut *mut _,
+                    &mut error_blob as *mut *mut _ as *mut *mut _,
+                )
             }
+        } else {
+            // Strip the 1.1 flags, the ranges of each table need their own 1.0 copy.
+            let ranges: Vec<Vec<d3d12::D3D12_DESCRIPTOR_RANGE>> = self
+                .parameters
+                .iter()
+                .map(|parameter| {
+                    if parameter.0.ParameterType
+                        != d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE
+                    {
+                        return Vec::new();
+                    }
+                    let table = unsafe { parameter.0.u.DescriptorTable() };
+                    let table_ranges = unsafe {
+                        slice::from_raw_parts(
+                            table.pDescriptorRanges as *const DescriptorRange,
+                            table.NumDescriptorRanges as usize,
+                        )
+                    };
+                    table_ranges
+                        .iter()
+                        .map(DescriptorRange::to_version_1_0)
+                        .collect()
+                })
+                .collect();
+            let parameters: Vec<d3d12::D3D12_ROOT_PARAMETER> = self
+                .parameters
+                .iter()
+                .zip(ranges.iter())
+                .map(|(parameter, ranges)| {
+                    let mut parameter_1_0 = unsafe {
+                        d3d12::D3D12_ROOT_PARAMETER {
+                            ParameterType: parameter.0.ParameterType,
+                            ShaderVisibility: parameter.0.ShaderVisibility,
+                            ..mem::zeroed()
+                        }
+                    };
+                    unsafe {
+                        match parameter.0.ParameterType {
+                            d3d12::D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE => {
+                                *parameter_1_0.u.DescriptorTable_mut() =
+                                    d3d12::D3D12_ROOT_DESCRIPTOR_TABLE {
+                                        NumDescriptorRanges: ranges.len() as _,
+                                        pDescriptorRanges: ranges.as_ptr(),
+                                    }
+                            }
+                            d3d12::D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS => {
+                                *parameter_1_0.u.Constants_mut() = *parameter.0.u.Constants()
+                            }
+                            _ => {
+                                let descriptor = parameter.0.u.Descriptor();
+                                *parameter_1_0.u.Descriptor_mut() = d3d12::D3D12_ROOT_DESCRIPTOR {
+                                    ShaderRegister: descriptor.ShaderRegister,
+                                    RegisterSpace: descriptor.RegisterSpace,
+                                }
+                            }
+                        }
+                    }
+                    parameter_1_0
+                })
+                .collect();
 
-            if FAILED(device.native.CreateRootSignature(
-                0,
-                (*out_blob).GetBufferPointer(),
+            let desc = d3d12::D3D12_ROOT_SIGNATURE_DESC {
+                NumParameters: parameters.len() as _,
+                pParameters: parameters.as_ptr(),
+                NumStaticSamplers: self.static_samplers.len() as _,
+                pStaticSamplers: self.static_samplers.as_ptr() as *const _,
+                Flags: flags,
+            };
+            unsafe {
+                d3d12::D3D12SerializeRootSignature(
+                    &desc,
+                    d3d12::D3D_ROOT_SIGNATURE_VERSION_1,
+                    &mut out_blob as *mut *mut _ as *mut *mut _,
+                    &mut error_blob as *mut *mut _ as *mut *mut _,
+                )
+            }
+        };
+        if FAILED(hr) {
+            let message = if error_blob.is_null() {
+                format!("{:#x}", hr)
+            } else {
+                unsafe {
+                    let pointer = (*error_blob).GetBufferPointer();
+                    let size = (*error_blob).GetBufferSize();
+                    let slice = slice::from_raw_parts(pointer as *const u8, size as usize);
+                    let message = String::from_utf8_lossy(slice).into_owned();
+                    (*error_blob).Release();
+                    message
+                }
+            };
+            return Err(RootSignatureError::SerializeFailed(message));
+        }
+        if !error_blob.is_null() {
+            unsafe {
+                (*error_blob).Release();
+            }
+        }
+
+        let blob = unsafe {
+            let blob = slice::from_raw_parts(
+                (*out_blob).GetBufferPointer() as *const u8,
                 (*out_blob).GetBufferSize(),
+            )
+            .to_vec();
+            (*out_blob).Release();
+            blob
+        };
+
+        // Identical signatures share a single device object.
+        if let Some(root_signature) = device.root_signatures.borrow().get(&blob) {
+            return Ok(root_signature.clone());
+        }
+
+        let hr = unsafe {
+            device.native.CreateRootSignature(
+                0,
+                blob.as_ptr() as *const _,
+                blob.len(),
                 &d3d12::ID3D12RootSignature::uuidof(),
                 &mut signature as *mut *mut _ as *mut *mut _,
-            )) {
-                (*out_blob).Release();
-                (*error_blob).Release();
-                panic!("Failed to create root signature");
-            }
+            )
+        };
+        if FAILED(hr) {
+            return Err(RootSignatureError::CreateFailed);
         }
 
-        // TODO: Cache compiled root signatures
+        // The serialized blob identifies the signature contents, so e.g. PSO caches can key on it.
+        let mut hasher = StableHasher::default();
+        blob.hash(&mut hasher);
+        let root_signature = RootSignature {
+            native: unsafe { ComPtr::from_ptr(signature) },
+            hash: hasher.finish(),
+        };
+
+        device
+            .root_signatures
+            .borrow_mut()
+            .insert(blob, root_signature.clone());
 
-        RootSignature(unsafe { ComPtr::from_ptr(signature) })
+        Ok(root_signature)
     }
 }
 
+fn is_root_signature_1_1_supported(device: &Device) -> bool {
+    let mut data = d3d12::D3D12_FEATURE_DATA_ROOT_SIGNATURE {
+        HighestVersion: d3d12::D3D_ROOT_SIGNATURE_VERSION_1_1,
+    };
+    SUCCEEDED(unsafe {
+        device.native.CheckFeatureSupport(
+            d3d12::D3D12_FEATURE_ROOT_SIGNATURE,
+            &mut data as *mut _ as *mut _,
+            mem::size_of::<d3d12::D3D12_FEATURE_DATA_ROOT_SIGNATURE>() as _,
+        )
+    }) && data.HighestVersion >= d3d12::D3D_ROOT_SIGNATURE_VERSION_1_1
+}
+
 impl Default for RootSignatureBuilder {
     fn default() -> Self {
         RootSignatureBuilder {
             parameters: Vec::new(),
+            static_samplers: Vec::new(),
+            dword_count: 0,
+            overflow_parameter: None,
         }
     }
 }
 
-pub struct RootSignature(pub(crate) ComPtr<d3d12::ID3D12RootSignature>);
+// Cheap to clone, clones share the same device object.
+#[derive(Clone)]
+pub struct RootSignature {
+    pub(crate) native: ComPtr<d3d12::ID3D12RootSignature>,
+    pub(crate) hash: u64,
+}
+
+// Root signatures are immutable once created, so they can be bound from any recording thread.
+unsafe impl Send for RootSignature {}
+unsafe impl Sync for RootSignature {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VISIBILITY: d3d12::D3D12_SHADER_VISIBILITY = d3d12::D3D12_SHADER_VISIBILITY_ALL;
+
+    fn table() -> RootParameter {
+        RootParameter::new_descriptor_table(VISIBILITY, &[])
+    }
+
+    fn descriptor() -> RootParameter {
+        RootParameter::new_descriptor(RootDescriptorType::Cbv, 0, 0, VISIBILITY)
+    }
+
+    fn constants(count: u32) -> RootParameter {
+        RootParameter::new_constants(count, 0, 0, VISIBILITY)
+    }
+
+    #[test]
+    fn dword_cost_matches_parameter_type() {
+        assert_eq!(table().dword_cost(), 1);
+        assert_eq!(descriptor().dword_cost(), 2);
+        assert_eq!(
+            RootParameter::new_descriptor(RootDescriptorType::Uav, 0, 0, VISIBILITY).dword_cost(),
+            2
+        );
+        assert_eq!(constants(1).dword_cost(), 1);
+        assert_eq!(constants(16).dword_cost(), 16);
+    }
+
+    #[test]
+    fn exactly_64_dwords_fit() {
+        let builder = RootSignatureBuilder::default()
+            .add_parameter(constants(60))
+            .add_parameter(descriptor())
+            .add_parameter(table())
+            .add_parameter(table());
+        assert_eq!(builder.dword_count, 64);
+        assert_eq!(builder.overflow_parameter, None);
+    }
+
+    #[test]
+    fn first_parameter_over_64_dwords_is_reported() {
+        let builder = RootSignatureBuilder::default()
+            .add_parameter(constants(63))
+            .add_parameter(descriptor())
+            .add_parameter(table());
+        assert_eq!(builder.dword_count, 66);
+        assert_eq!(builder.overflow_parameter, Some(1));
+
+        let builder = RootSignatureBuilder::default()
+            .add_parameter(constants(64))
+            .add_parameter(table());
+        assert_eq!(builder.dword_count, 65);
+        assert_eq!(builder.overflow_parameter, Some(1));
+    }
+}
@@ -1,11 +1,17 @@
 use crate::com::ComPtr;
 use crate::device::Device;
 
-use winapi::shared::winerror::{self, SUCCEEDED};
+use winapi::shared::{
+    minwindef,
+    winerror::{self, SUCCEEDED},
+};
 use winapi::um::{d3d12, handleapi, synchapi, winbase, winnt};
 use winapi::Interface;
 
+use std::cell::Cell;
 use std::ptr;
+use std::rc::Rc;
+use std::time::Duration;
 
 #[repr(transparent)]
 #[derive(Debug, Copy, Clone)]
@@ -28,7 +34,8 @@ impl Event {
     }
 
     pub fn wait(self, timeout_ms: u32) -> bool {
-        let hr = unsafe { synchapi::WaitForSingleObject(self.handle, timeout_ms) };
+        let hr =
+            unsafe { synchapi::WaitForSingleObjectEx(self.handle, timeout_ms, minwindef::FALSE) };
         match hr {
             winbase::WAIT_OBJECT_0 => true,
             winbase::WAIT_ABANDONED => true,
@@ -49,8 +56,13 @@ pub enum Error {
     FenceSetCompletionEventFailed,
 }
 
+// Fences are free threaded, their value can be read and waited on from any thread.
+#[derive(Clone)]
 pub struct Fence(pub(crate) ComPtr<d3d12::ID3D12Fence>);
 
+unsafe impl Send for Fence {}
+unsafe impl Sync for Fence {}
+
 impl Fence {
     pub fn new(device: &Device) -> Result<Self, Error> {
         Fence::new_with_value(device, 0)
@@ -100,20 +112,72 @@ impl Fence {
     }
 
     pub fn wait(&self, event: Event, value: u64) -> Result<bool, Error> {
-        self.wait_with_timeout(event, value, u64::max_value())
+        self.wait_for_ms(event, value, winbase::INFINITE)
+    }
+
+    // Returns Ok(false) if the fence did not reach `value` before the timeout elapsed.
+    pub fn wait_timeout(&self, event: Event, value: u64, timeout: Duration) -> Result<bool, Error> {
+        // Clamp below INFINITE so that long timeouts still expire.
+        let timeout_ms = timeout.as_millis().min(u128::from(winbase::INFINITE - 1)) as u32;
+        self.wait_for_ms(event, value, timeout_ms)
     }
 
-    pub fn wait_with_timeout(
-        &self,
-        event: Event,
-        value: u64,
-        timeout_ns: u64,
-    ) -> Result<bool, Error> {
+    fn wait_for_ms(&self, event: Event, value: u64, timeout_ms: u32) -> Result<bool, Error> {
         if self.get_value() >= value {
             return Ok(true);
         }
 
         self.set_event_on_completion(event, value)
-            .map(|_| event.wait(timeout_ns as _))
+            .map(|_| event.wait(timeout_ms))
+    }
+}
+
+// Fence values of one queue handed out as monotonically increasing tickets, one per signal.
+// Clones share the fence and the last ticket, so each system can keep its own handle to check
+// when the work it submitted has completed.
+#[derive(Clone)]
+pub struct GpuTimeline {
+    pub(crate) fence: Fence,
+    last_ticket: Rc<Cell<u64>>,
+}
+
+impl GpuTimeline {
+    pub fn new(device: &Device) -> Result<Self, Error> {
+        Ok(GpuTimeline {
+            fence: Fence::new(device)?,
+            last_ticket: Rc::new(Cell::new(0)),
+        })
+    }
+
+    // Reserves the ticket of the next signal, which must be signaled right away.
+    pub(crate) fn next_ticket(&self) -> u64 {
+        let ticket = self.last_ticket.get() + 1;
+        self.last_ticket.set(ticket);
+        ticket
+    }
+
+    // Ticket of the last signal, 0 before the first one.
+    pub fn last_ticket(&self) -> u64 {
+        self.last_ticket.get()
+    }
+
+    // Every ticket up to this one has completed on the GPU.
+    pub fn completed_ticket(&self) -> u64 {
+        self.fence.get_value()
+    }
+
+    pub fn is_complete(&self, ticket: u64) -> bool {
+        ticket <= self.completed_ticket()
+    }
+
+    // Blocks the CPU until the GPU has reached `ticket`.
+    pub fn wait(&self, ticket: u64) -> Result<(), Error> {
+        if self.is_complete(ticket) {
+            return Ok(());
+        }
+        let event = Event::new();
+        let result = self.fence.wait(event, ticket).map(|_| ());
+        event.close();
+        result
     }
 }
@@ -0,0 +1,512 @@
+use crate::command::{CommandContext, CommandContextPool, CommandQueue};
+use crate::device::Device;
+#[cfg(feature = "image")]
+use crate::mips::MipGenerator;
+use crate::resource::{GpuResource, SubresourceData};
+
+use winapi::shared::dxgiformat;
+use winapi::um::d3d12;
+
+use std::cmp;
+use std::convert::TryInto;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHeader,
+    UnsupportedFormat,
+    // Only 2D textures, texture arrays and cube maps are loaded.
+    UnsupportedDimension,
+    DataTooSmall,
+    TextureCreateFailed,
+    #[cfg(feature = "image")]
+    ImageDecodeFailed,
+}
+
+const DDS_MAGIC: u32 = 0x2053_4444; // "DDS "
+const DDS_HEADER_SIZE: usize = 124;
+const DDS_HEADER_DXT10_SIZE: usize = 20;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x0002_0000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xfc00;
+const DDSCAPS2_VOLUME: u32 = 0x0020_0000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+
+const fn four_cc(code: &[u8; 4]) -> u32 {
+    (code[0] as u32) | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+
+// Description of a DDS file, as read from its headers.
+struct DdsInfo {
+    format: dxgiformat::DXGI_FORMAT,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    // Number of 2D slices, six per cube for cube maps.
+    array_size: u32,
+    // Offset of the pixel data from the start of the file.
+    data_offset: usize,
+}
+
+// Loads a 2D texture, texture array or cube map from the contents of a DDS file, and uploads all
+// its subresources, leaving it ready for pixel shader reads. Mips are loaded as stored, they are
+// not generated. Block compressed BC1 to BC7 formats are supported, along with the common 32 bits
+// RGBA and floating point formats.
+pub fn load_dds(
+    device: &Device,
+    command_queue: &mut CommandQueue,
+    pool: &mut CommandContextPool,
+    bytes: &[u8],
+) -> Result<GpuResource, Error> {
+    let info = parse_dds(bytes)?;
+
+    // Data is stored per slice, every mip of a slice before the next slice, which matches the
+    // D3D12 subresource order.
+    let mut subresources = Vec::with_capacity(info.array_size as usize * info.mip_levels as usize);
+    let mut offset = info.data_offset;
+    for _ in 0..info.array_size {
+        for mip in 0..info.mip_levels {
+            let width = cmp::max(1, info.width >> mip);
+            let height = cmp::max(1, info.height >> mip);
+            let (row_pitch, row_count) = surface_pitch(info.format, width, height);
+            let slice_pitch = row_pitch * row_count;
+            let end = match offset.checked_add(slice_pitch) {
+                Some(end) if end <= bytes.len() => end,
+                _ => return Err(Error::DataTooSmall),
+            };
+            subresources.push(SubresourceData {
+                data: &bytes[offset..end],
+                row_pitch,
+                slice_pitch,
+            });
+            offset = end;
+        }
+    }
+
+    let mut texture = GpuResource::create_texture_2d_array(
+        device,
+        info.format,
+        info.width,
+        info.height,
+        info.array_size as _,
+        info.mip_levels as _,
+        d3d12::D3D12_RESOURCE_FLAG_NONE,
+        d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+    )
+    .map_err(|_| Error::TextureCreateFailed)?;
+    CommandContext::init_texture(command_queue, pool, &mut texture, &subresources);
+
+    Ok(texture)
+}
+
+// Decodes a PNG or JPEG image into an R8G8B8A8_UNORM texture and uploads it, leaving it ready for
+// pixel shader reads. With a mip generator the full mip chain is created and generated from the
+// image, otherwise the texture has a single mip.
+#[cfg(feature = "image")]
+pub fn load_image(
+    device: &Device,
+    command_queue: &mut CommandQueue,
+    pool: &mut CommandContextPool,
+    bytes: &[u8],
+    mip_generator: Option<&MipGenerator>,
+) -> Result<GpuResource, Error> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|_| Error::ImageDecodeFailed)?
+        .to_rgba();
+    let (width, height) = image.dimensions();
+    let pixels = image.into_raw();
+
+    let (mip_levels, flags) = match mip_generator {
+        Some(_) => (
+            32 - cmp::max(width, height).leading_zeros(),
+            d3d12::D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+        ),
+        None => (1, d3d12::D3D12_RESOURCE_FLAG_NONE),
+    };
+    let mut texture = GpuResource::create_texture_2d(
+        device,
+        dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM,
+        width,
+        height,
+        mip_levels as _,
+        flags,
+        d3d12::D3D12_RESOURCE_STATE_COPY_DEST,
+    )
+    .map_err(|_| Error::TextureCreateFailed)?;
+
+    // Only the top mip is uploaded, the others are generated from it.
+    let row_pitch = width as usize * 4;
+    CommandContext::init_texture(
+        command_queue,
+        pool,
+        &mut texture,
+        &[SubresourceData {
+            data: &pixels,
+            row_pitch,
+            slice_pitch: row_pitch * height as usize,
+        }],
+    );
+
+    if let Some(mip_generator) = mip_generator {
+        let context = pool.request(command_queue);
+        context.generate_mips(device, mip_generator, &mut texture);
+        context.end(command_queue, pool, true);
+    }
+
+    Ok(texture)
+}
+
+fn parse_dds(bytes: &[u8]) -> Result<DdsInfo, Error> {
+    if bytes.len() < 4 + DDS_HEADER_SIZE || read_u32(bytes, 0) != DDS_MAGIC {
+        return Err(Error::InvalidHeader);
+    }
+    // Offsets below are relative to the file start, the header follows the magic number.
+    if read_u32(bytes, 4) as usize != DDS_HEADER_SIZE {
+        return Err(Error::InvalidHeader);
+    }
+    let flags = read_u32(bytes, 8);
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let mip_map_count = read_u32(bytes, 28);
+    let pixel_format_flags = read_u32(bytes, 80);
+    let pixel_format_four_cc = read_u32(bytes, 84);
+    let caps2 = read_u32(bytes, 112);
+
+    let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+        cmp::max(1, mip_map_count)
+    } else {
+        1
+    };
+
+    let mut data_offset = 4 + DDS_HEADER_SIZE;
+    let (format, array_size) =
+        if pixel_format_flags & DDPF_FOURCC != 0 && pixel_format_four_cc == four_cc(b"DX10") {
+            if bytes.len() < data_offset + DDS_HEADER_DXT10_SIZE {
+                return Err(Error::InvalidHeader);
+            }
+            let format = read_u32(bytes, data_offset);
+            let dimension = read_u32(bytes, data_offset + 4);
+            let misc_flag = read_u32(bytes, data_offset + 8);
+            let array_size = cmp::max(1, read_u32(bytes, data_offset + 12));
+            data_offset += DDS_HEADER_DXT10_SIZE;
+
+            if dimension != D3D10_RESOURCE_DIMENSION_TEXTURE2D {
+                return Err(Error::UnsupportedDimension);
+            }
+            let array_size = if misc_flag & DDS_RESOURCE_MISC_TEXTURECUBE != 0 {
+                array_size.checked_mul(6).ok_or(Error::InvalidHeader)?
+            } else {
+                array_size
+            };
+            (format, array_size)
+        } else {
+            if caps2 & DDSCAPS2_VOLUME != 0 {
+                return Err(Error::UnsupportedDimension);
+            }
+            let array_size = if caps2 & DDSCAPS2_CUBEMAP != 0 {
+                // Legacy cube maps may omit faces, which D3D12 cube maps cannot.
+                if caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+                    return Err(Error::UnsupportedDimension);
+                }
+                6
+            } else {
+                1
+            };
+            (legacy_format(bytes)?, array_size)
+        };
+
+    if block_size(format).is_none() && bits_per_pixel(format).is_none() {
+        return Err(Error::UnsupportedFormat);
+    }
+    if width == 0 || height == 0 || array_size > u32::from(u16::max_value()) {
+        return Err(Error::InvalidHeader);
+    }
+    // A full mip chain goes down to 1x1, more mips than that would shift the size out of range.
+    let max_mip_levels = 32 - cmp::max(width, height).leading_zeros();
+    if mip_levels > max_mip_levels {
+        return Err(Error::InvalidHeader);
+    }
+    // Both are bounded above, but the subresource count is still checked for overflow.
+    array_size
+        .checked_mul(mip_levels)
+        .ok_or(Error::InvalidHeader)?;
+
+    Ok(DdsInfo {
+        format,
+        width,
+        height,
+        mip_levels,
+        array_size,
+        data_offset,
+    })
+}
+
+// Maps the pixel format of files without the DX10 header to a DXGI format.
+fn legacy_format(bytes: &[u8]) -> Result<dxgiformat::DXGI_FORMAT, Error> {
+    let flags = read_u32(bytes, 80);
+    if flags & DDPF_FOURCC != 0 {
+        let four_cc_code = read_u32(bytes, 84);
+        return match four_cc_code {
+            c if c == four_cc(b"DXT1") => Ok(dxgiformat::DXGI_FORMAT_BC1_UNORM),
+            c if c == four_cc(b"DXT2") || c == four_cc(b"DXT3") => {
+                Ok(dxgiformat::DXGI_FORMAT_BC2_UNORM)
+            }
+            c if c == four_cc(b"DXT4") || c == four_cc(b"DXT5") => {
+                Ok(dxgiformat::DXGI_FORMAT_BC3_UNORM)
+            }
+            c if c == four_cc(b"ATI1") || c == four_cc(b"BC4U") => {
+                Ok(dxgiformat::DXGI_FORMAT_BC4_UNORM)
+            }
+            c if c == four_cc(b"BC4S") => Ok(dxgiformat::DXGI_FORMAT_BC4_SNORM),
+            c if c == four_cc(b"ATI2") || c == four_cc(b"BC5U") => {
+                Ok(dxgiformat::DXGI_FORMAT_BC5_UNORM)
+            }
+            c if c == four_cc(b"BC5S") => Ok(dxgiformat::DXGI_FORMAT_BC5_SNORM),
+            // D3DFMT_A16B16G16R16F and D3DFMT_A32B32G32R32F
+            113 => Ok(dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT),
+            116 => Ok(dxgiformat::DXGI_FORMAT_R32G32B32A32_FLOAT),
+            _ => Err(Error::UnsupportedFormat),
+        };
+    }
+
+    // Uncompressed formats are identified by their channel masks.
+    let bit_count = read_u32(bytes, 88);
+    let masks = (
+        read_u32(bytes, 92),
+        read_u32(bytes, 96),
+        read_u32(bytes, 100),
+        read_u32(bytes, 104),
+    );
+    match (bit_count, masks) {
+        (32, (0x0000_00ff, 0x0000_ff00, 0x00ff_0000, 0xff00_0000)) => {
+            Ok(dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM)
+        }
+        (32, (0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000)) => {
+            Ok(dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM)
+        }
+        (32, (0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0)) => {
+            Ok(dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM)
+        }
+        _ => Err(Error::UnsupportedFormat),
+    }
+}
+
+// Size in bytes of a 4x4 block of block compressed formats.
+fn block_size(format: dxgiformat::DXGI_FORMAT) -> Option<usize> {
+    match format {
+        dxgiformat::DXGI_FORMAT_BC1_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC1_UNORM
+        | dxgiformat::DXGI_FORMAT_BC1_UNORM_SRGB
+        | dxgiformat::DXGI_FORMAT_BC4_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC4_UNORM
+        | dxgiformat::DXGI_FORMAT_BC4_SNORM => Some(8),
+        dxgiformat::DXGI_FORMAT_BC2_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC2_UNORM
+        | dxgiformat::DXGI_FORMAT_BC2_UNORM_SRGB
+        | dxgiformat::DXGI_FORMAT_BC3_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC3_UNORM
+        | dxgiformat::DXGI_FORMAT_BC3_UNORM_SRGB
+        | dxgiformat::DXGI_FORMAT_BC5_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC5_UNORM
+        | dxgiformat::DXGI_FORMAT_BC5_SNORM
+        | dxgiformat::DXGI_FORMAT_BC6H_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC6H_UF16
+        | dxgiformat::DXGI_FORMAT_BC6H_SF16
+        | dxgiformat::DXGI_FORMAT_BC7_TYPELESS
+        | dxgiformat::DXGI_FORMAT_BC7_UNORM
+        | dxgiformat::DXGI_FORMAT_BC7_UNORM_SRGB => Some(16),
+        _ => None,
+    }
+}
+
+fn bits_per_pixel(format: dxgiformat::DXGI_FORMAT) -> Option<usize> {
+    match format {
+        dxgiformat::DXGI_FORMAT_R32G32B32A32_FLOAT => Some(128),
+        dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT => Some(64),
+        dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM
+        | dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM
+        | dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        | dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM
+        | dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => Some(32),
+        _ => None,
+    }
+}
+
+// Row pitch in bytes and number of rows of a surface. Rows of block compressed formats are rows of
+// blocks, which is also what the copy footprints count.
+fn surface_pitch(format: dxgiformat::DXGI_FORMAT, width: u32, height: u32) -> (usize, usize) {
+    let (width, height) = (width as usize, height as usize);
+    match block_size(format) {
+        Some(block_size) => (
+            cmp::max(1, (width + 3) / 4) * block_size,
+            cmp::max(1, (height + 3) / 4),
+        ),
+        None => ((width * bits_per_pixel(format).unwrap() + 7) / 8, height),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // Legacy header with a FourCC pixel format, without pixel data.
+    fn legacy_header(width: u32, height: u32, mip_map_count: u32, four_cc_code: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 4 + DDS_HEADER_SIZE];
+        write_u32(&mut bytes, 0, DDS_MAGIC);
+        write_u32(&mut bytes, 4, DDS_HEADER_SIZE as _);
+        write_u32(&mut bytes, 8, DDSD_MIPMAPCOUNT);
+        write_u32(&mut bytes, 12, height);
+        write_u32(&mut bytes, 16, width);
+        write_u32(&mut bytes, 28, mip_map_count);
+        write_u32(&mut bytes, 80, DDPF_FOURCC);
+        write_u32(&mut bytes, 84, four_cc_code);
+        bytes
+    }
+
+    // Header followed by the DX10 extension, for a BC7 texture array or cube array.
+    fn dx10_header(width: u32, height: u32, array_size: u32, misc_flag: u32) -> Vec<u8> {
+        let mut bytes = legacy_header(width, height, 1, four_cc(b"DX10"));
+        bytes.extend_from_slice(&[0; DDS_HEADER_DXT10_SIZE]);
+        write_u32(&mut bytes, 128, dxgiformat::DXGI_FORMAT_BC7_UNORM);
+        write_u32(&mut bytes, 132, D3D10_RESOURCE_DIMENSION_TEXTURE2D);
+        write_u32(&mut bytes, 136, misc_flag);
+        write_u32(&mut bytes, 140, array_size);
+        bytes
+    }
+
+    #[test]
+    fn parses_legacy_and_dx10_headers() {
+        let info = parse_dds(&legacy_header(256, 128, 9, four_cc(b"DXT1"))).unwrap();
+        assert_eq!(info.format, dxgiformat::DXGI_FORMAT_BC1_UNORM);
+        assert_eq!((info.width, info.height), (256, 128));
+        assert_eq!((info.mip_levels, info.array_size), (9, 1));
+        assert_eq!(info.data_offset, 4 + DDS_HEADER_SIZE);
+
+        let info = parse_dds(&dx10_header(64, 64, 2, DDS_RESOURCE_MISC_TEXTURECUBE)).unwrap();
+        assert_eq!(info.format, dxgiformat::DXGI_FORMAT_BC7_UNORM);
+        assert_eq!((info.mip_levels, info.array_size), (1, 12));
+        assert_eq!(
+            info.data_offset,
+            4 + DDS_HEADER_SIZE + DDS_HEADER_DXT10_SIZE
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let bytes = legacy_header(256, 256, 1, four_cc(b"DXT1"));
+        match parse_dds(&bytes[..bytes.len() - 1]) {
+            Err(Error::InvalidHeader) => {}
+            other => panic!("Unexpected result {:?}", other.map(|info| info.format)),
+        }
+
+        let bytes = dx10_header(256, 256, 1, 0);
+        match parse_dds(&bytes[..bytes.len() - 1]) {
+            Err(Error::InvalidHeader) => {}
+            other => panic!("Unexpected result {:?}", other.map(|info| info.format)),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_mip_counts() {
+        // 256x128 has 9 mips, down to 1x1.
+        assert!(parse_dds(&legacy_header(256, 128, 9, four_cc(b"DXT1"))).is_ok());
+        for &(width, height, mip_map_count) in &[(256, 128, 10), (1, 1, 2), (1, 1, 33)] {
+            let bytes = legacy_header(width, height, mip_map_count, four_cc(b"DXT1"));
+            match parse_dds(&bytes) {
+                Err(Error::InvalidHeader) => {}
+                other => panic!("Unexpected result {:?}", other.map(|info| info.mip_levels)),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_cube_array_overflows() {
+        // Overflows u32 once multiplied by the six faces.
+        let bytes = dx10_header(
+            64,
+            64,
+            u32::max_value() / 6 + 1,
+            DDS_RESOURCE_MISC_TEXTURECUBE,
+        );
+        match parse_dds(&bytes) {
+            Err(Error::InvalidHeader) => {}
+            other => panic!("Unexpected result {:?}", other.map(|info| info.array_size)),
+        }
+
+        // Fits u32, but not the u16 array size of D3D12 resources.
+        let bytes = dx10_header(64, 64, 10923, DDS_RESOURCE_MISC_TEXTURECUBE);
+        match parse_dds(&bytes) {
+            Err(Error::InvalidHeader) => {}
+            other => panic!("Unexpected result {:?}", other.map(|info| info.array_size)),
+        }
+    }
+
+    #[test]
+    fn surface_pitch_counts_blocks_for_compressed_formats() {
+        assert_eq!(
+            surface_pitch(dxgiformat::DXGI_FORMAT_BC1_UNORM, 10, 10),
+            (24, 3)
+        );
+        assert_eq!(
+            surface_pitch(dxgiformat::DXGI_FORMAT_BC7_UNORM, 1, 1),
+            (16, 1)
+        );
+        assert_eq!(
+            surface_pitch(dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM, 5, 3),
+            (20, 3)
+        );
+        assert_eq!(
+            surface_pitch(dxgiformat::DXGI_FORMAT_R32G32B32A32_FLOAT, 3, 2),
+            (48, 2)
+        );
+    }
+
+    #[test]
+    fn legacy_format_maps_four_cc_and_channel_masks() {
+        let bytes = legacy_header(4, 4, 1, four_cc(b"DXT5"));
+        assert_eq!(
+            legacy_format(&bytes).unwrap(),
+            dxgiformat::DXGI_FORMAT_BC3_UNORM
+        );
+        let bytes = legacy_header(4, 4, 1, 113);
+        assert_eq!(
+            legacy_format(&bytes).unwrap(),
+            dxgiformat::DXGI_FORMAT_R16G16B16A16_FLOAT
+        );
+        let bytes = legacy_header(4, 4, 1, four_cc(b"ETC2"));
+        match legacy_format(&bytes) {
+            Err(Error::UnsupportedFormat) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // Uncompressed formats have no FourCC.
+        let mut bytes = legacy_header(4, 4, 1, 0);
+        write_u32(&mut bytes, 80, 0);
+        write_u32(&mut bytes, 88, 32);
+        write_u32(&mut bytes, 92, 0x00ff_0000);
+        write_u32(&mut bytes, 96, 0x0000_ff00);
+        write_u32(&mut bytes, 100, 0x0000_00ff);
+        write_u32(&mut bytes, 104, 0xff00_0000);
+        assert_eq!(
+            legacy_format(&bytes).unwrap(),
+            dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM
+        );
+        write_u32(&mut bytes, 104, 0);
+        assert_eq!(
+            legacy_format(&bytes).unwrap(),
+            dxgiformat::DXGI_FORMAT_B8G8R8X8_UNORM
+        );
+    }
+}
@@ -1,5 +1,9 @@
+use std::thread;
 use std::time::{Duration, Instant};
 
+// Sleeping is only accurate to the scheduler period, the end of a frame is busy waited for.
+const SPIN_WAIT_TIME: Duration = Duration::from_millis(2);
+
 pub struct GameTimer {
     base_time: Instant,
     current_time: Instant,
@@ -8,6 +12,18 @@ pub struct GameTimer {
     delta_time: Duration,
     paused_time: Duration,
     is_stopped: bool,
+    is_fixed_time_step: bool,
+    target_time_per_update_us: u64,
+    // Elapsed time not yet consumed by fixed updates.
+    leftover_time: Duration,
+    // Longest frame time fixed updates catch up on, e.g. after a debugger break.
+    max_delta_time: Duration,
+    // Minimum duration of a frame when the frame rate is capped.
+    target_frame_time: Option<Duration>,
+    // Frames counted since the frame rate was last measured, once per second.
+    fps_frame_count: u32,
+    fps_elapsed_time: Duration,
+    frames_per_second: u32,
     pub total_frames: u64,
 }
 
@@ -29,10 +45,18 @@ impl GameTimer {
         total_time.as_secs_f64()
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.is_stopped
+    }
+
     pub fn reset(&mut self) {
-        self.base_time = Instant::now();
-        self.previous_time = Instant::now();
-        self.stop_time = Instant::now();
+        let now = Instant::now();
+        self.base_time = now;
+        self.current_time = now;
+        self.previous_time = now;
+        self.stop_time = now;
+        self.delta_time = Duration::from_secs_f64(0.0);
+        self.paused_time = Duration::from_secs_f64(0.0);
         self.is_stopped = false;
     }
 
@@ -40,6 +64,8 @@ impl GameTimer {
         if self.is_stopped {
             let now = Instant::now();
             self.paused_time += now - self.stop_time;
+            // The time spent paused must not show up in the next frame's delta.
+            self.previous_time = now;
             self.stop_time = now;
             self.is_stopped = false;
         }
@@ -53,6 +79,111 @@ impl GameTimer {
         }
     }
 
+    pub fn is_fixed_timestep(&self) -> bool {
+        self.is_fixed_time_step
+    }
+
+    pub fn set_fixed_timestep(&mut self, enabled: bool) {
+        self.is_fixed_time_step = enabled;
+        self.leftover_time = Duration::from_secs_f64(0.0);
+    }
+
+    // Frames per second measured over the last full second.
+    pub fn frames_per_second(&self) -> u32 {
+        self.frames_per_second
+    }
+
+    // Caps the frame rate when wait_for_next_frame() is called at the end of each frame, None
+    // runs uncapped.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.target_frame_time = fps.map(|fps| {
+            assert!(fps > 0);
+            Duration::from_micros(1_000_000 / u64::from(fps))
+        });
+    }
+
+    // Sleeps for the remainder of the frame started by the last update, saving CPU and GPU time
+    // when the target frame rate is met easily. Does nothing when uncapped.
+    pub fn wait_for_next_frame(&self) {
+        let target_frame_time = match self.target_frame_time {
+            Some(target_frame_time) => target_frame_time,
+            None => return,
+        };
+        let frame_end = self.current_time + target_frame_time;
+        let now = Instant::now();
+        if frame_end <= now {
+            return;
+        }
+
+        let remaining = frame_end - now;
+        if remaining > SPIN_WAIT_TIME {
+            thread::sleep(remaining - SPIN_WAIT_TIME);
+        }
+        while Instant::now() < frame_end {
+            thread::yield_now();
+        }
+    }
+
+    // Caps the time a single frame adds to the fixed update backlog (100ms by default), so a long
+    // stall is followed by a few catch-up updates instead of an unbounded number of them.
+    pub fn set_max_delta_time(&mut self, max_delta_time: Duration) {
+        self.max_delta_time = max_delta_time;
+    }
+
+    pub fn set_target_updates_per_second(&mut self, updates_per_second: u32) {
+        assert!(updates_per_second > 0);
+        self.target_time_per_update_us = 1_000_000 / u64::from(updates_per_second);
+    }
+
+    // Advances the timer and calls `on_update` once in variable mode, or once per elapsed target
+    // interval in fixed mode, with delta_time set to exactly that interval.
+    pub fn update<F>(&mut self, mut on_update: F)
+    where
+        F: FnMut(&GameTimer),
+    {
+        if !self.is_fixed_time_step {
+            self.tick();
+            if !self.is_stopped {
+                on_update(self);
+            }
+            return;
+        }
+
+        if self.is_stopped {
+            self.delta_time = Duration::from_secs_f64(0.0);
+            return;
+        }
+
+        self.current_time = Instant::now();
+        let elapsed = self.current_time - self.previous_time;
+        self.previous_time = self.current_time;
+        self.count_frame(elapsed);
+
+        let target_time = Duration::from_micros(self.target_time_per_update_us);
+        // At least one update's worth is let through, whatever the target rate.
+        let elapsed = elapsed.min(self.max_delta_time.max(target_time));
+        // Snap to the target when close enough, so small clock drift doesn't skip or add updates.
+        let elapsed = if elapsed > target_time {
+            if elapsed - target_time < Duration::from_micros(250) {
+                target_time
+            } else {
+                elapsed
+            }
+        } else if target_time - elapsed < Duration::from_micros(250) {
+            target_time
+        } else {
+            elapsed
+        };
+
+        self.leftover_time += elapsed;
+        self.delta_time = target_time;
+        while self.leftover_time >= target_time {
+            self.leftover_time -= target_time;
+            self.total_frames += 1;
+            on_update(self);
+        }
+    }
+
     pub fn tick(&mut self) {
         if self.is_stopped {
             self.delta_time = Duration::from_secs_f64(0.0);
@@ -64,6 +195,17 @@ impl GameTimer {
         self.delta_time = self.current_time - self.previous_time;
         self.previous_time = self.current_time;
         self.total_frames += 1;
+        self.count_frame(self.delta_time);
+    }
+
+    fn count_frame(&mut self, elapsed: Duration) {
+        self.fps_frame_count += 1;
+        self.fps_elapsed_time += elapsed;
+        if self.fps_elapsed_time >= Duration::from_secs(1) {
+            self.frames_per_second = self.fps_frame_count;
+            self.fps_frame_count = 0;
+            self.fps_elapsed_time -= Duration::from_secs(1);
+        }
     }
 }
 
@@ -79,6 +221,15 @@ impl Default for GameTimer {
             delta_time: zero_duration,
             paused_time: zero_duration,
             is_stopped: false,
+            is_fixed_time_step: false,
+            // 60 updates per second.
+            target_time_per_update_us: 16_667,
+            leftover_time: zero_duration,
+            max_delta_time: Duration::from_millis(100),
+            target_frame_time: None,
+            fps_frame_count: 0,
+            fps_elapsed_time: zero_duration,
+            frames_per_second: 0,
             total_frames: 0,
         }
     }